@@ -21,12 +21,29 @@ pub enum CreateCommands {
         /// Whether to delegate the governance voting power too.
         governance: bool,
         proof: String,
+        /// If specified, the delegation is automatically released from this block height.
+        #[clap(long)]
+        unlock_height: Option<u64>,
+        /// If specified, the delegation is automatically released from this timestamp.
+        #[clap(long)]
+        unlock_timestamp: Option<i64>,
+        /// If set, the consensus voting power is released while the delegatee is inactive.
+        #[clap(long)]
+        unlock_if_delegatee_inactive: bool,
+        /// If set, the delegation is automatically released once the validator set changes.
+        #[clap(long)]
+        unlock_if_validator_set_changes: bool,
     },
     /// An extra-agenda transaction that undelegates the consensus voting power and
     /// the governance voting power (if delegated).
     TxUndelegate { delegator: String, proof: String },
     /// An extra-agenda transaction that reports a misbehaving validator.
-    TxReport, // TODO
+    TxReport {
+        /// The first of the two conflicting signed consensus votes or proposals, in JSON.
+        first: String,
+        /// The second of the two conflicting signed consensus votes or proposals, in JSON.
+        second: String,
+    },
     /// A block waiting for finalization.
     Block,
     /// An agenda waiting for governance approval.
@@ -40,6 +57,18 @@ pub enum SignCommands {
         /// Whether to delegate the governance voting power too.
         governance: bool,
         target_height: u64,
+        /// If specified, the delegation is automatically released from this block height.
+        #[clap(long)]
+        unlock_height: Option<u64>,
+        /// If specified, the delegation is automatically released from this timestamp.
+        #[clap(long)]
+        unlock_timestamp: Option<i64>,
+        /// If set, the consensus voting power is released while the delegatee is inactive.
+        #[clap(long)]
+        unlock_if_delegatee_inactive: bool,
+        /// If set, the delegation is automatically released once the validator set changes.
+        #[clap(long)]
+        unlock_if_validator_set_changes: bool,
     },
     TxUndelegate {
         target_height: u64,
@@ -49,6 +78,43 @@ pub enum SignCommands {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum KeystoreCommands {
+    /// Generate a new mnemonic phrase and store the derived private key encrypted.
+    ///
+    /// The phrase is printed once; write it down to recover the keys later.
+    Create {
+        /// The signature scheme of the key (`secp256k1`, `ed25519` or `bls12381`).
+        #[clap(long, default_value = "secp256k1")]
+        scheme: String,
+        /// What the key is used for (`consensus`, `governance` or `network`).
+        #[clap(long, default_value = "consensus")]
+        purpose: String,
+        #[clap(long, default_value_t = 0)]
+        account: u32,
+        /// The number of words of the mnemonic phrase.
+        #[clap(long, default_value_t = 24)]
+        words: usize,
+    },
+    /// Derive the private key from an existing mnemonic phrase (prompted) and store it encrypted.
+    Recover {
+        /// The signature scheme of the key (`secp256k1`, `ed25519` or `bls12381`).
+        #[clap(long, default_value = "secp256k1")]
+        scheme: String,
+        /// What the key is used for (`consensus`, `governance` or `network`).
+        #[clap(long, default_value = "consensus")]
+        purpose: String,
+        #[clap(long, default_value_t = 0)]
+        account: u32,
+    },
+    /// Encrypt an existing private key (prompted in hex) and store it.
+    Import,
+    /// Print the decrypted private key in hex.
+    Export,
+    /// Re-encrypt the stored private key with a new password.
+    ChangePassword,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Initialize a new Simperby node in the current directory.
@@ -132,6 +198,14 @@ pub enum Commands {
     /// Sign a message with the configured private key.
     #[command(subcommand)]
     Sign(SignCommands),
+    /// Manage an encrypted keystore of the node.
+    Keystore {
+        /// The keystore file in the node directory.
+        #[clap(long, default_value = "keystore.json")]
+        file: String,
+        #[command(subcommand)]
+        command: KeystoreCommands,
+    },
     /// THIS IS TEMPORARY.
     GenesisProposer,
     /// THIS IS TEMPORARY
@@ -41,16 +41,7 @@ async fn setup_peer(path: &str, peers: &[Peer]) {
     file.flush().await.unwrap();
 }
 
-pub async fn run_genesis_proposer(private_key: &str) {
-    let private_key = PrivateKey::from_array(
-        hex::decode(private_key)
-            .unwrap()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-    )
-    .unwrap();
-
+pub async fn run_genesis_proposer(private_key: PrivateKey) {
     let dir = create_temp_dir();
     println!("----------------------------DIRECTORY: {}", dir);
     setup_peer(&dir, &[]).await;
@@ -76,7 +67,8 @@ pub async fn run_genesis_proposer(private_key: &str) {
         Config {
             chain_name: "PDAO-mainnet".to_owned(),
             public_key: private_key.public_key(),
-            private_key,
+            private_key: Some(private_key),
+            keystore: None,
             broadcast_interval_ms: None,
             fetch_interval_ms: None,
             public_repo_url: vec![],
@@ -141,16 +133,7 @@ pub async fn run_genesis_proposer(private_key: &str) {
     .await;
 }
 
-pub async fn run_genesis_non_proposer(private_key: &str) {
-    let private_key = PrivateKey::from_array(
-        hex::decode(private_key)
-            .unwrap()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-    )
-    .unwrap();
-
+pub async fn run_genesis_non_proposer(private_key: PrivateKey) {
     let dir = create_temp_dir();
     println!("----------------------------DIRECTORY: {}", dir);
     setup_peer(&dir, &[]).await;
@@ -197,7 +180,8 @@ pub async fn run_genesis_non_proposer(private_key: &str) {
         Config {
             chain_name: "PDAO-mainnet".to_owned(),
             public_key: private_key.public_key(),
-            private_key,
+            private_key: Some(private_key),
+            keystore: None,
             broadcast_interval_ms: None,
             fetch_interval_ms: None,
             public_repo_url: vec![],
@@ -5,7 +5,17 @@ use clap::Parser;
 use cli::*;
 use eyre::{eyre, Result};
 use simperby_node::{
-    simperby_common::*, simperby_repository::CommitHash, CommitInfo, Config, SimperbyApi,
+    simperby_common::{
+        keystore::Keystore,
+        mnemonic::{KeyPurpose, Mnemonic},
+        *,
+    },
+    simperby_repository::{
+        format,
+        raw::{RawRepository, RawRepositoryImpl},
+        CommitHash, FINALIZED_BRANCH_NAME,
+    },
+    CommitInfo, Config, SimperbyApi, SimperbyNode,
 };
 
 fn to_commit_hash(s: &str) -> Result<CommitHash> {
@@ -17,24 +27,24 @@ fn to_commit_hash(s: &str) -> Result<CommitHash> {
     Ok(CommitHash { hash })
 }
 
+fn to_public_key(s: &str) -> Result<PublicKey> {
+    serde_spb::from_str(&serde_spb::to_string(&s)?).map_err(|_| eyre!("invalid public key"))
+}
+
+fn to_signature(s: &str) -> Result<Signature> {
+    serde_spb::from_str(&serde_spb::to_string(&s)?).map_err(|_| eyre!("invalid signature"))
+}
+
 #[tokio::main(flavor = "multi_thread")]
-#[allow(unreachable_code)]
 async fn main() -> eyre::Result<()> {
     color_eyre::install().unwrap();
     env_logger::init();
 
-    let private_key = std::env::args().nth(1).unwrap();
-    let sorc = std::env::args().nth(2).unwrap();
-    if sorc == "s" {
-        genesis::run_genesis_proposer(&private_key).await;
-    } else {
-        genesis::run_genesis_non_proposer(&private_key).await;
-    }
-
-    return Ok(());
-
     let args = cli::Cli::parse();
     let path = args.path.display().to_string();
+    if let Commands::Keystore { file, command } = args.command {
+        return manage_keystore(command, &format!("{}/{}", path, file)).await;
+    }
     let config: Config =
         serde_spb::from_str(&tokio::fs::read_to_string(&format!("{}/config.json", path)).await?)?;
 
@@ -45,6 +55,62 @@ async fn main() -> eyre::Result<()> {
         Commands::Clean { .. } => todo!(),
         Commands::Create(CreateCommands::Agenda) => todo!(),
         Commands::Create(CreateCommands::Block) => todo!(),
+        Commands::Create(CreateCommands::TxDelegate {
+            delegator,
+            delegatee,
+            governance,
+            proof,
+            unlock_height,
+            unlock_timestamp,
+            unlock_if_delegatee_inactive,
+            unlock_if_validator_set_changes,
+        }) => {
+            let delegator = to_public_key(&delegator)?;
+            let tx = TxDelegate {
+                delegatee: to_public_key(&delegatee)?,
+                governance,
+                conditions: delegation_conditions(
+                    &path,
+                    unlock_height,
+                    unlock_timestamp,
+                    unlock_if_delegatee_inactive,
+                    unlock_if_validator_set_changes,
+                )
+                .await?,
+                block_height: last_finalized_header(&path).await?.height + 1,
+                proof: TypedSignature::new(to_signature(&proof)?, delegator.clone()),
+                delegator,
+                timestamp: get_timestamp(),
+            };
+            initialize_node(config, &path)
+                .await?
+                .create_extra_agenda_transaction(ExtraAgendaTransaction::Delegate(tx))
+                .await?;
+        }
+        Commands::Create(CreateCommands::TxReport { first, second }) => {
+            let misbehavior = if let (Ok(first), Ok(second)) = (
+                serde_spb::from_str::<SignedConsensusVote>(&first),
+                serde_spb::from_str::<SignedConsensusVote>(&second),
+            ) {
+                Misbehavior::DoubleVote(first, second)
+            } else {
+                Misbehavior::DoubleProposal(
+                    serde_spb::from_str(&first)
+                        .map_err(|e| eyre!("invalid first vote or proposal: {}", e))?,
+                    serde_spb::from_str(&second)
+                        .map_err(|e| eyre!("invalid second vote or proposal: {}", e))?,
+                )
+            };
+            let tx = TxReport {
+                headers: report_headers(&path, misbehavior.height()).await?,
+                misbehavior,
+                timestamp: get_timestamp(),
+            };
+            initialize_node(config, &path)
+                .await?
+                .create_extra_agenda_transaction(ExtraAgendaTransaction::Report(tx))
+                .await?;
+        }
         Commands::Show { commit } => show(config, &path, commit).await?,
         Commands::Consensus { show: _ } => todo!(),
         Commands::Serve => todo!(),
@@ -58,18 +124,271 @@ async fn main() -> eyre::Result<()> {
             );
             println!(
                 "{}",
-                Signature::sign(hash, &config.private_key).map_err(|_| eyre!("failed to sign"))?
+                Signature::sign(hash, &unlock_private_key(&config, &path).await?)
+                    .map_err(|_| eyre!("failed to sign"))?
             );
         }
+        Commands::Sign(SignCommands::TxDelegate {
+            delegatee,
+            governance,
+            target_height,
+            unlock_height,
+            unlock_timestamp,
+            unlock_if_delegatee_inactive,
+            unlock_if_validator_set_changes,
+        }) => {
+            let private_key = unlock_private_key(&config, &path).await?;
+            let conditions = delegation_conditions(
+                &path,
+                unlock_height,
+                unlock_timestamp,
+                unlock_if_delegatee_inactive,
+                unlock_if_validator_set_changes,
+            )
+            .await?;
+            let proof = TypedSignature::sign_with(
+                &(
+                    private_key.public_key(),
+                    to_public_key(&delegatee)?,
+                    governance,
+                    conditions,
+                    target_height,
+                ),
+                hash_encoding(&path).await?,
+                &private_key,
+            )
+            .map_err(|_| eyre!("failed to sign"))?;
+            println!("{}", proof.signature());
+        }
+        Commands::Sign(SignCommands::TxUndelegate { target_height }) => {
+            let private_key = unlock_private_key(&config, &path).await?;
+            let proof = TypedSignature::sign_with(
+                &(private_key.public_key(), target_height),
+                hash_encoding(&path).await?,
+                &private_key,
+            )
+            .map_err(|_| eyre!("failed to sign"))?;
+            println!("{}", proof.signature());
+        }
         Commands::GenesisNonProposer => {
-            // genesis::run_genesis_non_proposer(config, &path).await;
+            genesis::run_genesis_non_proposer(unlock_private_key(&config, &path).await?).await;
         }
         Commands::GenesisProposer => {
-            // genesis::run_genesis_proposer(config, &path).await;
+            genesis::run_genesis_proposer(unlock_private_key(&config, &path).await?).await;
         }
         _ => unimplemented!(),
     }
-    #[allow(unreachable_code)]
+    Ok(())
+}
+
+fn read_password(prompt: &str) -> Result<String> {
+    Ok(rpassword::prompt_password(prompt)?)
+}
+
+fn read_new_password() -> Result<String> {
+    let password = read_password("New password: ")?;
+    if password != read_password("Confirm the new password: ")? {
+        return Err(eyre!("the passwords don't match"));
+    }
+    Ok(password)
+}
+
+/// Returns the private key of the node, asking the password if the keystore is configured.
+async fn unlock_private_key(config: &Config, path: &str) -> Result<PrivateKey> {
+    if config.keystore.is_some() {
+        let password = read_password("Password: ")?;
+        config.unlock_private_key(path, Some(&password)).await
+    } else {
+        config.unlock_private_key(path, None).await
+    }
+}
+
+/// Initializes the node, asking the password if the keystore is configured.
+async fn initialize_node(config: Config, path: &str) -> Result<SimperbyNode> {
+    if config.keystore.is_some() {
+        simperby_node::initialize_with_password(config, path, &read_password("Password: ")?).await
+    } else {
+        simperby_node::initialize(config, path).await
+    }
+}
+
+/// Reads the last finalized header from the `finalized` branch.
+async fn last_finalized_header(path: &str) -> Result<BlockHeader> {
+    let raw = RawRepositoryImpl::open(&format!("{}/repository/repo", path)).await?;
+    let commit_hash = raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+    let commit = format::from_semantic_commit(raw.read_semantic_commit(commit_hash).await?)
+        .map_err(|e| eyre!(e))?;
+    if let Commit::Block(block_header) = commit {
+        Ok(block_header)
+    } else {
+        Err(eyre!("`finalized` branch is not on a block"))
+    }
+}
+
+/// Returns the encoding of the data to be signed, which is the one of the last finalized header.
+async fn hash_encoding(path: &str) -> Result<serde_spb::HashEncoding> {
+    last_finalized_header(path)
+        .await?
+        .hash_encoding()
+        .map_err(|e| eyre!(e))
+}
+
+/// Builds the conditions that release a delegation from the command-line options.
+///
+/// It must give the same conditions for signing and creating the transaction,
+/// so the validator set of the condition is the one of the reserved state checked out.
+async fn delegation_conditions(
+    path: &str,
+    unlock_height: Option<BlockHeight>,
+    unlock_timestamp: Option<Timestamp>,
+    unlock_if_delegatee_inactive: bool,
+    unlock_if_validator_set_changes: bool,
+) -> Result<Vec<DelegationCondition>> {
+    let mut conditions = unlock_height
+        .map(DelegationCondition::UnlockAtHeight)
+        .into_iter()
+        .chain(unlock_timestamp.map(DelegationCondition::UnlockAtTimestamp))
+        .collect::<Vec<_>>();
+    if unlock_if_delegatee_inactive {
+        conditions.push(DelegationCondition::UnlockIfDelegateeInactive);
+    }
+    if unlock_if_validator_set_changes {
+        let raw = RawRepositoryImpl::open(&format!("{}/repository/repo", path)).await?;
+        conditions.push(DelegationCondition::UnlockIfValidatorSetChanges(
+            raw.read_reserved_state()
+                .await?
+                .get_base_validator_set_hash(),
+        ));
+    }
+    Ok(conditions)
+}
+
+/// Reads the finalized headers that a report of a misbehavior at the given height carries,
+/// from the one right before that height to the one right before the last finalized header.
+async fn report_headers(path: &str, height: BlockHeight) -> Result<Vec<BlockHeader>> {
+    let raw = RawRepositoryImpl::open(&format!("{}/repository/repo", path)).await?;
+    let commit_hash = raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+    let mut headers = Vec::new();
+    for commit_hash in raw.list_ancestors(commit_hash, None).await? {
+        let commit = format::from_semantic_commit(raw.read_semantic_commit(commit_hash).await?)
+            .map_err(|e| eyre!(e))?;
+        if let Commit::Block(block_header) = commit {
+            let done = block_header.height + 1 >= height;
+            headers.push(block_header);
+            if done {
+                break;
+            }
+        }
+    }
+    headers.reverse();
+    Ok(headers)
+}
+
+fn get_timestamp() -> Timestamp {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as Timestamp
+}
+
+async fn read_keystore(file: &str) -> Result<Keystore> {
+    Ok(serde_spb::from_str(
+        &tokio::fs::read_to_string(file).await?,
+    )?)
+}
+
+async fn write_keystore(file: &str, keystore: &Keystore) -> Result<()> {
+    tokio::fs::write(file, serde_spb::to_string(keystore)?).await?;
+    Ok(())
+}
+
+/// Derives the private key from the mnemonic and stores it in the keystore.
+async fn store_derived_key(
+    file: &str,
+    mnemonic: &Mnemonic,
+    scheme: &str,
+    purpose: &str,
+    account: u32,
+) -> Result<()> {
+    let scheme: SignatureScheme = scheme.parse().map_err(|e: String| eyre!(e))?;
+    let purpose: KeyPurpose = purpose.parse().map_err(|e: String| eyre!(e))?;
+    let passphrase = read_password("Mnemonic passphrase (empty if none): ")?;
+    let path = purpose.derivation_path(account);
+    let (public_key, private_key) = mnemonic.derive_keypair(&passphrase, &path, scheme)?;
+    write_keystore(
+        file,
+        &Keystore::encrypt(&private_key, &read_new_password()?)?,
+    )
+    .await?;
+    println!("{} key at {}: {}", purpose, path, public_key);
+    Ok(())
+}
+
+/// Manages the keystore file.
+///
+/// It doesn't touch `config.json`; set its `keystore` to the file to use it.
+async fn manage_keystore(command: KeystoreCommands, file: &str) -> Result<()> {
+    if matches!(
+        command,
+        KeystoreCommands::Create { .. }
+            | KeystoreCommands::Recover { .. }
+            | KeystoreCommands::Import
+    ) && std::path::Path::new(file).exists()
+    {
+        return Err(eyre!("the keystore already exists: {}", file));
+    }
+    match command {
+        KeystoreCommands::Create {
+            scheme,
+            purpose,
+            account,
+            words,
+        } => {
+            let mnemonic = Mnemonic::generate(words)?;
+            println!("Write down the mnemonic phrase to recover the keys:");
+            println!("{}", mnemonic.phrase());
+            store_derived_key(file, &mnemonic, &scheme, &purpose, account).await?;
+        }
+        KeystoreCommands::Recover {
+            scheme,
+            purpose,
+            account,
+        } => {
+            let mnemonic = Mnemonic::parse(read_password("Mnemonic phrase: ")?.trim())?;
+            store_derived_key(file, &mnemonic, &scheme, &purpose, account).await?;
+        }
+        KeystoreCommands::Import => {
+            let private_key: PrivateKey = serde_spb::from_str(&serde_json::to_string(
+                read_password("Private key: ")?.trim(),
+            )?)
+            .map_err(|_| eyre!("invalid private key"))?;
+            write_keystore(
+                file,
+                &Keystore::encrypt(&private_key, &read_new_password()?)?,
+            )
+            .await?;
+            println!("public key: {}", private_key.public_key());
+        }
+        KeystoreCommands::Export => {
+            let private_key = read_keystore(file)
+                .await?
+                .decrypt(&read_password("Password: ")?)?;
+            println!(
+                "{}",
+                serde_json::to_value(&private_key)?
+                    .as_str()
+                    .ok_or_else(|| eyre!("failed to encode the private key"))?
+            );
+        }
+        KeystoreCommands::ChangePassword => {
+            let keystore = read_keystore(file).await?;
+            let old_password = read_password("Password: ")?;
+            // Fail early before asking the new one.
+            keystore.decrypt(&old_password)?;
+            let keystore = keystore.change_password(&old_password, &read_new_password()?)?;
+            write_keystore(file, &keystore).await?;
+        }
+    }
     Ok(())
 }
 
@@ -81,13 +400,23 @@ async fn main() -> eyre::Result<()> {
 /// For a block, show the consensus status projected on this block.
 /// For an extra-agenda transaction and a chat log, TODO.
 async fn show(config: Config, path: &str, commit_hash: String) -> Result<()> {
-    let node = simperby_node::initialize(config, path).await?;
+    let node = initialize_node(config, path).await?;
     let result = node.show(to_commit_hash(&commit_hash)?).await?;
     match result {
         CommitInfo::Block { block_header, .. } => {
             println!("hash: {}", block_header.to_hash256());
             // TODO
         }
+        CommitInfo::Transaction {
+            transaction,
+            reserved_state_diff,
+            ..
+        } => {
+            println!("{}", transaction.head);
+            if let Some(reserved_state_diff) = reserved_state_diff {
+                println!("reserved state:\n{}", reserved_state_diff);
+            }
+        }
         _ => todo!(),
     }
     Ok(())
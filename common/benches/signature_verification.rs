@@ -0,0 +1,50 @@
+//! Compares `verify_batch` with verifying the signatures one by one,
+//! as in the finalization proofs of a long chain.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use simperby_common::*;
+
+const SIGNATURES: usize = 200;
+
+fn generate_signatures(scheme: SignatureScheme) -> Vec<(Hash256, Signature, PublicKey)> {
+    (0..SIGNATURES)
+        .map(|i| {
+            let (public_key, private_key) = generate_keypair_with_scheme([i as u8], scheme);
+            let data = Hash256::hash(format!("block {}", i));
+            let signature = Signature::sign(data, &private_key).unwrap();
+            (data, signature, public_key)
+        })
+        .collect()
+}
+
+fn signature_verification(c: &mut Criterion) {
+    let mut group = c.benchmark_group("signature_verification");
+    for scheme in [
+        SignatureScheme::Secp256k1,
+        SignatureScheme::Ed25519,
+        SignatureScheme::Bls12381,
+    ] {
+        let signatures = generate_signatures(scheme);
+        let items = signatures
+            .iter()
+            .map(|(data, signature, public_key)| (*data, signature, public_key))
+            .collect::<Vec<_>>();
+        group.bench_with_input(
+            BenchmarkId::new("sequential", scheme),
+            &items,
+            |b, items| {
+                b.iter(|| {
+                    for (data, signature, public_key) in items {
+                        signature.verify(*data, public_key).unwrap();
+                    }
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("batch", scheme), &items, |b, items| {
+            b.iter(|| verify_batch(items).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, signature_verification);
+criterion_main!(benches);
@@ -1,4 +1,5 @@
 //! A set of types and functions related to cryptography, that are widely used in the entire Simperby project.
+use crate::serde_spb::HashEncoding;
 use secp256k1::{
     ecdsa::{RecoverableSignature, RecoveryId},
     Message, Secp256k1, SecretKey,
@@ -9,6 +10,10 @@ use std::fmt;
 use thiserror::Error;
 
 const EVM_EC_RECOVERY_OFFSET: u8 = 27;
+/// The domain separation tag of the BLS signatures, which assumes the proof of possession of the keys.
+const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+/// The domain separation tag of the proofs of possession of the BLS keys.
+const BLS_POP_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
 
 #[derive(Error, Debug, Clone)]
 pub enum CryptoError {
@@ -23,6 +28,16 @@ type Error = CryptoError;
 
 pub trait ToHash256 {
     fn to_hash256(&self) -> Hash256;
+
+    /// Calculates the hash with the given encoding, which must be the one of the chain
+    /// that the data belongs to.
+    ///
+    /// It is the same as `to_hash256()` for the types whose hash doesn't depend on the encoding
+    /// or that carry their own protocol version (e.g., `BlockHeader`).
+    fn to_hash256_with(&self, encoding: HashEncoding) -> Hash256 {
+        let _ = encoding;
+        self.to_hash256()
+    }
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Copy)]
@@ -36,12 +51,18 @@ impl<const N: usize> HexSerializedBytes<N> {
     }
 }
 
+/// It is a hex string in human-readable formats and raw bytes otherwise
+/// (e.g., the canonical encoding in `serde_spb`).
 impl<const N: usize> Serialize for HexSerializedBytes<N> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::ser::Serializer,
     {
-        serializer.serialize_str(hex::encode(self.data).as_str())
+        if serializer.is_human_readable() {
+            serializer.serialize_str(hex::encode(self.data).as_str())
+        } else {
+            serializer.serialize_bytes(&self.data)
+        }
     }
 }
 
@@ -62,8 +83,12 @@ impl<'de, const N: usize> Deserialize<'de> for HexSerializedBytes<N> {
     where
         D: serde::de::Deserializer<'de>,
     {
-        let s: String = Deserialize::deserialize(deserializer)?;
-        let bytes = hex::decode(s).map_err(|e| serde::de::Error::custom(e.to_string()))?;
+        let bytes: Vec<u8> = if deserializer.is_human_readable() {
+            let s: String = Deserialize::deserialize(deserializer)?;
+            hex::decode(s).map_err(|e| serde::de::Error::custom(e.to_string()))?
+        } else {
+            Deserialize::deserialize(deserializer)?
+        };
         if bytes.len() != N {
             return Err(serde::de::Error::custom("invalid length"));
         }
@@ -122,66 +147,274 @@ impl fmt::Display for Hash256 {
     }
 }
 
+/// A signature scheme that the keys and signatures are of.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    /// The recoverable ECDSA over secp256k1, which is the default one.
+    Secp256k1,
+    /// The EdDSA over Curve25519.
+    Ed25519,
+    /// The BLS signature over BLS12-381 (with public keys in G1), which can be aggregated.
+    Bls12381,
+}
+
+impl SignatureScheme {
+    /// The prefix of the keys and signatures of this scheme in human-readable formats.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            SignatureScheme::Secp256k1 => "secp256k1",
+            SignatureScheme::Ed25519 => "ed25519",
+            SignatureScheme::Bls12381 => "bls12381",
+        }
+    }
+
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        [
+            SignatureScheme::Secp256k1,
+            SignatureScheme::Ed25519,
+            SignatureScheme::Bls12381,
+        ]
+        .into_iter()
+        .find(|scheme| scheme.prefix() == prefix)
+    }
+}
+
+impl std::str::FromStr for SignatureScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::from_prefix(s).ok_or_else(|| format!("unknown signature scheme: {}", s))
+    }
+}
+
+impl fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.prefix())
+    }
+}
+
+/// Bytes of any scheme, where `S`, `E` and `B` are the lengths for secp256k1, Ed25519 and BLS12-381.
+///
+/// In human-readable formats, it is a hex string with the scheme prefix (e.g., `ed25519:0a1b...`),
+/// except for secp256k1 which is a bare hex string as before (the prefix is accepted though).
+/// Otherwise it is the raw bytes, whose scheme is told by the length.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Copy)]
+enum SchemeBytes<const S: usize, const E: usize, const B: usize> {
+    Secp256k1(HexSerializedBytes<S>),
+    Ed25519(HexSerializedBytes<E>),
+    Bls12381(HexSerializedBytes<B>),
+}
+
+impl<const S: usize, const E: usize, const B: usize> SchemeBytes<S, E, B> {
+    fn scheme(&self) -> SignatureScheme {
+        match self {
+            SchemeBytes::Secp256k1(_) => SignatureScheme::Secp256k1,
+            SchemeBytes::Ed25519(_) => SignatureScheme::Ed25519,
+            SchemeBytes::Bls12381(_) => SignatureScheme::Bls12381,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            SchemeBytes::Secp256k1(x) => &x.data,
+            SchemeBytes::Ed25519(x) => &x.data,
+            SchemeBytes::Bls12381(x) => &x.data,
+        }
+    }
+
+    fn from_slice(scheme: SignatureScheme, bytes: &[u8]) -> Result<Self, Error> {
+        let invalid_length = || {
+            Error::InvalidFormat(format!(
+                "invalid length for {}: {}",
+                scheme,
+                hex::encode(bytes)
+            ))
+        };
+        Ok(match scheme {
+            SignatureScheme::Secp256k1 => SchemeBytes::Secp256k1(HexSerializedBytes {
+                data: bytes.try_into().map_err(|_| invalid_length())?,
+            }),
+            SignatureScheme::Ed25519 => SchemeBytes::Ed25519(HexSerializedBytes {
+                data: bytes.try_into().map_err(|_| invalid_length())?,
+            }),
+            SignatureScheme::Bls12381 => SchemeBytes::Bls12381(HexSerializedBytes {
+                data: bytes.try_into().map_err(|_| invalid_length())?,
+            }),
+        })
+    }
+}
+
+impl<const S: usize, const E: usize, const B: usize> fmt::Display for SchemeBytes<S, E, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemeBytes::Secp256k1(x) => write!(f, "{}", x),
+            SchemeBytes::Ed25519(x) => write!(f, "{}:{}", SignatureScheme::Ed25519, x),
+            SchemeBytes::Bls12381(x) => write!(f, "{}:{}", SignatureScheme::Bls12381, x),
+        }
+    }
+}
+
+impl<const S: usize, const E: usize, const B: usize> fmt::Debug for SchemeBytes<S, E, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl<const S: usize, const E: usize, const B: usize> Serialize for SchemeBytes<S, E, B> {
+    fn serialize<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: serde::ser::Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
+    }
+}
+
+impl<'de, const S: usize, const E: usize, const B: usize> Deserialize<'de>
+    for SchemeBytes<S, E, B>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        let (scheme, bytes) = if deserializer.is_human_readable() {
+            let s: String = Deserialize::deserialize(deserializer)?;
+            let (scheme, s) = match s.split_once(':') {
+                Some((prefix, s)) => (
+                    SignatureScheme::from_prefix(prefix).ok_or_else(|| {
+                        serde::de::Error::custom(format!("unknown signature scheme: {}", prefix))
+                    })?,
+                    s,
+                ),
+                None => (SignatureScheme::Secp256k1, s.as_str()),
+            };
+            let bytes = hex::decode(s).map_err(|e| serde::de::Error::custom(e.to_string()))?;
+            (scheme, bytes)
+        } else {
+            let bytes: Vec<u8> = Deserialize::deserialize(deserializer)?;
+            let scheme = match bytes.len() {
+                x if x == S => SignatureScheme::Secp256k1,
+                x if x == E => SignatureScheme::Ed25519,
+                x if x == B => SignatureScheme::Bls12381,
+                _ => return Err(serde::de::Error::custom("invalid length")),
+            };
+            (scheme, bytes)
+        };
+        Self::from_slice(scheme, &bytes).map_err(|e| serde::de::Error::custom(e.to_string()))
+    }
+}
+
 /// A cryptographic signature.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Signature {
-    signature: HexSerializedBytes<65>,
+    signature: SchemeBytes<65, 64, 96>,
 }
 
 impl Signature {
     pub const fn zero() -> Self {
         Signature {
-            signature: HexSerializedBytes { data: [0; 65] },
+            signature: SchemeBytes::Secp256k1(HexSerializedBytes { data: [0; 65] }),
         }
     }
 
     /// Creates a new signature from the given data and keys.
     pub fn sign(data: Hash256, private_key: &PrivateKey) -> Result<Self, Error> {
-        let private_key = secp256k1::SecretKey::from_slice(&private_key.key.data)
-            .map_err(|_| Error::InvalidFormat("private key: [omitted]".to_owned()))?;
-        let message = Message::from_slice(data.as_ref()).unwrap();
-        let (recovery_id, rs) = Secp256k1::signing_only()
-            .sign_ecdsa_recoverable(&message, &private_key)
-            .serialize_compact();
-        let v = recovery_id.to_i32() as u8;
-        let bytes: [u8; 65] = {
-            let mut whole: [u8; 65] = [0; 65];
-            let (left, right) = whole.split_at_mut(rs.len());
-            left.copy_from_slice(&rs);
-            right.copy_from_slice(&[v + EVM_EC_RECOVERY_OFFSET; 1]);
-            whole
-        };
-        Ok(Signature {
-            signature: HexSerializedBytes { data: bytes },
-        })
+        match &private_key.key {
+            SchemeBytes::Secp256k1(key) => {
+                let private_key = secp256k1::SecretKey::from_slice(&key.data)
+                    .map_err(|_| Error::InvalidFormat("private key: [omitted]".to_owned()))?;
+                let message = Message::from_slice(data.as_ref()).unwrap();
+                let (recovery_id, rs) = Secp256k1::signing_only()
+                    .sign_ecdsa_recoverable(&message, &private_key)
+                    .serialize_compact();
+                let v = recovery_id.to_i32() as u8;
+                let bytes: [u8; 65] = {
+                    let mut whole: [u8; 65] = [0; 65];
+                    let (left, right) = whole.split_at_mut(rs.len());
+                    left.copy_from_slice(&rs);
+                    right.copy_from_slice(&[v + EVM_EC_RECOVERY_OFFSET; 1]);
+                    whole
+                };
+                Ok(Signature::from_array(bytes))
+            }
+            SchemeBytes::Ed25519(key) => {
+                let keypair = ed25519_dalek::Keypair::from_bytes(&key.data)
+                    .map_err(|_| Error::InvalidFormat("private key: [omitted]".to_owned()))?;
+                let signature = ed25519_dalek::Signer::sign(&keypair, data.as_ref());
+                Ok(Signature::from_ed25519_array(signature.to_bytes()))
+            }
+            SchemeBytes::Bls12381(key) => {
+                let secret_key = blst::min_pk::SecretKey::from_bytes(&key.data[0..32])
+                    .map_err(|_| Error::InvalidFormat("private key: [omitted]".to_owned()))?;
+                let signature = secret_key.sign(data.as_ref(), BLS_DST, &[]);
+                Ok(Signature::from_bls12381_array(signature.to_bytes()))
+            }
+        }
     }
 
     /// Verifies the signature against the given data and public key.
+    ///
+    /// It fails if the signature and the public key are of different schemes.
     pub fn verify(&self, data: Hash256, public_key: &PublicKey) -> Result<(), Error> {
-        let signature = secp256k1::ecdsa::Signature::from_compact(&self.signature.data[0..64])
-            .map_err(|_| Error::InvalidFormat(format!("signature: {}", self)))?;
-        let public_key = secp256k1::PublicKey::from_slice(&public_key.key.data)
-            .map_err(|_| Error::InvalidFormat(format!("public_key: {}", public_key)))?;
-        let message = Message::from_slice(data.as_ref()).unwrap();
-        Secp256k1::verification_only()
-            .verify_ecdsa(&message, &signature, &public_key)
-            .map_err(|_| Error::VerificationFailed)
+        match (&self.signature, &public_key.key) {
+            (SchemeBytes::Secp256k1(signature), SchemeBytes::Secp256k1(key)) => {
+                let signature =
+                    secp256k1::ecdsa::Signature::from_compact(&signature.data[0..64])
+                        .map_err(|_| Error::InvalidFormat(format!("signature: {}", self)))?;
+                let public_key = secp256k1::PublicKey::from_slice(&key.data)
+                    .map_err(|_| Error::InvalidFormat(format!("public_key: {}", public_key)))?;
+                let message = Message::from_slice(data.as_ref()).unwrap();
+                Secp256k1::verification_only()
+                    .verify_ecdsa(&message, &signature, &public_key)
+                    .map_err(|_| Error::VerificationFailed)
+            }
+            (SchemeBytes::Ed25519(signature), SchemeBytes::Ed25519(key)) => {
+                let signature = ed25519_dalek::Signature::from_bytes(&signature.data)
+                    .map_err(|_| Error::InvalidFormat(format!("signature: {}", self)))?;
+                let public_key = ed25519_dalek::PublicKey::from_bytes(&key.data)
+                    .map_err(|_| Error::InvalidFormat(format!("public_key: {}", public_key)))?;
+                public_key
+                    .verify_strict(data.as_ref(), &signature)
+                    .map_err(|_| Error::VerificationFailed)
+            }
+            (SchemeBytes::Bls12381(_), SchemeBytes::Bls12381(_)) => {
+                self.verify_aggregated(data, std::slice::from_ref(public_key))
+            }
+            _ => Err(Error::InvalidFormat(format!(
+                "signature scheme mismatch: {} signature for {} public key",
+                self.scheme(),
+                public_key.scheme()
+            ))),
+        }
     }
 
     /// Recover a public key from the given signature.
+    ///
+    /// Only secp256k1 signatures are recoverable.
     pub fn recover(&self, data: Hash256) -> Result<PublicKey, Error> {
+        let signature = match &self.signature {
+            SchemeBytes::Secp256k1(signature) => signature,
+            SchemeBytes::Ed25519(_) | SchemeBytes::Bls12381(_) => {
+                return Err(Error::InvalidFormat(format!(
+                    "{} signature is not recoverable",
+                    self.scheme()
+                )))
+            }
+        };
         let message = Message::from_slice(data.as_ref()).unwrap();
-        let recovery_id = RecoveryId::from_i32(
-            self.signature.data[64..65][0] as i32 - EVM_EC_RECOVERY_OFFSET as i32,
-        )
-        .unwrap();
+        let recovery_id =
+            RecoveryId::from_i32(signature.data[64..65][0] as i32 - EVM_EC_RECOVERY_OFFSET as i32)
+                .unwrap();
         if recovery_id.to_i32() != 0 && recovery_id.to_i32() != 1 {
             println!("recid: {}", recovery_id.to_i32());
             return Err(Error::VerificationFailed);
         }
         let signature =
-            RecoverableSignature::from_compact(&self.signature.data[0..64], recovery_id).unwrap();
+            RecoverableSignature::from_compact(&signature.data[0..64], recovery_id).unwrap();
         let secp = Secp256k1::new();
         let public_key = secp
             .recover_ecdsa(&message, &signature)
@@ -190,17 +423,137 @@ impl Signature {
         PublicKey::from_array(public_key)
     }
 
-    /// Constructs a signature from the given bytes, but does not verify its validity.
+    /// Constructs a secp256k1 signature from the given bytes, but does not verify its validity.
     pub fn from_array(bytes: [u8; 65]) -> Self {
         Signature {
-            signature: HexSerializedBytes { data: bytes },
+            signature: SchemeBytes::Secp256k1(HexSerializedBytes { data: bytes }),
+        }
+    }
+
+    /// Constructs an Ed25519 signature from the given bytes, but does not verify its validity.
+    pub fn from_ed25519_array(bytes: [u8; 64]) -> Self {
+        Signature {
+            signature: SchemeBytes::Ed25519(HexSerializedBytes { data: bytes }),
+        }
+    }
+
+    /// Constructs a BLS12-381 signature from the given compressed bytes,
+    /// but does not verify its validity.
+    pub fn from_bls12381_array(bytes: [u8; 96]) -> Self {
+        Signature {
+            signature: SchemeBytes::Bls12381(HexSerializedBytes { data: bytes }),
+        }
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        self.signature.scheme()
+    }
+
+    /// Aggregates the given BLS12-381 signatures into one.
+    pub fn aggregate(signatures: &[Signature]) -> Result<Self, Error> {
+        let signatures = signatures
+            .iter()
+            .map(|signature| match &signature.signature {
+                SchemeBytes::Bls12381(x) => blst::min_pk::Signature::sig_validate(&x.data, true)
+                    .map_err(|_| Error::InvalidFormat(format!("signature: {}", signature))),
+                _ => Err(Error::InvalidFormat(format!(
+                    "{} signature can't be aggregated",
+                    signature.scheme()
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let signatures = signatures.iter().collect::<Vec<_>>();
+        let signature = blst::min_pk::AggregateSignature::aggregate(&signatures, false)
+            .map_err(|_| Error::InvalidFormat("no signatures to aggregate".to_owned()))?;
+        Ok(Signature::from_bls12381_array(
+            signature.to_signature().to_bytes(),
+        ))
+    }
+
+    /// Verifies the aggregated BLS12-381 signature against the given data and the public keys
+    /// of the signers, which must have signed the same data.
+    ///
+    /// Note that the signers must have proven the possession of their keys
+    /// (see `prove_possession()`) to prevent the rogue key attack.
+    pub fn verify_aggregated(&self, data: Hash256, public_keys: &[PublicKey]) -> Result<(), Error> {
+        let signature = match &self.signature {
+            SchemeBytes::Bls12381(x) => blst::min_pk::Signature::from_bytes(&x.data)
+                .map_err(|_| Error::InvalidFormat(format!("signature: {}", self)))?,
+            _ => {
+                return Err(Error::InvalidFormat(format!(
+                    "{} signature is not an aggregated one",
+                    self.scheme()
+                )))
+            }
+        };
+        let public_keys = public_keys
+            .iter()
+            .map(|public_key| match &public_key.key {
+                SchemeBytes::Bls12381(x) => blst::min_pk::PublicKey::key_validate(&x.data)
+                    .map_err(|_| Error::InvalidFormat(format!("public_key: {}", public_key))),
+                _ => Err(Error::InvalidFormat(format!(
+                    "signature scheme mismatch: {} signature for {} public key",
+                    self.scheme(),
+                    public_key.scheme()
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if public_keys.is_empty() {
+            return Err(Error::VerificationFailed);
+        }
+        let public_keys = public_keys.iter().collect::<Vec<_>>();
+        match signature.fast_aggregate_verify(true, data.as_ref(), BLS_DST, &public_keys) {
+            blst::BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(Error::VerificationFailed),
+        }
+    }
+
+    /// Creates the proof of possession of the given BLS12-381 private key,
+    /// which is a signature on its public key in a separate domain.
+    pub fn prove_possession(private_key: &PrivateKey) -> Result<Self, Error> {
+        let secret_key = match &private_key.key {
+            SchemeBytes::Bls12381(key) => blst::min_pk::SecretKey::from_bytes(&key.data[0..32])
+                .map_err(|_| Error::InvalidFormat("private key: [omitted]".to_owned()))?,
+            _ => {
+                return Err(Error::InvalidFormat(format!(
+                    "{} key has no proof of possession",
+                    private_key.scheme()
+                )))
+            }
+        };
+        let public_key = secret_key.sk_to_pk().to_bytes();
+        let signature = secret_key.sign(&public_key, BLS_POP_DST, &[]);
+        Ok(Signature::from_bls12381_array(signature.to_bytes()))
+    }
+
+    /// Verifies the proof of possession of the given BLS12-381 public key.
+    pub fn verify_possession(&self, public_key: &PublicKey) -> Result<(), Error> {
+        match (&self.signature, &public_key.key) {
+            (SchemeBytes::Bls12381(signature), SchemeBytes::Bls12381(key)) => {
+                let signature = blst::min_pk::Signature::from_bytes(&signature.data)
+                    .map_err(|_| Error::InvalidFormat(format!("signature: {}", self)))?;
+                let key_point = blst::min_pk::PublicKey::key_validate(&key.data)
+                    .map_err(|_| Error::InvalidFormat(format!("public_key: {}", public_key)))?;
+                match signature.verify(true, &key.data, BLS_POP_DST, &[], &key_point, false) {
+                    blst::BLST_ERROR::BLST_SUCCESS => Ok(()),
+                    _ => Err(Error::VerificationFailed),
+                }
+            }
+            _ => Err(Error::InvalidFormat(format!(
+                "{} signature is not a proof of possession of {} public key",
+                self.scheme(),
+                public_key.scheme()
+            ))),
         }
     }
 }
 
 /// A signature that is explicitly marked with the type of the signed data.
 ///
-/// This implies that the signature is created on `Hash256::hash(serde_spb::to_vec(T).unwrap())`.
+/// This implies that the signature is created on `T::to_hash256_with(encoding)`
+/// where the encoding is the one of the chain that the data belongs to
+/// (`sign_with()` and `verify_with()`), or on `T::to_hash256()` for the data
+/// that doesn't belong to a chain (`sign()` and `verify()`).
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
 pub struct TypedSignature<T> {
     signature: Signature,
@@ -220,6 +573,20 @@ impl<T: ToHash256> TypedSignature<T> {
         })
     }
 
+    /// Creates a new signature on the hash of the data in the given encoding.
+    pub fn sign_with(
+        data: &T,
+        encoding: HashEncoding,
+        private_key: &PrivateKey,
+    ) -> Result<Self, Error> {
+        let data = data.to_hash256_with(encoding);
+        Signature::sign(data, private_key).map(|signature| TypedSignature {
+            signature,
+            signer: private_key.public_key(),
+            _mark: std::marker::PhantomData,
+        })
+    }
+
     pub fn new(signature: Signature, signer: PublicKey) -> Self {
         TypedSignature {
             signature,
@@ -238,14 +605,24 @@ impl<T: ToHash256> TypedSignature<T> {
         self.signature.verify(data, &self.signer)
     }
 
+    /// Verifies the signature against the hash of the data in the given encoding.
+    pub fn verify_with(&self, data: &T, encoding: HashEncoding) -> Result<(), Error> {
+        let data = data.to_hash256_with(encoding);
+        self.signature.verify(data, &self.signer)
+    }
+
     pub fn get_raw_signature(&self) -> Signature {
         self.signature.clone()
     }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
 }
 
 impl std::convert::AsRef<[u8]> for Signature {
     fn as_ref(&self) -> &[u8] {
-        &self.signature.data
+        self.signature.as_bytes()
     }
 }
 
@@ -259,12 +636,12 @@ impl fmt::Display for Signature {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct PublicKey {
-    key: HexSerializedBytes<33>,
+    key: SchemeBytes<33, 32, 48>,
 }
 
 impl std::convert::AsRef<[u8]> for PublicKey {
     fn as_ref(&self) -> &[u8] {
-        &self.key.data
+        self.key.as_bytes()
     }
 }
 
@@ -277,7 +654,7 @@ impl fmt::Display for PublicKey {
 impl PublicKey {
     pub fn zero() -> Self {
         Self {
-            key: HexSerializedBytes::zero(),
+            key: SchemeBytes::Secp256k1(HexSerializedBytes::zero()),
         }
     }
 
@@ -286,55 +663,235 @@ impl PublicKey {
             .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?
             .serialize();
         Ok(PublicKey {
-            key: HexSerializedBytes { data: key },
+            key: SchemeBytes::Secp256k1(HexSerializedBytes { data: key }),
         })
     }
 
+    /// Constructs a secp256k1 public key from the given compressed bytes.
     pub fn from_array(array: [u8; 33]) -> Result<Self, Error> {
         let key = secp256k1::PublicKey::from_slice(array.as_ref())
             .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?
             .serialize();
         Ok(PublicKey {
-            key: HexSerializedBytes { data: key },
+            key: SchemeBytes::Secp256k1(HexSerializedBytes { data: key }),
+        })
+    }
+
+    /// Constructs an Ed25519 public key from the given bytes.
+    pub fn from_ed25519_array(array: [u8; 32]) -> Result<Self, Error> {
+        let key = ed25519_dalek::PublicKey::from_bytes(&array)
+            .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?
+            .to_bytes();
+        Ok(PublicKey {
+            key: SchemeBytes::Ed25519(HexSerializedBytes { data: key }),
+        })
+    }
+
+    /// Constructs a BLS12-381 public key from the given compressed bytes.
+    pub fn from_bls12381_array(array: [u8; 48]) -> Result<Self, Error> {
+        let key = blst::min_pk::PublicKey::key_validate(&array)
+            .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?
+            .to_bytes();
+        Ok(PublicKey {
+            key: SchemeBytes::Bls12381(HexSerializedBytes { data: key }),
         })
     }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        self.key.scheme()
+    }
 }
 
 /// A private key.
+///
+/// Ed25519 and BLS12-381 private keys are the keypairs (the secret key followed by the public key).
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct PrivateKey {
-    pub key: HexSerializedBytes<32>,
+    key: SchemeBytes<32, 64, 80>,
 }
 
 impl std::convert::AsRef<[u8]> for PrivateKey {
     fn as_ref(&self) -> &[u8] {
-        &self.key.data
+        self.key.as_bytes()
     }
 }
 
 impl PrivateKey {
     pub fn zero() -> Self {
         Self {
-            key: HexSerializedBytes::zero(),
+            key: SchemeBytes::Secp256k1(HexSerializedBytes::zero()),
         }
     }
 
+    /// Constructs a secp256k1 private key from the given bytes.
     pub fn from_array(array: [u8; 32]) -> Result<Self, Error> {
         let key = secp256k1::SecretKey::from_slice(&array)
             .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?
             .secret_bytes();
         Ok(PrivateKey {
-            key: HexSerializedBytes { data: key },
+            key: SchemeBytes::Secp256k1(HexSerializedBytes { data: key }),
+        })
+    }
+
+    /// Constructs an Ed25519 private key from the given 32-byte secret key.
+    pub fn from_ed25519_secret(array: [u8; 32]) -> Result<Self, Error> {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&array)
+            .map_err(|_| Error::InvalidFormat("given bytes: [omitted]".to_owned()))?;
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        Ok(PrivateKey {
+            key: SchemeBytes::Ed25519(HexSerializedBytes {
+                data: ed25519_dalek::Keypair { secret, public }.to_bytes(),
+            }),
+        })
+    }
+
+    /// Constructs a BLS12-381 private key from the given 32-byte secret key.
+    pub fn from_bls12381_secret(array: [u8; 32]) -> Result<Self, Error> {
+        let secret = blst::min_pk::SecretKey::from_bytes(&array)
+            .map_err(|_| Error::InvalidFormat("given bytes: [omitted]".to_owned()))?;
+        let public = secret.sk_to_pk();
+        Ok(PrivateKey {
+            key: SchemeBytes::Bls12381(HexSerializedBytes {
+                data: [secret.to_bytes().as_slice(), public.to_bytes().as_slice()]
+                    .concat()
+                    .try_into()
+                    .expect("invalid length"),
+            }),
         })
     }
 
     pub fn public_key(&self) -> PublicKey {
-        let private_key = SecretKey::from_slice(&self.key.data).expect("invalid private key");
-        let secp = Secp256k1::new();
-        let public_key = private_key.public_key(&secp);
-        PublicKey::from_array(public_key.serialize()).expect("invalid public key")
+        match &self.key {
+            SchemeBytes::Secp256k1(key) => {
+                let private_key = SecretKey::from_slice(&key.data).expect("invalid private key");
+                let secp = Secp256k1::new();
+                let public_key = private_key.public_key(&secp);
+                PublicKey::from_array(public_key.serialize()).expect("invalid public key")
+            }
+            SchemeBytes::Ed25519(key) => {
+                let secret = ed25519_dalek::SecretKey::from_bytes(&key.data[0..32])
+                    .expect("invalid private key");
+                let public_key = ed25519_dalek::PublicKey::from(&secret);
+                PublicKey::from_ed25519_array(public_key.to_bytes()).expect("invalid public key")
+            }
+            SchemeBytes::Bls12381(key) => {
+                let secret = blst::min_pk::SecretKey::from_bytes(&key.data[0..32])
+                    .expect("invalid private key");
+                PublicKey::from_bls12381_array(secret.sk_to_pk().to_bytes())
+                    .expect("invalid public key")
+            }
+        }
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        self.key.scheme()
+    }
+}
+
+/// The number of signatures below which `verify_batch` doesn't bother to spawn threads.
+const PARALLEL_VERIFICATION_THRESHOLD: usize = 16;
+
+/// Verifies many signatures at once, each of which is on its own data with its own public key.
+///
+/// It accepts exactly the signatures that `Signature::verify` accepts, but faster:
+/// - BLS12-381 signatures are verified together with random linear combinations,
+/// falling back to one by one only to find the invalid ones.
+/// - the others are verified one by one in parallel. (Ed25519 batch verification is not used
+/// because it accepts some signatures that `verify_strict` rejects.)
+///
+/// On failure, it returns the indices of the invalid signatures in ascending order.
+pub fn verify_batch(items: &[(Hash256, &Signature, &PublicKey)]) -> Result<(), Vec<usize>> {
+    let (bls, others): (Vec<usize>, Vec<usize>) = (0..items.len()).partition(|&i| {
+        let (_, signature, public_key) = &items[i];
+        signature.scheme() == SignatureScheme::Bls12381
+            && public_key.scheme() == SignatureScheme::Bls12381
+    });
+    let mut invalid = verify_each(items, &others);
+    if !verify_bls12381_batch(items, &bls) {
+        invalid.extend(verify_each(items, &bls));
     }
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        invalid.sort_unstable();
+        Err(invalid)
+    }
+}
+
+/// Verifies the signatures at the given indices one by one, returning the invalid ones.
+fn verify_each(items: &[(Hash256, &Signature, &PublicKey)], indices: &[usize]) -> Vec<usize> {
+    let verify_chunk = |chunk: &[usize]| {
+        chunk
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let (data, signature, public_key) = &items[i];
+                signature.verify(*data, public_key).is_err()
+            })
+            .collect::<Vec<_>>()
+    };
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    if indices.len() < PARALLEL_VERIFICATION_THRESHOLD || threads == 1 {
+        return verify_chunk(indices);
+    }
+    let chunk_size = indices.len() / threads + 1;
+    std::thread::scope(|scope| {
+        indices
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || verify_chunk(chunk)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("verification thread panicked"))
+            .collect()
+    })
+}
+
+/// Verifies the BLS12-381 signatures at the given indices together.
+fn verify_bls12381_batch(items: &[(Hash256, &Signature, &PublicKey)], indices: &[usize]) -> bool {
+    if indices.is_empty() {
+        return true;
+    }
+    let mut signatures = Vec::new();
+    let mut public_keys = Vec::new();
+    for &i in indices {
+        let (_, signature, public_key) = &items[i];
+        let signature = blst::min_pk::Signature::from_bytes(signature.signature.as_bytes());
+        let public_key = blst::min_pk::PublicKey::key_validate(public_key.key.as_bytes());
+        match (signature, public_key) {
+            (Ok(signature), Ok(public_key)) => {
+                signatures.push(signature);
+                public_keys.push(public_key);
+            }
+            _ => return false,
+        }
+    }
+    let messages = indices
+        .iter()
+        .map(|&i| items[i].0.as_ref())
+        .collect::<Vec<_>>();
+    // 64-bit random non-zero scalars, which make forging a batch as hard as guessing them.
+    let scalars = indices
+        .iter()
+        .map(|_| {
+            let mut scalar = blst::blst_scalar::default();
+            rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut scalar.b[0..8]);
+            scalar.b[0] |= 1;
+            scalar
+        })
+        .collect::<Vec<_>>();
+    blst::min_pk::Signature::verify_multiple_aggregate_signatures(
+        &messages,
+        BLS_DST,
+        &public_keys.iter().collect::<Vec<_>>(),
+        false,
+        &signatures.iter().collect::<Vec<_>>(),
+        true,
+        &scalars,
+        64,
+    ) == blst::BLST_ERROR::BLST_SUCCESS
 }
 
 /// Checks whether the given public and private keys match.
@@ -344,23 +901,46 @@ pub fn check_keypair_match(public_key: &PublicKey, private_key: &PrivateKey) ->
     signature.verify(Hash256::hash(msg), public_key)
 }
 
-/// Generates a new keypair using the seed.
+/// Generates a new secp256k1 keypair using the seed.
 pub fn generate_keypair(seed: impl AsRef<[u8]>) -> (PublicKey, PrivateKey) {
+    generate_keypair_with_scheme(seed, SignatureScheme::Secp256k1)
+}
+
+/// Generates a new keypair of the given scheme using the seed.
+pub fn generate_keypair_with_scheme(
+    seed: impl AsRef<[u8]>,
+    scheme: SignatureScheme,
+) -> (PublicKey, PrivateKey) {
     let mut seed_: [u8; 32] = [0; 32];
     for (i, x) in Hash256::hash(seed).as_ref()[0..32].iter().enumerate() {
         seed_[i] = *x;
     }
-    use secp256k1::rand::SeedableRng;
-    let mut rng = secp256k1::rand::rngs::StdRng::from_seed(seed_);
-    let secp = Secp256k1::new();
-    let (private_key, public_key) = secp.generate_keypair(&mut rng);
-    (
-        PublicKey::from_array(public_key.serialize()).expect("invalid public key"),
-        PrivateKey::from_array(private_key.secret_bytes()).expect("invalid private key"),
-    )
+    match scheme {
+        SignatureScheme::Secp256k1 => {
+            use secp256k1::rand::SeedableRng;
+            let mut rng = secp256k1::rand::rngs::StdRng::from_seed(seed_);
+            let secp = Secp256k1::new();
+            let (private_key, public_key) = secp.generate_keypair(&mut rng);
+            (
+                PublicKey::from_array(public_key.serialize()).expect("invalid public key"),
+                PrivateKey::from_array(private_key.secret_bytes()).expect("invalid private key"),
+            )
+        }
+        SignatureScheme::Ed25519 => {
+            let private_key = PrivateKey::from_ed25519_secret(seed_).expect("invalid private key");
+            (private_key.public_key(), private_key)
+        }
+        SignatureScheme::Bls12381 => {
+            let secret =
+                blst::min_pk::SecretKey::key_gen(&seed_, &[]).expect("invalid key material");
+            let private_key =
+                PrivateKey::from_bls12381_secret(secret.to_bytes()).expect("invalid private key");
+            (private_key.public_key(), private_key)
+        }
+    }
 }
 
-/// Generates a new keypair randomly
+/// Generates a new secp256k1 keypair randomly
 pub fn generate_keypair_random() -> (PublicKey, PrivateKey) {
     use secp256k1::rand::SeedableRng;
     let mut rng = secp256k1::rand::rngs::StdRng::from_entropy();
@@ -473,4 +1053,197 @@ mod tests {
             hex::encode(recovered.as_ref())
         );
     }
+
+    #[test]
+    fn ed25519_sign_verify() {
+        let (public_key, private_key) =
+            generate_keypair_with_scheme("hello world", SignatureScheme::Ed25519);
+        assert_eq!(public_key.scheme(), SignatureScheme::Ed25519);
+        assert_eq!(private_key.public_key(), public_key);
+        let signature = Signature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+        assert_eq!(signature.scheme(), SignatureScheme::Ed25519);
+        signature
+            .verify(Hash256::hash("hello world"), &public_key)
+            .unwrap();
+        signature
+            .verify(Hash256::hash("hello world2"), &public_key)
+            .unwrap_err();
+        signature.recover(Hash256::hash("hello world")).unwrap_err();
+        check_keypair_match(&public_key, &private_key).unwrap();
+    }
+
+    #[test]
+    fn signature_scheme_mismatch() {
+        let (secp_public_key, secp_private_key) = generate_keypair("hello world");
+        let (ed_public_key, ed_private_key) =
+            generate_keypair_with_scheme("hello world", SignatureScheme::Ed25519);
+        let data = Hash256::hash("hello world");
+        Signature::sign(data, &secp_private_key)
+            .unwrap()
+            .verify(data, &ed_public_key)
+            .unwrap_err();
+        Signature::sign(data, &ed_private_key)
+            .unwrap()
+            .verify(data, &secp_public_key)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn ed25519_encode_decode() {
+        let (public_key, private_key) =
+            generate_keypair_with_scheme("hello world", SignatureScheme::Ed25519);
+        let signature = Signature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+
+        let encoded = serde_spb::to_string(&public_key).unwrap();
+        assert_eq!(
+            encoded,
+            format!("\"ed25519:{}\"", hex::encode(public_key.as_ref()))
+        );
+        assert_eq!(
+            serde_spb::from_str::<PublicKey>(&encoded).unwrap(),
+            public_key
+        );
+        let encoded = serde_spb::to_string(&private_key).unwrap();
+        assert_eq!(
+            serde_spb::from_str::<PrivateKey>(&encoded).unwrap(),
+            private_key
+        );
+        let encoded = serde_spb::to_string(&signature).unwrap();
+        assert_eq!(
+            serde_spb::from_str::<Signature>(&encoded).unwrap(),
+            signature
+        );
+
+        let encoded = serde_spb::to_canonical_vec(&public_key).unwrap();
+        assert_eq!(encoded.len(), 1 + 32);
+        assert_eq!(
+            serde_spb::from_canonical_slice::<PublicKey>(&encoded).unwrap(),
+            public_key
+        );
+        let encoded = serde_spb::to_canonical_vec(&signature).unwrap();
+        assert_eq!(encoded.len(), 1 + 64);
+        assert_eq!(
+            serde_spb::from_canonical_slice::<Signature>(&encoded).unwrap(),
+            signature
+        );
+    }
+
+    #[test]
+    fn scheme_prefix() {
+        let (public_key, _) = generate_keypair("hello world");
+        let hex = hex::encode(public_key.as_ref());
+        assert_eq!(
+            serde_spb::from_str::<PublicKey>(&format!("\"secp256k1:{}\"", hex)).unwrap(),
+            public_key
+        );
+        assert_eq!(
+            serde_spb::from_str::<PublicKey>(&format!("\"{}\"", hex)).unwrap(),
+            public_key
+        );
+        serde_spb::from_str::<PublicKey>(&format!("\"ed25519:{}\"", hex)).unwrap_err();
+        serde_spb::from_str::<PublicKey>(&format!("\"rsa:{}\"", hex)).unwrap_err();
+    }
+
+    #[test]
+    fn bls12381_aggregate() {
+        let keys = (0..4)
+            .map(|i| generate_keypair_with_scheme([i], SignatureScheme::Bls12381))
+            .collect::<Vec<_>>();
+        let data = Hash256::hash("hello world");
+        let signatures = keys
+            .iter()
+            .map(|(_, private_key)| Signature::sign(data, private_key).unwrap())
+            .collect::<Vec<_>>();
+        signatures[0].verify(data, &keys[0].0).unwrap();
+        signatures[0].verify(data, &keys[1].0).unwrap_err();
+        let public_keys = keys.iter().map(|(x, _)| x.clone()).collect::<Vec<_>>();
+
+        let aggregated = Signature::aggregate(&signatures).unwrap();
+        aggregated.verify_aggregated(data, &public_keys).unwrap();
+        aggregated
+            .verify_aggregated(data, &public_keys[1..])
+            .unwrap_err();
+        aggregated
+            .verify_aggregated(Hash256::hash("hello world2"), &public_keys)
+            .unwrap_err();
+        Signature::aggregate(&signatures[1..])
+            .unwrap()
+            .verify_aggregated(data, &public_keys[1..])
+            .unwrap();
+
+        let (_, secp_private_key) = generate_keypair("hello world");
+        Signature::aggregate(&[Signature::sign(data, &secp_private_key).unwrap()]).unwrap_err();
+        Signature::aggregate(&[]).unwrap_err();
+
+        let encoded = serde_spb::to_string(&aggregated).unwrap();
+        assert!(encoded.starts_with("\"bls12381:"));
+        assert_eq!(
+            serde_spb::from_str::<Signature>(&encoded).unwrap(),
+            aggregated
+        );
+        let encoded = serde_spb::to_canonical_vec(&keys[0].1).unwrap();
+        assert_eq!(
+            serde_spb::from_canonical_slice::<PrivateKey>(&encoded).unwrap(),
+            keys[0].1
+        );
+    }
+
+    #[test]
+    fn bls12381_proof_of_possession() {
+        let (public_key, private_key) =
+            generate_keypair_with_scheme([0], SignatureScheme::Bls12381);
+        let (other_public_key, _) = generate_keypair_with_scheme([1], SignatureScheme::Bls12381);
+        let proof = Signature::prove_possession(&private_key).unwrap();
+        proof.verify_possession(&public_key).unwrap();
+        proof.verify_possession(&other_public_key).unwrap_err();
+        // A signature on the public key in the signing domain is not a proof of possession.
+        let data = Hash256::hash(public_key.as_ref());
+        Signature::sign(data, &private_key)
+            .unwrap()
+            .verify_possession(&public_key)
+            .unwrap_err();
+
+        let (secp_public_key, secp_private_key) = generate_keypair("hello world");
+        Signature::prove_possession(&secp_private_key).unwrap_err();
+        proof.verify_possession(&secp_public_key).unwrap_err();
+    }
+
+    #[test]
+    fn batch_verification() {
+        let schemes = [
+            SignatureScheme::Secp256k1,
+            SignatureScheme::Ed25519,
+            SignatureScheme::Bls12381,
+        ];
+        let entries = (0..30)
+            .map(|i| {
+                let (public_key, private_key) =
+                    generate_keypair_with_scheme(format!("{}", i), schemes[i % 3]);
+                let data = Hash256::hash(format!("message {}", i));
+                let signature = Signature::sign(data, &private_key).unwrap();
+                (data, signature, public_key)
+            })
+            .collect::<Vec<_>>();
+        let items = entries
+            .iter()
+            .map(|(data, signature, public_key)| (*data, signature, public_key))
+            .collect::<Vec<_>>();
+        verify_batch(&items).unwrap();
+        verify_batch(&[]).unwrap();
+
+        // Invalidate a few of each scheme: wrong data, wrong key and a scheme mismatch.
+        let mut items = items;
+        items[2].0 = Hash256::hash("forged");
+        items[7].2 = items[4].2;
+        items[9].0 = Hash256::hash("forged");
+        items[13].2 = items[14].2;
+        items[20].2 = items[23].2;
+        assert_eq!(verify_batch(&items), Err(vec![2, 7, 9, 13, 20]));
+        for (i, (data, signature, public_key)) in items.iter().enumerate() {
+            assert_eq!(
+                signature.verify(*data, public_key).is_ok(),
+                ![2, 7, 9, 13, 20].contains(&i)
+            );
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use crate::serde_spb::HashEncoding;
 use crate::*;
 
 impl ToHash256 for String {
@@ -6,57 +7,66 @@ impl ToHash256 for String {
     }
 }
 
-impl ToHash256 for Member {
-    fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
-    }
-}
-
-impl ToHash256 for BlockHeader {
-    fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
-    }
-}
-
-impl ToHash256 for Diff {
-    fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
-    }
-}
-
-impl ToHash256 for Transaction {
-    fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
-    }
-}
+/// Implements `ToHash256` for the types of a chain, which are hashed with `serde_spb`
+/// in the encoding of the chain's protocol version.
+macro_rules! impl_to_hash256_for_chain_data {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl ToHash256 for $t {
+                fn to_hash256(&self) -> Hash256 {
+                    Hash256::hash(serde_spb::to_hashable_vec(self).unwrap())
+                }
 
-impl ToHash256 for Agenda {
-    fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
-    }
+                fn to_hash256_with(&self, encoding: HashEncoding) -> Hash256 {
+                    Hash256::hash(serde_spb::to_hashable_vec_with(self, encoding).unwrap())
+                }
+            }
+        )*
+    };
 }
 
-impl ToHash256 for AgendaProof {
-    fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
-    }
-}
+impl_to_hash256_for_chain_data!(
+    Member,
+    Diff,
+    Transaction,
+    Agenda,
+    AgendaProof,
+    ExtraAgendaTransaction,
+    (
+        PublicKey,
+        PublicKey,
+        bool,
+        Vec<DelegationCondition>,
+        BlockHeight,
+    ),
+    (PublicKey, BlockHeight),
+    (Hash256, BlockHeight, Timestamp, String),
+    ChatLog,
+    GenesisInfo,
+);
 
-impl ToHash256 for ExtraAgendaTransaction {
+/// A block header is hashed with the encoding of its own version,
+/// so that it has the same hash for everyone regardless of the context.
+impl ToHash256 for BlockHeader {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
+        // A header with an invalid version is never accepted; hash it as in the first protocol version.
+        let encoding = self.hash_encoding().unwrap_or(HashEncoding::Json);
+        Hash256::hash(serde_spb::to_hashable_vec_with(self, encoding).unwrap())
     }
 }
 
-impl ToHash256 for ChatLog {
+/// A consensus vote is always hashed with the canonical encoding,
+/// because it has been introduced together with it.
+impl ToHash256 for ConsensusVote {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
+        Hash256::hash(serde_spb::to_canonical_vec(self).unwrap())
     }
 }
 
-impl ToHash256 for GenesisInfo {
+/// So is a consensus proposal.
+impl ToHash256 for ConsensusProposal {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
+        Hash256::hash(serde_spb::to_canonical_vec(self).unwrap())
     }
 }
 
@@ -71,6 +81,17 @@ impl ToHash256 for Commit {
             Commit::ChatLog(x) => x.to_hash256(),
         }
     }
+
+    fn to_hash256_with(&self, encoding: HashEncoding) -> Hash256 {
+        match self {
+            Commit::Block(x) => x.to_hash256_with(encoding),
+            Commit::Transaction(x) => x.to_hash256_with(encoding),
+            Commit::Agenda(x) => x.to_hash256_with(encoding),
+            Commit::AgendaProof(x) => x.to_hash256_with(encoding),
+            Commit::ExtraAgendaTransaction(x) => x.to_hash256_with(encoding),
+            Commit::ChatLog(x) => x.to_hash256_with(encoding),
+        }
+    }
 }
 
 impl Transaction {
@@ -85,23 +106,46 @@ impl Agenda {
     ///
     /// Don't confuse with the `impl ToHash256 for Agenda`, which
     /// calculates the hash of the agenda itself.
-    pub fn calculate_transactions_hash(transactions: &[Transaction]) -> Hash256 {
+    pub fn calculate_transactions_hash(
+        transactions: &[Transaction],
+        encoding: HashEncoding,
+    ) -> Hash256 {
         let mut hash = Hash256::zero();
         for tx in transactions {
-            hash = hash.aggregate(&tx.to_hash256());
+            hash = hash.aggregate(&tx.to_hash256_with(encoding));
         }
         hash
     }
 }
 
 impl BlockHeader {
+    /// Returns the encoding of the data to be hashed by the protocol version of this header.
+    ///
+    /// The data of the next block (i.e., the commits, the agenda and the consensus votes for it)
+    /// is hashed with the encoding of the last finalized header.
+    pub fn hash_encoding(&self) -> Result<HashEncoding, String> {
+        HashEncoding::for_protocol_version(&self.version)
+    }
+
     /// Calculates `commit_merkle_root`. Note that it doesn't verify the commits.
-    pub fn calculate_commit_merkle_root(commits: &[Commit]) -> Hash256 {
+    pub fn calculate_commit_merkle_root(commits: &[Commit], encoding: HashEncoding) -> Hash256 {
         let merkle_tree = crate::merkle_tree::OneshotMerkleTree::create(
-            commits.iter().map(|x| x.to_hash256()).collect(),
+            commits
+                .iter()
+                .map(|x| x.to_hash256_with(encoding))
+                .collect(),
         );
         merkle_tree.root()
     }
 
-    // note that `repository_merkle_root` is calculated from `simperby-repository`.
+    /// Calculates `repository_merkle_root` from the reserved state and the root of
+    /// the non-reserved files (see `merkle_tree::RepositoryMerkleTree`).
+    pub fn calculate_repository_merkle_root(
+        reserved_state: &ReservedState,
+        non_reserved_state_root: Hash256,
+    ) -> Hash256 {
+        let reserved_state_root =
+            crate::merkle_tree::FileMerkleTree::create(reserved_state.to_files()).root();
+        Hash256::aggregate(&reserved_state_root, &non_reserved_state_root)
+    }
 }
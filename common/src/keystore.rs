@@ -0,0 +1,267 @@
+//! An encrypted on-disk format of a private key.
+//!
+//! The encryption key is derived from the password with scrypt,
+//! and the private key is encrypted with XChaCha20-Poly1305 bound to the public key.
+use crate::{crypto::*, serde_spb};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The version of the keystore format.
+pub const KEYSTORE_VERSION: u32 = 1;
+const SALT_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+const ENCRYPTION_KEY_LENGTH: usize = 32;
+/// The bounds of the KDF parameters read from a keystore file,
+/// so that a crafted file can't exhaust the memory or hang the node.
+const MAX_SCRYPT_LOG_N: u8 = 20;
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+const MAX_SCRYPT_P: u32 = 16;
+
+#[derive(Error, Debug, Clone)]
+pub enum KeystoreError {
+    /// When the password is wrong or the keystore is corrupted.
+    #[error("failed to decrypt the keystore: wrong password or corrupted data")]
+    DecryptionFailed,
+    #[error("invalid keystore: {0}")]
+    InvalidFormat(String),
+    #[error("unsupported keystore version: {0}")]
+    UnsupportedVersion(u32),
+}
+
+type Error = KeystoreError;
+
+/// The parameters of scrypt, the password-based key derivation function.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct ScryptParams {
+    /// The base-2 logarithm of the CPU/memory cost.
+    pub log_n: u8,
+    /// The block size.
+    pub r: u32,
+    /// The parallelization.
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// About 32 MiB of memory and a fraction of a second for each derivation.
+    fn default() -> Self {
+        Self {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl ScryptParams {
+    /// Checks that the parameters are within the bounds that this node accepts.
+    pub fn check_bounds(&self) -> Result<(), Error> {
+        // scrypt uses `128 * r * 2^log_n` bytes of memory.
+        if self.log_n > MAX_SCRYPT_LOG_N
+            || (128 * self.r as u64) << self.log_n > MAX_SCRYPT_MEMORY
+            || self.p > MAX_SCRYPT_P
+        {
+            return Err(Error::InvalidFormat(format!(
+                "kdf parameters out of bounds: log_n {}, r {}, p {}",
+                self.log_n, self.r, self.p
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A private key encrypted with a password.
+///
+/// It is stored as JSON (see `serde_spb::to_string`).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Keystore {
+    pub version: u32,
+    /// The public key of the encrypted private key, which is not secret.
+    pub public_key: PublicKey,
+    pub kdf: ScryptParams,
+    #[serde(with = "hex_bytes")]
+    pub salt: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub nonce: Vec<u8>,
+    /// The encrypted private key (in the canonical encoding) followed by the authentication tag.
+    #[serde(with = "hex_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+impl Keystore {
+    /// Encrypts the private key with the password and the default KDF parameters.
+    pub fn encrypt(private_key: &PrivateKey, password: &str) -> Result<Self, Error> {
+        Self::encrypt_with_params(private_key, password, ScryptParams::default())
+    }
+
+    /// Encrypts the private key with the password, using a fresh salt and nonce.
+    pub fn encrypt_with_params(
+        private_key: &PrivateKey,
+        password: &str,
+        kdf: ScryptParams,
+    ) -> Result<Self, Error> {
+        let mut salt = vec![0; SALT_LENGTH];
+        let mut nonce = vec![0; NONCE_LENGTH];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let public_key = private_key.public_key();
+        let plaintext = serde_spb::to_canonical_vec(private_key)
+            .map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        let cipher = cipher(password, &kdf, &salt)?;
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: public_key.as_ref(),
+                },
+            )
+            .map_err(|_| Error::InvalidFormat("failed to encrypt".to_owned()))?;
+        Ok(Self {
+            version: KEYSTORE_VERSION,
+            public_key,
+            kdf,
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the private key with the password.
+    pub fn decrypt(&self, password: &str) -> Result<PrivateKey, Error> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(Error::UnsupportedVersion(self.version));
+        }
+        if self.nonce.len() != NONCE_LENGTH {
+            return Err(Error::InvalidFormat(format!(
+                "invalid nonce length: {}",
+                self.nonce.len()
+            )));
+        }
+        let cipher = cipher(password, &self.kdf, &self.salt)?;
+        let plaintext = cipher
+            .decrypt(
+                XNonce::from_slice(&self.nonce),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: self.public_key.as_ref(),
+                },
+            )
+            .map_err(|_| Error::DecryptionFailed)?;
+        let private_key: PrivateKey = serde_spb::from_canonical_slice(&plaintext)
+            .map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        if private_key.public_key() != self.public_key {
+            return Err(Error::InvalidFormat(
+                "the private key doesn't match the public key".to_owned(),
+            ));
+        }
+        Ok(private_key)
+    }
+
+    /// Re-encrypts the private key with the new password, keeping the KDF parameters.
+    pub fn change_password(&self, old_password: &str, new_password: &str) -> Result<Self, Error> {
+        let private_key = self.decrypt(old_password)?;
+        Self::encrypt_with_params(&private_key, new_password, self.kdf)
+    }
+}
+
+/// Derives the encryption key from the password.
+fn cipher(password: &str, kdf: &ScryptParams, salt: &[u8]) -> Result<XChaCha20Poly1305, Error> {
+    kdf.check_bounds()?;
+    let params = scrypt::Params::new(kdf.log_n, kdf.r, kdf.p)
+        .map_err(|e| Error::InvalidFormat(format!("invalid kdf parameters: {}", e)))?;
+    let mut key = [0; ENCRYPTION_KEY_LENGTH];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| Error::InvalidFormat(format!("failed to derive the key: {}", e)))?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Serializes bytes as a hex string.
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        hex::decode(s).map_err(|e| serde::de::Error::custom(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters to keep the tests fast.
+    const TEST_PARAMS: ScryptParams = ScryptParams {
+        log_n: 8,
+        r: 8,
+        p: 1,
+    };
+
+    #[test]
+    fn encrypt_decrypt() {
+        for scheme in [
+            SignatureScheme::Secp256k1,
+            SignatureScheme::Ed25519,
+            SignatureScheme::Bls12381,
+        ] {
+            let (public_key, private_key) = generate_keypair_with_scheme("hello world", scheme);
+            let keystore =
+                Keystore::encrypt_with_params(&private_key, "password", TEST_PARAMS).unwrap();
+            assert_eq!(keystore.public_key, public_key);
+            let encoded = serde_spb::to_string(&keystore).unwrap();
+            let keystore: Keystore = serde_spb::from_str(&encoded).unwrap();
+            assert_eq!(keystore.decrypt("password").unwrap(), private_key);
+            assert!(matches!(
+                keystore.decrypt("wrong password"),
+                Err(KeystoreError::DecryptionFailed)
+            ));
+        }
+    }
+
+    #[test]
+    fn change_password() {
+        let (_, private_key) = generate_keypair("hello world");
+        let keystore = Keystore::encrypt_with_params(&private_key, "old", TEST_PARAMS).unwrap();
+        keystore.change_password("wrong", "new").unwrap_err();
+        let changed = keystore.change_password("old", "new").unwrap();
+        assert_ne!(changed.salt, keystore.salt);
+        assert_eq!(changed.decrypt("new").unwrap(), private_key);
+        changed.decrypt("old").unwrap_err();
+    }
+
+    #[test]
+    fn tampered_keystore() {
+        let (_, private_key) = generate_keypair("hello world");
+        let keystore =
+            Keystore::encrypt_with_params(&private_key, "password", TEST_PARAMS).unwrap();
+        let mut tampered = keystore.clone();
+        tampered.ciphertext[0] ^= 1;
+        tampered.decrypt("password").unwrap_err();
+        let mut tampered = keystore.clone();
+        tampered.public_key = generate_keypair("other").0;
+        tampered.decrypt("password").unwrap_err();
+        let mut tampered = keystore.clone();
+        tampered.kdf.log_n = 40;
+        tampered.decrypt("password").unwrap_err();
+        let mut tampered = keystore.clone();
+        tampered.kdf.r = 1 << 20;
+        tampered.decrypt("password").unwrap_err();
+        let mut tampered = keystore.clone();
+        tampered.kdf.p = u32::MAX;
+        tampered.decrypt("password").unwrap_err();
+        let mut tampered = keystore;
+        tampered.version = 2;
+        assert!(matches!(
+            tampered.decrypt("password"),
+            Err(KeystoreError::UnsupportedVersion(2))
+        ));
+    }
+}
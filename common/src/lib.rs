@@ -1,7 +1,9 @@
 pub mod crypto;
 pub mod hash;
+pub mod keystore;
 pub mod light_client;
 pub mod merkle_tree;
+pub mod mnemonic;
 pub mod reserved;
 pub mod serde_spb;
 pub mod types;
@@ -11,4 +13,4 @@ pub use crypto::*;
 pub use reserved::*;
 pub use types::*;
 
-pub const SIMPERBY_CORE_PROTOCOL_VERSION: &str = "0.1.0";
+pub const SIMPERBY_CORE_PROTOCOL_VERSION: &str = "0.2.0";
@@ -1,39 +1,277 @@
 use crate::*;
 use merkle_tree::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{btree_map::Entry, BTreeMap};
+
+/// A source of finalized block headers, such as a full node, for `LightClient::sync`.
+pub trait HeaderSource {
+    /// Returns the header at the given height and its finalization proof.
+    fn get_header(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<(BlockHeader, FinalizationProof), String>;
+}
+
+/// An evidence that a header conflicting with the one verified by the light client
+/// has been finalized at the same height.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ForkEvidence {
+    pub height: BlockHeight,
+    /// The hash of the header that the light client has verified.
+    pub trusted_header_hash: Hash256,
+    pub conflicting_header: BlockHeader,
+    pub conflicting_proof: FinalizationProof,
+}
+
+/// The roots of a verified header that has been pruned out of the retention window of a `LightClient`.
+///
+/// They are the leaves of `LightClient::pruned_roots`, in the order of the height.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistoricalRoots {
+    pub height: BlockHeight,
+    pub repository_root: Hash256,
+    pub commit_root: Hash256,
+}
+
+impl HistoricalRoots {
+    /// Returns the leaf data in `LightClient::pruned_roots`.
+    pub fn to_leaf_data(&self) -> Vec<u8> {
+        serde_spb::to_hashable_vec(self).unwrap()
+    }
+}
+
+impl ToHash256 for HistoricalRoots {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(self.to_leaf_data())
+    }
+}
 
 /// A light client state machine.
+///
+/// It doesn't have to verify every header; see `update_skipping()` and `sync()`.
+/// The roots are kept only for the verified headers.
+///
+/// With a retention window, the roots of the old headers are folded into `pruned_roots`
+/// so that the client takes a bounded space but can still verify the data of the old blocks.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightClient {
-    pub repository_roots: Vec<Hash256>,
-    pub state_roots_height_offset: u64,
-    pub commit_roots: Vec<Hash256>,
-    pub commit_roots_height_offset: u64,
+    pub repository_roots: BTreeMap<BlockHeight, Hash256>,
+    pub commit_roots: BTreeMap<BlockHeight, Hash256>,
+    pub header_hashes: BTreeMap<BlockHeight, Hash256>,
     pub last_header: BlockHeader,
+    /// The number of the recent heights to keep the roots of, or `None` to keep all.
+    pub retention_window: Option<u64>,
+    /// The accumulator of the roots pruned out of the retention window.
+    pub pruned_roots: MerkleMountainRange,
+    /// The period in which the last header can be trusted to skip to a later header.
+    pub trusting_period: Timestamp,
 }
 
 impl LightClient {
     /// Intializes a new light client with the initial header.
     pub fn new(initial_header: BlockHeader) -> Self {
-        Self {
-            repository_roots: vec![initial_header.repository_merkle_root],
-            state_roots_height_offset: initial_header.height,
-            commit_roots: vec![initial_header.commit_merkle_root],
-            commit_roots_height_offset: initial_header.height,
-            last_header: initial_header,
+        let mut light_client = Self {
+            repository_roots: BTreeMap::new(),
+            commit_roots: BTreeMap::new(),
+            header_hashes: BTreeMap::new(),
+            last_header: initial_header.clone(),
+            retention_window: None,
+            pruned_roots: MerkleMountainRange::new(),
+            trusting_period: verify::DEFAULT_TRUSTING_PERIOD,
+        };
+        light_client.trust(initial_header);
+        light_client
+    }
+
+    /// Sets the retention window, pruning the roots out of it immediately.
+    ///
+    /// The roots of the last header are always kept even if the window is zero.
+    pub fn set_retention_window(&mut self, retention_window: Option<u64>) {
+        self.retention_window = retention_window;
+        self.prune();
+    }
+
+    /// Sets the period in which the last header can be trusted to skip to a later header.
+    pub fn set_trusting_period(&mut self, trusting_period: Timestamp) {
+        self.trusting_period = trusting_period;
+    }
+
+    fn trust(&mut self, header: BlockHeader) {
+        self.repository_roots
+            .insert(header.height, header.repository_merkle_root);
+        self.commit_roots
+            .insert(header.height, header.commit_merkle_root);
+        self.header_hashes
+            .insert(header.height, header.to_hash256());
+        self.last_header = header;
+        self.prune();
+    }
+
+    fn prune(&mut self) {
+        let retention_window = match self.retention_window {
+            Some(x) => x,
+            None => return,
+        };
+        while let Some((&height, _)) = self.commit_roots.iter().next() {
+            if height == self.last_header.height
+                || height + retention_window > self.last_header.height
+            {
+                break;
+            }
+            let roots = HistoricalRoots {
+                height,
+                repository_root: self.repository_roots.remove(&height).unwrap(),
+                commit_root: self.commit_roots.remove(&height).unwrap(),
+            };
+            self.header_hashes.remove(&height);
+            self.pruned_roots.append(roots.to_hash256());
+        }
+    }
+
+    /// Encodes the state of the client into a compact checkpoint, from which it can resume.
+    pub fn to_checkpoint(&self) -> Vec<u8> {
+        serde_spb::to_canonical_vec(self).unwrap()
+    }
+
+    /// Restores the client from the checkpoint created by `to_checkpoint()`.
+    pub fn from_checkpoint(checkpoint: &[u8]) -> Result<Self, String> {
+        let light_client: Self =
+            serde_spb::from_canonical_slice(checkpoint).map_err(|e| e.to_string())?;
+        let height = light_client.last_header.height;
+        if light_client.header_hashes.get(&height) != Some(&light_client.last_header.to_hash256())
+            || light_client
+                .commit_roots
+                .keys()
+                .ne(light_client.header_hashes.keys())
+            || light_client
+                .repository_roots
+                .keys()
+                .ne(light_client.header_hashes.keys())
+            || light_client.header_hashes.keys().next_back() != Some(&height)
+        {
+            return Err("invalid checkpoint: inconsistent headers".to_string());
         }
+        if light_client.pruned_roots.peaks.len()
+            != light_client.pruned_roots.leaf_count.count_ones() as usize
+        {
+            return Err("invalid checkpoint: inconsistent pruned roots".to_string());
+        }
+        Ok(light_client)
     }
 
     /// Updates the header by providing the next block and the proof of it.
     pub fn update(&mut self, header: BlockHeader, proof: FinalizationProof) -> Result<(), String> {
         verify::verify_header_to_header(&self.last_header, &header).map_err(|e| e.to_string())?;
         verify::verify_finalization_proof(&header, &proof).map_err(|e| e.to_string())?;
-        self.repository_roots.push(header.repository_merkle_root);
-        self.commit_roots.push(header.commit_merkle_root);
-        self.last_header = header;
+        self.trust(header);
         Ok(())
     }
 
+    /// Updates the header by providing a later (not necessarily the next) block and the proof of it.
+    ///
+    /// It fails if the validators of the last header don't have enough voting power in the proof
+    /// (see `verify::verify_skipping_header()`); use `sync()` to verify the intermediate headers then.
+    /// It fails too if the last header is older than the trusting period.
+    pub fn update_skipping(
+        &mut self,
+        header: BlockHeader,
+        proof: FinalizationProof,
+    ) -> Result<(), String> {
+        self.verify_skipping_header(&header, &proof)
+            .map_err(|e| e.to_string())?;
+        self.trust(header);
+        Ok(())
+    }
+
+    /// Updates the header to the given height, fetching the headers from the source.
+    ///
+    /// It tries to skip directly to the target header, and bisects the range
+    /// whenever the last header doesn't have enough trust on the header to skip to.
+    pub fn sync(
+        &mut self,
+        source: &mut impl HeaderSource,
+        target_height: BlockHeight,
+    ) -> Result<(), String> {
+        if target_height <= self.last_header.height {
+            return Err(format!(
+                "invalid target height: expected larger than {}, got {}",
+                self.last_header.height, target_height
+            ));
+        }
+        let mut fetched: BTreeMap<BlockHeight, (BlockHeader, FinalizationProof)> = BTreeMap::new();
+        let mut pending = vec![target_height];
+        while let Some(&height) = pending.last() {
+            let (header, proof) = match fetched.entry(height) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    let (header, proof) = source.get_header(height)?;
+                    if header.height != height {
+                        return Err(format!(
+                            "invalid header from the source: expected height {}, got {}",
+                            height, header.height
+                        ));
+                    }
+                    entry.insert((header, proof)).clone()
+                }
+            };
+            if height == self.last_header.height + 1 {
+                self.update(header, proof)?;
+            } else {
+                match self.verify_skipping_header(&header, &proof) {
+                    Ok(()) => self.trust(header),
+                    Err(verify::Error::InsufficientTrust(_)) => {
+                        pending
+                            .push(self.last_header.height + (height - self.last_header.height) / 2);
+                        continue;
+                    }
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+            pending.pop();
+        }
+        Ok(())
+    }
+
+    fn verify_skipping_header(
+        &self,
+        header: &BlockHeader,
+        proof: &FinalizationProof,
+    ) -> Result<(), verify::Error> {
+        verify::verify_skipping_header(
+            &self.last_header,
+            header,
+            proof,
+            self.trusting_period,
+            verify::local_timestamp(),
+        )
+    }
+
+    /// Checks whether the given finalized header conflicts with the verified one at the same height.
+    ///
+    /// The given header must be signed by the validators of the last header that have more than
+    /// 1/3 of its voting power, since its own validator set may have been made up by the attacker.
+    ///
+    /// Returns `None` if there is no verified header at the height, the headers are the same,
+    /// or the given header is not finalized with enough trust.
+    pub fn detect_fork(
+        &self,
+        header: &BlockHeader,
+        proof: &FinalizationProof,
+    ) -> Option<ForkEvidence> {
+        let trusted_header_hash = *self.header_hashes.get(&header.height)?;
+        if trusted_header_hash == header.to_hash256()
+            || verify::verify_trusted_finalization_proof(&self.last_header, header, proof).is_err()
+        {
+            return None;
+        }
+        Some(ForkEvidence {
+            height: header.height,
+            trusted_header_hash,
+            conflicting_header: header.clone(),
+            conflicting_proof: proof.clone(),
+        })
+    }
+
     /// Verifies the given data with its proof.
     pub fn verify_commitment(
         &self,
@@ -41,16 +279,88 @@ impl LightClient {
         block_height: u64,
         proof: MerkleProof,
     ) -> bool {
-        if block_height < self.commit_roots_height_offset
-            || block_height >= self.commit_roots_height_offset + self.commit_roots.len() as u64
-        {
-            return false;
+        match self.commit_roots.get(&block_height) {
+            Some(root) => proof.verify(*root, &message).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Verifies the given data, all committed in the same block, with their multi-proof.
+    pub fn verify_commitments(
+        &self,
+        messages: &[Vec<u8>],
+        block_height: u64,
+        proof: MerkleMultiProof,
+    ) -> bool {
+        match self.commit_roots.get(&block_height) {
+            Some(root) => proof.verify(*root, messages).is_ok(),
+            None => false,
         }
-        proof
-            .verify(
-                self.commit_roots[(block_height - self.commit_roots_height_offset) as usize],
-                &message,
-            )
+    }
+
+    /// Verifies the given data with its proof, in a block of which the roots have been pruned.
+    ///
+    /// `roots_proof` is the proof of the roots in `pruned_roots`,
+    /// created by `MerkleMountainRange::create_proof()`.
+    pub fn verify_historical_commitment(
+        &self,
+        message: Vec<u8>,
+        roots: &HistoricalRoots,
+        roots_proof: &MerkleMountainRangeProof,
+        proof: MerkleProof,
+    ) -> bool {
+        self.pruned_roots
+            .verify(&roots.to_leaf_data(), roots_proof)
             .is_ok()
+            && proof.verify(roots.commit_root, &message).is_ok()
+    }
+
+    /// Verifies that the file at `path` had the given content at a height of which the roots have been pruned.
+    ///
+    /// See `verify_historical_commitment()` for `roots_proof`.
+    pub fn verify_historical_state(
+        &self,
+        path: &str,
+        content: &[u8],
+        roots: &HistoricalRoots,
+        roots_proof: &MerkleMountainRangeProof,
+        proof: MerkleProof,
+    ) -> bool {
+        self.pruned_roots
+            .verify(&roots.to_leaf_data(), roots_proof)
+            .is_ok()
+            && RepositoryMerkleTree::verify_membership(roots.repository_root, path, content, &proof)
+                .is_ok()
+    }
+
+    /// Verifies that the file at `path` had the given content at the given height,
+    /// with the proof created by `merkle_tree::RepositoryMerkleTree`.
+    pub fn verify_state(
+        &self,
+        path: &str,
+        content: &[u8],
+        block_height: u64,
+        proof: MerkleProof,
+    ) -> bool {
+        match self.repository_roots.get(&block_height) {
+            Some(root) => {
+                RepositoryMerkleTree::verify_membership(*root, path, content, &proof).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    /// Verifies that there was no file at `path` at the given height,
+    /// with the proof created by `merkle_tree::RepositoryMerkleTree`.
+    pub fn verify_state_absence(
+        &self,
+        path: &str,
+        block_height: u64,
+        proof: NonMembershipProof,
+    ) -> bool {
+        match self.repository_roots.get(&block_height) {
+            Some(root) => RepositoryMerkleTree::verify_non_membership(*root, path, &proof).is_ok(),
+            None => false,
+        }
     }
 }
@@ -1,5 +1,6 @@
 use crate::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use thiserror::Error;
 
 /// A Merkle tree that is created once but never modified.
@@ -60,6 +61,44 @@ impl OneshotMerkleTree {
         Some(merkle_proof)
     }
 
+    /// Creates a Merkle proof for multiple data in the tree at once.
+    ///
+    /// Returns `None` if any of the data is not in the tree or is given more than once.
+    ///
+    /// The siblings shared by the given data are included only once,
+    /// and those that can be calculated from the given data are omitted.
+    pub fn create_merkle_multi_proof(&self, keys: &[Hash256]) -> Option<MerkleMultiProof> {
+        let mut leaf_indices = Vec::new();
+        for key in keys {
+            let index = self.hash_list.iter().position(|x| x == key)? as u64;
+            if leaf_indices.contains(&index) {
+                return None;
+            }
+            leaf_indices.push(index);
+        }
+        let mut siblings = Vec::new();
+        let mut merkle_tree: Vec<Vec<Hash256>> = Self::merkle_tree(&self.hash_list);
+        // Pop because the root is never included in the Merkle proof
+        merkle_tree.pop();
+        let mut known: BTreeSet<usize> = leaf_indices.iter().map(|x| *x as usize).collect();
+        for level in merkle_tree {
+            let mut upper_known = BTreeSet::new();
+            for &index in &known {
+                let sibling = index ^ 1;
+                if sibling < level.len() && !known.contains(&sibling) {
+                    siblings.push(level[sibling]);
+                }
+                upper_known.insert(index / 2);
+            }
+            known = upper_known;
+        }
+        Some(MerkleMultiProof {
+            leaf_count: self.hash_list.len() as u64,
+            leaf_indices,
+            siblings,
+        })
+    }
+
     /// Creates a merkle tree from the given hash list.
     ///
     /// Merkle tree is returned in the form of Vec of Vec of Hash256.
@@ -121,6 +160,672 @@ impl OneshotMerkleTree {
     }
 }
 
+/// Returns the leaf data of a `SparseMerkleTree`, which is `0x00 || key || value`.
+///
+/// The leading byte makes it distinguishable from the data of an internal node.
+pub fn sparse_leaf_data(key: &Hash256, value: &Hash256) -> Vec<u8> {
+    [&[0], key.as_ref(), value.as_ref()].concat()
+}
+
+/// Returns the bit of the key at the given depth, where `true` is for the right child.
+fn key_bit(key: &Hash256, depth: usize) -> bool {
+    (key.as_ref()[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+enum SparseMerkleNode {
+    Empty,
+    /// A subtree that has only one leaf is represented as the leaf itself.
+    Leaf {
+        key: Hash256,
+        value: Hash256,
+    },
+    /// A subtree that has more than one leaf, with its hash cached.
+    Internal {
+        hash: Hash256,
+        left: Box<SparseMerkleNode>,
+        right: Box<SparseMerkleNode>,
+    },
+}
+
+impl SparseMerkleNode {
+    fn hash(&self) -> Hash256 {
+        match self {
+            SparseMerkleNode::Empty => SparseMerkleTree::EMPTY_HASH,
+            SparseMerkleNode::Leaf { key, value } => Hash256::hash(sparse_leaf_data(key, value)),
+            SparseMerkleNode::Internal { hash, .. } => *hash,
+        }
+    }
+
+    fn internal(left: Self, right: Self) -> Self {
+        SparseMerkleNode::Internal {
+            hash: Hash256::aggregate(&left.hash(), &right.hash()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Creates the smallest subtree at `depth` that contains the two leaves with different keys.
+    fn split(a: Self, a_key: &Hash256, b: Self, b_key: &Hash256, depth: usize) -> Self {
+        match (key_bit(a_key, depth), key_bit(b_key, depth)) {
+            (false, true) => Self::internal(a, b),
+            (true, false) => Self::internal(b, a),
+            (false, false) => {
+                Self::internal(Self::split(a, a_key, b, b_key, depth + 1), Self::Empty)
+            }
+            (true, true) => Self::internal(Self::Empty, Self::split(a, a_key, b, b_key, depth + 1)),
+        }
+    }
+
+    fn insert(self, key: Hash256, value: Hash256, depth: usize) -> Self {
+        match self {
+            SparseMerkleNode::Empty => SparseMerkleNode::Leaf { key, value },
+            SparseMerkleNode::Leaf { key: k, .. } if k == key => {
+                SparseMerkleNode::Leaf { key, value }
+            }
+            SparseMerkleNode::Leaf { key: k, value: v } => Self::split(
+                SparseMerkleNode::Leaf { key: k, value: v },
+                &k,
+                SparseMerkleNode::Leaf { key, value },
+                &key,
+                depth,
+            ),
+            SparseMerkleNode::Internal { left, right, .. } => {
+                if key_bit(&key, depth) {
+                    Self::internal(*left, right.insert(key, value, depth + 1))
+                } else {
+                    Self::internal(left.insert(key, value, depth + 1), *right)
+                }
+            }
+        }
+    }
+
+    fn remove(self, key: &Hash256, depth: usize) -> Self {
+        match self {
+            SparseMerkleNode::Leaf { key: k, .. } if &k == key => SparseMerkleNode::Empty,
+            SparseMerkleNode::Internal { left, right, .. } => {
+                let (left, right) = if key_bit(key, depth) {
+                    (*left, right.remove(key, depth + 1))
+                } else {
+                    (left.remove(key, depth + 1), *right)
+                };
+                // Collapse the subtree if it has only one leaf left.
+                match (left, right) {
+                    (SparseMerkleNode::Empty, SparseMerkleNode::Empty) => SparseMerkleNode::Empty,
+                    (leaf @ SparseMerkleNode::Leaf { .. }, SparseMerkleNode::Empty)
+                    | (SparseMerkleNode::Empty, leaf @ SparseMerkleNode::Leaf { .. }) => leaf,
+                    (left, right) => Self::internal(left, right),
+                }
+            }
+            node => node,
+        }
+    }
+}
+
+/// A Merkle tree keyed by `Hash256`, which can be updated incrementally.
+///
+/// A leaf is placed on the path given by the bits of its key (`0` for left and `1` for right),
+/// but a subtree with only one leaf is shortened to the leaf itself and an empty subtree
+/// is `Self::EMPTY_HASH`. This keeps the proofs short while being able to prove the absence of a key.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SparseMerkleTree {
+    root: SparseMerkleNode,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize, Clone)]
+pub enum SparseMerkleTreeError {
+    #[error("key already exists: {0}")]
+    KeyAlreadyExists(Hash256),
+    #[error("key not found: {0}")]
+    KeyNotFound(Hash256),
+}
+
+impl Default for SparseMerkleTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SparseMerkleTree {
+    pub const EMPTY_HASH: Hash256 = Hash256::zero();
+
+    /// Creates a new empty SparseMerkleTree.
+    pub fn new() -> Self {
+        SparseMerkleTree {
+            root: SparseMerkleNode::Empty,
+        }
+    }
+
+    /// Returns the value of the given key.
+    pub fn get(&self, key: &Hash256) -> Option<Hash256> {
+        match self.locate(key).1 {
+            SparseMerkleNode::Leaf { key: k, value } if k == key => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Inserts a new key. Fails if the key already exists.
+    pub fn insert(&mut self, key: Hash256, value: Hash256) -> Result<(), SparseMerkleTreeError> {
+        if self.get(&key).is_some() {
+            return Err(SparseMerkleTreeError::KeyAlreadyExists(key));
+        }
+        let root = std::mem::replace(&mut self.root, SparseMerkleNode::Empty);
+        self.root = root.insert(key, value, 0);
+        Ok(())
+    }
+
+    /// Updates the value of an existing key. Fails if the key doesn't exist.
+    pub fn update(&mut self, key: Hash256, value: Hash256) -> Result<(), SparseMerkleTreeError> {
+        if self.get(&key).is_none() {
+            return Err(SparseMerkleTreeError::KeyNotFound(key));
+        }
+        let root = std::mem::replace(&mut self.root, SparseMerkleNode::Empty);
+        self.root = root.insert(key, value, 0);
+        Ok(())
+    }
+
+    /// Deletes an existing key. Fails if the key doesn't exist.
+    pub fn delete(&mut self, key: &Hash256) -> Result<(), SparseMerkleTreeError> {
+        if self.get(key).is_none() {
+            return Err(SparseMerkleTreeError::KeyNotFound(*key));
+        }
+        let root = std::mem::replace(&mut self.root, SparseMerkleNode::Empty);
+        self.root = root.remove(key, 0);
+        Ok(())
+    }
+
+    /// Returns the root of the tree.
+    ///
+    /// If the tree is empty, this returns a `Self::EMPTY_HASH`.
+    pub fn root(&self) -> Hash256 {
+        self.root.hash()
+    }
+
+    /// Returns the siblings on the path of the key (from the bottom) and the node where the path ends.
+    fn locate(&self, key: &Hash256) -> (MerkleProof, &SparseMerkleNode) {
+        let mut entries = Vec::new();
+        let mut node = &self.root;
+        let mut depth = 0;
+        while let SparseMerkleNode::Internal { left, right, .. } = node {
+            if key_bit(key, depth) {
+                entries.push(MerkleProofEntry::LeftChild(left.hash()));
+                node = right;
+            } else {
+                entries.push(MerkleProofEntry::RightChild(right.hash()));
+                node = left;
+            }
+            depth += 1;
+        }
+        entries.reverse();
+        (MerkleProof { proof: entries }, node)
+    }
+
+    /// Creates a Merkle proof for the given key, which can be verified with `sparse_leaf_data`.
+    ///
+    /// Returns `None` if the key is not in the tree.
+    pub fn create_membership_proof(&self, key: &Hash256) -> Option<MerkleProof> {
+        match self.locate(key) {
+            (proof, SparseMerkleNode::Leaf { key: k, .. }) if k == key => Some(proof),
+            _ => None,
+        }
+    }
+
+    /// Creates a proof that the given key is not in the tree.
+    ///
+    /// Returns `None` if the key is in the tree.
+    pub fn create_non_membership_proof(&self, key: &Hash256) -> Option<NonMembershipProof> {
+        match self.locate(key) {
+            (proof, SparseMerkleNode::Empty) => Some(NonMembershipProof { proof, leaf: None }),
+            (proof, SparseMerkleNode::Leaf { key: k, value }) if k != key => {
+                Some(NonMembershipProof {
+                    proof,
+                    leaf: Some((*k, *value)),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Verifies whether the given key has the given value in the tree of the root.
+    ///
+    /// Unlike `MerkleProof::verify`, it also checks that the proof follows the path of the key.
+    pub fn verify_membership(
+        root: Hash256,
+        key: &Hash256,
+        value: &Hash256,
+        proof: &MerkleProof,
+    ) -> Result<(), MerkleProofError> {
+        check_sparse_path(key, proof)?;
+        proof.verify(root, &sparse_leaf_data(key, value))
+    }
+}
+
+/// Checks whether the proof of a `SparseMerkleTree` follows the path of the key.
+fn check_sparse_path(key: &Hash256, proof: &MerkleProof) -> Result<(), MerkleProofError> {
+    let depth = proof.proof.len();
+    if depth > 256 {
+        return Err(MerkleProofError::MalformedProof(format!(
+            "too deep path: {}",
+            depth
+        )));
+    }
+    for (i, entry) in proof.proof.iter().enumerate() {
+        let is_right = match entry {
+            MerkleProofEntry::LeftChild(_) => true,
+            MerkleProofEntry::RightChild(_) => false,
+            MerkleProofEntry::OnlyChild => {
+                return Err(MerkleProofError::MalformedProof(
+                    "unexpected only child in a sparse merkle proof".to_string(),
+                ))
+            }
+        };
+        if is_right != key_bit(key, depth - 1 - i) {
+            return Err(MerkleProofError::MalformedProof(format!(
+                "the proof doesn't follow the path of the key at depth {}",
+                depth - 1 - i
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A proof that a key is not in a `SparseMerkleTree`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct NonMembershipProof {
+    /// The siblings on the path of the key, from the node where the path ends.
+    pub proof: MerkleProof,
+    /// The `(key, value)` of the other leaf where the path ends,
+    /// or `None` if the path ends in an empty subtree.
+    pub leaf: Option<(Hash256, Hash256)>,
+}
+
+impl NonMembershipProof {
+    /// Calculates the root of the tree implied by this proof, in which the key is absent.
+    pub fn calculate_root(&self, key: &Hash256) -> Result<Hash256, MerkleProofError> {
+        check_sparse_path(key, &self.proof)?;
+        let depth = self.proof.proof.len();
+        let mut calculated_root = match &self.leaf {
+            None => SparseMerkleTree::EMPTY_HASH,
+            Some((leaf_key, leaf_value)) => {
+                if leaf_key == key {
+                    return Err(MerkleProofError::MalformedProof(
+                        "the leaf has the key".to_string(),
+                    ));
+                }
+                if (0..depth).any(|d| key_bit(leaf_key, d) != key_bit(key, d)) {
+                    return Err(MerkleProofError::MalformedProof(
+                        "the leaf is not on the path of the key".to_string(),
+                    ));
+                }
+                Hash256::hash(sparse_leaf_data(leaf_key, leaf_value))
+            }
+        };
+        for node in &self.proof.proof {
+            calculated_root = match node {
+                MerkleProofEntry::LeftChild(pair_hash) => {
+                    Hash256::aggregate(pair_hash, &calculated_root)
+                }
+                MerkleProofEntry::RightChild(pair_hash) => {
+                    Hash256::aggregate(&calculated_root, pair_hash)
+                }
+                MerkleProofEntry::OnlyChild => unreachable!("checked by check_sparse_path"),
+            };
+        }
+        Ok(calculated_root)
+    }
+
+    /// Verifies whether the given key is absent in the tree of the root.
+    pub fn verify(&self, root: Hash256, key: &Hash256) -> Result<(), MerkleProofError> {
+        let calculated_root = self.calculate_root(key)?;
+        if root == calculated_root {
+            Ok(())
+        } else {
+            Err(MerkleProofError::UnmatchedRoot(
+                root.to_string(),
+                calculated_root.to_string(),
+            ))
+        }
+    }
+}
+
+/// Returns the leaf data of a file in a `FileMerkleTree`.
+///
+/// It is the leaf data of a `SparseMerkleTree` where the key is the hash of the path
+/// and the value is the hash of the content.
+pub fn file_leaf_data(path: &str, content: &[u8]) -> Vec<u8> {
+    sparse_leaf_data(&Hash256::hash(path), &Hash256::hash(content))
+}
+
+/// A Merkle tree over a set of files, keyed by the hash of the path.
+pub struct FileMerkleTree {
+    tree: SparseMerkleTree,
+}
+
+impl FileMerkleTree {
+    /// Creates a new FileMerkleTree from the given `(path, content)` list.
+    pub fn create(files: Vec<(String, Vec<u8>)>) -> Self {
+        let mut tree = SparseMerkleTree::new();
+        for (path, content) in files {
+            let key = Hash256::hash(path);
+            let value = Hash256::hash(content);
+            if tree.insert(key, value).is_err() {
+                tree.update(key, value).unwrap();
+            }
+        }
+        FileMerkleTree { tree }
+    }
+
+    /// Creates a Merkle proof for the given file.
+    ///
+    /// Returns `None` if the file is not in the tree with the given content.
+    pub fn create_merkle_proof(&self, path: &str, content: &[u8]) -> Option<MerkleProof> {
+        let key = Hash256::hash(path);
+        if self.tree.get(&key)? != Hash256::hash(content) {
+            return None;
+        }
+        self.tree.create_membership_proof(&key)
+    }
+
+    /// Creates a proof that there is no file at the given path.
+    ///
+    /// Returns `None` if the file exists.
+    pub fn create_non_membership_proof(&self, path: &str) -> Option<NonMembershipProof> {
+        self.tree.create_non_membership_proof(&Hash256::hash(path))
+    }
+
+    /// Returns the root of the tree.
+    pub fn root(&self) -> Hash256 {
+        self.tree.root()
+    }
+}
+
+/// A Merkle tree over the working tree of the repository, of which the root is
+/// `BlockHeader::repository_merkle_root`.
+///
+/// The root is the aggregation of the two `FileMerkleTree`s;
+/// one for the reserved directory (left) and the other for the non-reserved files (right).
+pub struct RepositoryMerkleTree {
+    reserved: FileMerkleTree,
+    non_reserved: FileMerkleTree,
+}
+
+fn is_reserved_path(path: &str) -> bool {
+    path.starts_with(&format!("{}/", RESERVED_DIRECTORY))
+}
+
+impl RepositoryMerkleTree {
+    /// Creates a new RepositoryMerkleTree from the files of the working tree,
+    /// including the ones in the reserved directory.
+    pub fn create(files: Vec<(String, Vec<u8>)>) -> Self {
+        let (reserved_files, non_reserved_files) = files
+            .into_iter()
+            .partition(|(path, _)| is_reserved_path(path));
+        RepositoryMerkleTree {
+            reserved: FileMerkleTree::create(reserved_files),
+            non_reserved: FileMerkleTree::create(non_reserved_files),
+        }
+    }
+
+    /// Returns the sibling subtree of the given path, as the last entry of a proof.
+    fn top_entry(&self, path: &str) -> MerkleProofEntry {
+        if is_reserved_path(path) {
+            MerkleProofEntry::RightChild(self.non_reserved.root())
+        } else {
+            MerkleProofEntry::LeftChild(self.reserved.root())
+        }
+    }
+
+    fn subtree(&self, path: &str) -> &FileMerkleTree {
+        if is_reserved_path(path) {
+            &self.reserved
+        } else {
+            &self.non_reserved
+        }
+    }
+
+    /// Creates a Merkle proof for the given file, which can be verified with `Self::verify_membership`.
+    ///
+    /// Returns `None` if the file is not in the tree with the given content.
+    pub fn create_merkle_proof(&self, path: &str, content: &[u8]) -> Option<MerkleProof> {
+        let mut proof = self.subtree(path).create_merkle_proof(path, content)?;
+        proof.proof.push(self.top_entry(path));
+        Some(proof)
+    }
+
+    /// Creates a proof that there is no file at the given path,
+    /// which can be verified with `Self::verify_non_membership`.
+    ///
+    /// Returns `None` if the file exists.
+    pub fn create_non_membership_proof(&self, path: &str) -> Option<NonMembershipProof> {
+        let mut proof = self.subtree(path).create_non_membership_proof(path)?;
+        proof.proof.proof.push(self.top_entry(path));
+        Some(proof)
+    }
+
+    /// Verifies whether the file at the given path has the given content in the tree of the root.
+    pub fn verify_membership(
+        root: Hash256,
+        path: &str,
+        content: &[u8],
+        proof: &MerkleProof,
+    ) -> Result<(), MerkleProofError> {
+        let (top_entry, subtree_entries) = proof
+            .proof
+            .split_last()
+            .ok_or_else(|| MerkleProofError::MalformedProof("empty proof".to_string()))?;
+        match (is_reserved_path(path), top_entry) {
+            (true, MerkleProofEntry::RightChild(_)) | (false, MerkleProofEntry::LeftChild(_)) => (),
+            _ => {
+                return Err(MerkleProofError::MalformedProof(
+                    "invalid subtree of the repository".to_string(),
+                ))
+            }
+        }
+        check_sparse_path(
+            &Hash256::hash(path),
+            &MerkleProof {
+                proof: subtree_entries.to_vec(),
+            },
+        )?;
+        proof.verify(root, &file_leaf_data(path, content))
+    }
+
+    /// Verifies whether there is no file at the given path in the tree of the root.
+    pub fn verify_non_membership(
+        root: Hash256,
+        path: &str,
+        proof: &NonMembershipProof,
+    ) -> Result<(), MerkleProofError> {
+        let mut subtree_proof = proof.clone();
+        let top_entry = subtree_proof
+            .proof
+            .proof
+            .pop()
+            .ok_or_else(|| MerkleProofError::MalformedProof("empty proof".to_string()))?;
+        let subtree_root = subtree_proof.calculate_root(&Hash256::hash(path))?;
+        let calculated_root = match (is_reserved_path(path), top_entry) {
+            (true, MerkleProofEntry::RightChild(pair_hash)) => {
+                Hash256::aggregate(&subtree_root, &pair_hash)
+            }
+            (false, MerkleProofEntry::LeftChild(pair_hash)) => {
+                Hash256::aggregate(&pair_hash, &subtree_root)
+            }
+            _ => {
+                return Err(MerkleProofError::MalformedProof(
+                    "invalid subtree of the repository".to_string(),
+                ))
+            }
+        };
+        if root == calculated_root {
+            Ok(())
+        } else {
+            Err(MerkleProofError::UnmatchedRoot(
+                root.to_string(),
+                calculated_root.to_string(),
+            ))
+        }
+    }
+
+    /// Returns the root of the files in the reserved directory.
+    pub fn reserved_root(&self) -> Hash256 {
+        self.reserved.root()
+    }
+
+    /// Returns the root of the non-reserved files.
+    pub fn non_reserved_root(&self) -> Hash256 {
+        self.non_reserved.root()
+    }
+
+    /// Returns the root of the tree.
+    pub fn root(&self) -> Hash256 {
+        Hash256::aggregate(&self.reserved.root(), &self.non_reserved.root())
+    }
+}
+
+/// An append-only accumulator that keeps only the peaks of a Merkle mountain range.
+///
+/// The leaves are grouped into perfect binary trees of decreasing sizes (the peaks),
+/// which are given by the bits of `leaf_count`. Appending a leaf merges the peaks of the same size,
+/// so it takes `O(log n)` space to keep while every leaf stays provable.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct MerkleMountainRange {
+    pub leaf_count: u64,
+    /// The roots of the perfect binary trees, from the largest (leftmost) one.
+    pub peaks: Vec<Hash256>,
+}
+
+/// A Merkle proof of a leaf in a `MerkleMountainRange`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct MerkleMountainRangeProof {
+    pub leaf_index: u64,
+    /// The path from the leaf to its peak.
+    pub proof: MerkleProof,
+}
+
+impl MerkleMountainRange {
+    /// Creates a new empty MerkleMountainRange.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the hash of a leaf.
+    pub fn append(&mut self, leaf: Hash256) {
+        let mut node = leaf;
+        // Each trailing one bit of the leaf count is a peak of the same size to merge.
+        let mut count = self.leaf_count;
+        while count & 1 == 1 {
+            let left = self
+                .peaks
+                .pop()
+                .expect("the number of peaks is the number of one bits");
+            node = Hash256::aggregate(&left, &node);
+            count >>= 1;
+        }
+        self.peaks.push(node);
+        self.leaf_count += 1;
+    }
+
+    /// Returns the root which commits to all the leaves, bagging the peaks from the right.
+    ///
+    /// If the range is empty, this returns a `OneshotMerkleTree::EMPTY_HASH`.
+    pub fn root(&self) -> Hash256 {
+        let mut peaks = self.peaks.iter().rev();
+        match peaks.next() {
+            Some(last) => peaks.fold(*last, |acc, peak| Hash256::aggregate(peak, &acc)),
+            None => OneshotMerkleTree::EMPTY_HASH,
+        }
+    }
+
+    /// Returns the index of the peak containing the leaf, the index of its first leaf and its height.
+    fn locate_peak(leaf_count: u64, leaf_index: u64) -> Option<(usize, u64, u32)> {
+        let mut offset = 0;
+        let mut peak_index = 0;
+        for height in (0..64).rev() {
+            if leaf_count & (1 << height) == 0 {
+                continue;
+            }
+            if leaf_index < offset + (1 << height) {
+                return Some((peak_index, offset, height));
+            }
+            offset += 1 << height;
+            peak_index += 1;
+        }
+        None
+    }
+
+    /// Creates a proof for the leaf at the given index, given all the leaves appended so far.
+    ///
+    /// This is for the provers who keep all the leaves, unlike the `MerkleMountainRange` itself.
+    pub fn create_proof(leaves: &[Hash256], leaf_index: u64) -> Option<MerkleMountainRangeProof> {
+        let (_, offset, height) = Self::locate_peak(leaves.len() as u64, leaf_index)?;
+        let mut level = leaves[offset as usize..(offset as usize + (1 << height))].to_vec();
+        let mut index = (leaf_index - offset) as usize;
+        let mut proof = Vec::new();
+        while level.len() > 1 {
+            if index % 2 == 1 {
+                proof.push(MerkleProofEntry::LeftChild(level[index - 1]));
+            } else {
+                proof.push(MerkleProofEntry::RightChild(level[index + 1]));
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| Hash256::aggregate(&pair[0], &pair[1]))
+                .collect();
+            index /= 2;
+        }
+        Some(MerkleMountainRangeProof {
+            leaf_index,
+            proof: MerkleProof { proof },
+        })
+    }
+
+    /// Verifies whether the given data is a leaf of this range.
+    ///
+    /// The hash of the leaf must be `Hash256::hash(data)`.
+    pub fn verify(
+        &self,
+        data: &[u8],
+        proof: &MerkleMountainRangeProof,
+    ) -> Result<(), MerkleProofError> {
+        let (peak_index, offset, height) = Self::locate_peak(self.leaf_count, proof.leaf_index)
+            .ok_or_else(|| {
+                MerkleProofError::MalformedProof(format!(
+                    "leaf index {} out of {}",
+                    proof.leaf_index, self.leaf_count
+                ))
+            })?;
+        if proof.proof.proof.len() != height as usize {
+            return Err(MerkleProofError::MalformedProof(format!(
+                "expected a path of length {} but found {}",
+                height,
+                proof.proof.proof.len()
+            )));
+        }
+        let index = proof.leaf_index - offset;
+        for (depth, entry) in proof.proof.proof.iter().enumerate() {
+            let is_right = match entry {
+                MerkleProofEntry::LeftChild(_) => true,
+                MerkleProofEntry::RightChild(_) => false,
+                MerkleProofEntry::OnlyChild => {
+                    return Err(MerkleProofError::MalformedProof(
+                        "unexpected only child in a merkle mountain range proof".to_string(),
+                    ))
+                }
+            };
+            if is_right != ((index >> depth) & 1 == 1) {
+                return Err(MerkleProofError::MalformedProof(format!(
+                    "the proof doesn't follow the path of the leaf at depth {}",
+                    depth
+                )));
+            }
+        }
+        let peak = self.peaks.get(peak_index).ok_or_else(|| {
+            MerkleProofError::MalformedProof("the range has inconsistent peaks".to_string())
+        })?;
+        proof.proof.verify(*peak, data)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct MerkleProof {
     pub proof: Vec<MerkleProofEntry>,
@@ -169,6 +874,97 @@ impl MerkleProof {
     }
 }
 
+/// A Merkle proof of multiple data in a `OneshotMerkleTree`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct MerkleMultiProof {
+    /// The number of the leaves in the tree.
+    pub leaf_count: u64,
+    /// The indices of the proven data in the tree, in the order of the data given to `verify()`.
+    pub leaf_indices: Vec<u64>,
+    /// The sibling hashes that can't be calculated from the proven data,
+    /// from the bottom level and from the left in each level.
+    pub siblings: Vec<Hash256>,
+}
+
+impl MerkleMultiProof {
+    /// Calculates the root of the tree from the given data,
+    /// each of which corresponds to `leaf_indices`.
+    pub fn calculate_root(&self, data: &[Vec<u8>]) -> Result<Hash256, MerkleProofError> {
+        if data.len() != self.leaf_indices.len() {
+            return Err(MerkleProofError::MalformedProof(format!(
+                "expected {} data but found {}",
+                self.leaf_indices.len(),
+                data.len()
+            )));
+        }
+        if data.is_empty() {
+            return Err(MerkleProofError::MalformedProof(
+                "no data to verify".to_string(),
+            ));
+        }
+        let mut level: BTreeMap<u64, Hash256> = BTreeMap::new();
+        for (index, data) in self.leaf_indices.iter().zip(data) {
+            if *index >= self.leaf_count {
+                return Err(MerkleProofError::MalformedProof(format!(
+                    "leaf index {} out of {}",
+                    index, self.leaf_count
+                )));
+            }
+            if level.insert(*index, Hash256::hash(data)).is_some() {
+                return Err(MerkleProofError::MalformedProof(format!(
+                    "duplicate leaf index {}",
+                    index
+                )));
+            }
+        }
+        let mut siblings = self.siblings.iter();
+        let mut next_sibling = || {
+            siblings
+                .next()
+                .copied()
+                .ok_or_else(|| MerkleProofError::MalformedProof("not enough siblings".to_string()))
+        };
+        let mut width = self.leaf_count;
+        while width > 1 {
+            let mut upper_level = BTreeMap::new();
+            let mut nodes = level.into_iter().peekable();
+            while let Some((index, hash)) = nodes.next() {
+                let parent = if index % 2 == 1 {
+                    Hash256::aggregate(&next_sibling()?, &hash)
+                } else if index + 1 == width {
+                    Hash256::hash(hash)
+                } else if let Some((_, right)) = nodes.next_if(|(i, _)| *i == index + 1) {
+                    Hash256::aggregate(&hash, &right)
+                } else {
+                    Hash256::aggregate(&hash, &next_sibling()?)
+                };
+                upper_level.insert(index / 2, parent);
+            }
+            level = upper_level;
+            width = width / 2 + width % 2;
+        }
+        if siblings.next().is_some() {
+            return Err(MerkleProofError::MalformedProof(
+                "too many siblings".to_string(),
+            ));
+        }
+        Ok(level[&0])
+    }
+
+    /// Verifies whether all the given data are in the block.
+    pub fn verify(&self, root: Hash256, data: &[Vec<u8>]) -> Result<(), MerkleProofError> {
+        let calculated_root = self.calculate_root(data)?;
+        if root == calculated_root {
+            Ok(())
+        } else {
+            Err(MerkleProofError::UnmatchedRoot(
+                root.to_string(),
+                calculated_root.to_string(),
+            ))
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -265,4 +1061,274 @@ mod test {
         assert!(root_hash != OneshotMerkleTree::EMPTY_HASH);
         assert!(MerkleProof::verify(&merkle_proof.unwrap(), root_hash, &[10]).is_ok());
     }
+
+    #[test]
+    /// Test if Merkle multi-proofs work well for every subset of the leaves in trees of various sizes.
+    fn merkle_multi_proof() {
+        for number in 1..=9u8 {
+            let hash_list: Vec<Hash256> = create_hash_list(number);
+            let merkle_tree: OneshotMerkleTree = OneshotMerkleTree::create(hash_list.clone());
+            let root_hash: Hash256 = merkle_tree.root();
+            for subset in 1..(1u32 << number) {
+                let data: Vec<u8> = (0..number)
+                    .rev()
+                    .filter(|n| subset & (1 << n) != 0)
+                    .collect();
+                let keys: Vec<Hash256> = data.iter().map(|n| hash_list[*n as usize]).collect();
+                let data: Vec<Vec<u8>> = data.into_iter().map(|n| vec![n]).collect();
+                let proof = merkle_tree.create_merkle_multi_proof(&keys).unwrap();
+                proof.verify(root_hash, &data).unwrap();
+                // Siblings shared by the proven data are given only once.
+                let single_proofs_len: usize = keys
+                    .iter()
+                    .map(|key| merkle_tree.create_merkle_proof(*key).unwrap().proof.len())
+                    .sum();
+                assert!(proof.siblings.len() <= single_proofs_len);
+            }
+        }
+    }
+
+    #[test]
+    /// Test if verification of Merkle multi-proofs fails for invalid data or proofs.
+    fn merkle_multi_proof_verification_failure() {
+        let hash_list: Vec<Hash256> = create_hash_list(11);
+        let merkle_tree: OneshotMerkleTree = OneshotMerkleTree::create(hash_list.clone());
+        let root_hash: Hash256 = merkle_tree.root();
+        assert!(merkle_tree
+            .create_merkle_multi_proof(&[hash_list[1], Hash256::hash([42])])
+            .is_none());
+        assert!(merkle_tree
+            .create_merkle_multi_proof(&[hash_list[1], hash_list[1]])
+            .is_none());
+
+        let proof = merkle_tree
+            .create_merkle_multi_proof(&[hash_list[1], hash_list[6], hash_list[10]])
+            .unwrap();
+        proof
+            .verify(root_hash, &[vec![1], vec![6], vec![10]])
+            .unwrap();
+        proof
+            .verify(root_hash, &[vec![1], vec![10], vec![6]])
+            .unwrap_err();
+        proof
+            .verify(root_hash, &[vec![1], vec![6], vec![42]])
+            .unwrap_err();
+        proof.verify(root_hash, &[vec![1], vec![6]]).unwrap_err();
+
+        let mut invalid_proof = proof.clone();
+        invalid_proof.siblings.pop();
+        invalid_proof
+            .verify(root_hash, &[vec![1], vec![6], vec![10]])
+            .unwrap_err();
+        let mut invalid_proof = proof.clone();
+        invalid_proof.siblings.push(Hash256::zero());
+        invalid_proof
+            .verify(root_hash, &[vec![1], vec![6], vec![10]])
+            .unwrap_err();
+        let mut invalid_proof = proof;
+        invalid_proof.leaf_indices[2] = 11;
+        invalid_proof
+            .verify(root_hash, &[vec![1], vec![6], vec![10]])
+            .unwrap_err();
+    }
+
+    #[test]
+    /// Test if proofs of a Merkle mountain range work well for every leaf in ranges of various sizes.
+    fn merkle_mountain_range_proof() {
+        let mut range = MerkleMountainRange::new();
+        assert_eq!(range.root(), OneshotMerkleTree::EMPTY_HASH);
+        let mut leaves = Vec::new();
+        for n in 0..40u8 {
+            range.append(Hash256::hash([n]));
+            leaves.push(Hash256::hash([n]));
+            assert_eq!(range.peaks.len(), range.leaf_count.count_ones() as usize);
+            for index in 0..=n {
+                let proof = MerkleMountainRange::create_proof(&leaves, index as u64).unwrap();
+                range.verify(&[index], &proof).unwrap();
+                range.verify(&[index + 1], &proof).unwrap_err();
+            }
+            assert!(MerkleMountainRange::create_proof(&leaves, n as u64 + 1).is_none());
+        }
+        // A perfect range is the same as a `OneshotMerkleTree`.
+        let mut range = MerkleMountainRange::new();
+        for leaf in create_hash_list(16) {
+            range.append(leaf);
+        }
+        assert_eq!(
+            range.root(),
+            OneshotMerkleTree::create(create_hash_list(16)).root()
+        );
+    }
+
+    #[test]
+    /// Test if verification of Merkle mountain range proofs fails for invalid proofs.
+    fn merkle_mountain_range_verification_failure() {
+        let leaves = create_hash_list(11);
+        let mut range = MerkleMountainRange::new();
+        for leaf in &leaves {
+            range.append(*leaf);
+        }
+        let proof = MerkleMountainRange::create_proof(&leaves, 5).unwrap();
+        range.verify(&[5], &proof).unwrap();
+
+        let mut invalid_proof = proof.clone();
+        invalid_proof.leaf_index = 4;
+        range.verify(&[5], &invalid_proof).unwrap_err();
+        let mut invalid_proof = proof.clone();
+        invalid_proof.leaf_index = 11;
+        range.verify(&[5], &invalid_proof).unwrap_err();
+        let mut invalid_proof = proof;
+        invalid_proof.proof.proof.pop();
+        range.verify(&[5], &invalid_proof).unwrap_err();
+    }
+
+    #[test]
+    /// Test if the root of a sparse Merkle tree is independent of the insertion order and deletion.
+    fn sparse_merkle_tree_root() {
+        let hash_list: Vec<Hash256> = create_hash_list(16);
+        let mut tree = SparseMerkleTree::new();
+        assert_eq!(tree.root(), SparseMerkleTree::EMPTY_HASH);
+        for key in &hash_list {
+            tree.insert(*key, Hash256::hash(key)).unwrap();
+        }
+        let mut reversed_tree = SparseMerkleTree::new();
+        for key in hash_list.iter().rev() {
+            reversed_tree.insert(*key, Hash256::hash(key)).unwrap();
+        }
+        assert_eq!(tree.root(), reversed_tree.root());
+
+        let root = tree.root();
+        tree.insert(Hash256::hash([42]), Hash256::zero()).unwrap();
+        assert!(tree.insert(Hash256::hash([42]), Hash256::zero()).is_err());
+        assert_ne!(tree.root(), root);
+        tree.update(Hash256::hash([42]), Hash256::hash([1]))
+            .unwrap();
+        assert_eq!(tree.get(&Hash256::hash([42])), Some(Hash256::hash([1])));
+        tree.delete(&Hash256::hash([42])).unwrap();
+        assert!(tree.delete(&Hash256::hash([42])).is_err());
+        assert!(tree.update(Hash256::hash([42]), Hash256::zero()).is_err());
+        assert_eq!(tree.root(), root);
+
+        for key in &hash_list {
+            tree.delete(key).unwrap();
+        }
+        assert_eq!(tree.root(), SparseMerkleTree::EMPTY_HASH);
+    }
+
+    #[test]
+    /// Test if membership proofs of a sparse Merkle tree work well.
+    fn sparse_merkle_tree_membership_proof() {
+        let hash_list: Vec<Hash256> = create_hash_list(16);
+        let mut tree = SparseMerkleTree::new();
+        for key in &hash_list {
+            tree.insert(*key, Hash256::hash(key)).unwrap();
+        }
+        let root = tree.root();
+        for key in &hash_list {
+            let proof = tree.create_membership_proof(key).unwrap();
+            SparseMerkleTree::verify_membership(root, key, &Hash256::hash(key), &proof).unwrap();
+            proof
+                .verify(root, &sparse_leaf_data(key, &Hash256::hash(key)))
+                .unwrap();
+            SparseMerkleTree::verify_membership(root, key, &Hash256::zero(), &proof).unwrap_err();
+        }
+        assert!(tree.create_membership_proof(&Hash256::hash([42])).is_none());
+        // A proof for another key must not be accepted even if the leaf matches.
+        let proof = tree.create_membership_proof(&hash_list[0]).unwrap();
+        SparseMerkleTree::verify_membership(
+            root,
+            &hash_list[1],
+            &Hash256::hash(hash_list[0]),
+            &proof,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    /// Test if non-membership proofs of a sparse Merkle tree work well.
+    fn sparse_merkle_tree_non_membership_proof() {
+        let tree = SparseMerkleTree::new();
+        let proof = tree
+            .create_non_membership_proof(&Hash256::hash([42]))
+            .unwrap();
+        proof
+            .verify(SparseMerkleTree::EMPTY_HASH, &Hash256::hash([42]))
+            .unwrap();
+
+        let hash_list: Vec<Hash256> = create_hash_list(16);
+        let mut tree = SparseMerkleTree::new();
+        for key in &hash_list {
+            tree.insert(*key, Hash256::hash(key)).unwrap();
+        }
+        let root = tree.root();
+        let mut empty_ends = 0;
+        let mut leaf_ends = 0;
+        for n in 16..64u8 {
+            let key = Hash256::hash([n]);
+            let proof = tree.create_non_membership_proof(&key).unwrap();
+            proof.verify(root, &key).unwrap();
+            if proof.leaf.is_some() {
+                leaf_ends += 1;
+            } else {
+                empty_ends += 1;
+            }
+        }
+        assert!(empty_ends > 0 && leaf_ends > 0);
+
+        for key in &hash_list {
+            assert!(tree.create_non_membership_proof(key).is_none());
+            // Presenting the leaf of the key itself is not a proof of absence.
+            let proof = NonMembershipProof {
+                proof: tree.create_membership_proof(key).unwrap(),
+                leaf: Some((*key, Hash256::hash(key))),
+            };
+            proof.verify(root, key).unwrap_err();
+            // Neither is claiming an empty subtree where the leaf is.
+            let proof = NonMembershipProof {
+                proof: tree.create_membership_proof(key).unwrap(),
+                leaf: None,
+            };
+            proof.verify(root, key).unwrap_err();
+        }
+    }
+
+    #[test]
+    /// Test if the membership proofs of a repository Merkle tree are checked against the path.
+    fn repository_merkle_tree_membership_proof() {
+        let tree = RepositoryMerkleTree::create(vec![
+            ("README.md".to_owned(), b"hello".to_vec()),
+            ("reserved/members/a.json".to_owned(), b"{}".to_vec()),
+        ]);
+        let root = tree.root();
+        for (path, content) in [
+            ("README.md", b"hello".as_ref()),
+            ("reserved/members/a.json", b"{}".as_ref()),
+        ] {
+            let proof = tree.create_merkle_proof(path, content).unwrap();
+            RepositoryMerkleTree::verify_membership(root, path, content, &proof).unwrap();
+            RepositoryMerkleTree::verify_membership(root, path, b"bye", &proof).unwrap_err();
+        }
+
+        // A proof that goes the other way than the path, which still leads to the root.
+        let path = "README.md";
+        let leaf = Hash256::hash(file_leaf_data(path, b"hello"));
+        let sibling = Hash256::hash("sibling");
+        let (entry, subtree_root) = if key_bit(&Hash256::hash(path), 0) {
+            (
+                MerkleProofEntry::RightChild(sibling),
+                Hash256::aggregate(&leaf, &sibling),
+            )
+        } else {
+            (
+                MerkleProofEntry::LeftChild(sibling),
+                Hash256::aggregate(&sibling, &leaf),
+            )
+        };
+        let root = Hash256::aggregate(&tree.reserved_root(), &subtree_root);
+        let proof = MerkleProof {
+            proof: vec![entry, MerkleProofEntry::LeftChild(tree.reserved_root())],
+        };
+        proof.verify(root, &file_leaf_data(path, b"hello")).unwrap();
+        RepositoryMerkleTree::verify_membership(root, path, b"hello", &proof).unwrap_err();
+    }
 }
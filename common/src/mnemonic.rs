@@ -0,0 +1,399 @@
+//! Mnemonic phrases and hierarchical deterministic key derivation.
+//!
+//! A member backs up a single [BIP-39](https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki)
+//! phrase, and derives a separate key for each purpose (see `KeyPurpose`) from it.
+//! The derivation follows the de-facto standard of each scheme:
+//! - secp256k1: [BIP-32](https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki).
+//! - Ed25519: [SLIP-10](https://github.com/satoshilabs/slips/blob/master/slip-0010.md),
+//! which only allows hardened indices.
+//! - BLS12-381: [EIP-2333](https://eips.ethereum.org/EIPS/eip-2333), which takes the indices as they are.
+use crate::crypto::*;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use secp256k1::{Scalar, Secp256k1, SecretKey};
+use sha2::Sha512;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The flag of a hardened index (written as `i'` in a derivation path).
+pub const HARDENED: u32 = 1 << 31;
+/// The coin type of Simperby in the derivation paths (not registered in SLIP-44; "SP" in ASCII).
+pub const SIMPERBY_COIN_TYPE: u32 = 0x5350;
+
+#[derive(Error, Debug, Clone)]
+pub enum MnemonicError {
+    #[error("invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+    #[error("invalid derivation path: {0}")]
+    InvalidPath(String),
+    /// When the derived key is invalid, which is practically impossible.
+    #[error("failed to derive the key: {0}")]
+    DerivationFailed(String),
+}
+
+type Error = MnemonicError;
+
+/// A BIP-39 mnemonic phrase in English.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Mnemonic {
+    mnemonic: bip39::Mnemonic,
+}
+
+impl Mnemonic {
+    /// Generates a new random mnemonic of the given number of words (12, 15, 18, 21 or 24).
+    pub fn generate(word_count: usize) -> Result<Self, Error> {
+        if !(12..=24).contains(&word_count) || word_count / 3 * 3 != word_count {
+            return Err(Error::InvalidMnemonic(format!(
+                "invalid word count: {}",
+                word_count
+            )));
+        }
+        let mut entropy = vec![0; word_count / 3 * 4];
+        rand::rngs::OsRng.fill_bytes(&mut entropy);
+        Self::from_entropy(&entropy)
+    }
+
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            mnemonic: bip39::Mnemonic::from_entropy(entropy)
+                .map_err(|e| Error::InvalidMnemonic(e.to_string()))?,
+        })
+    }
+
+    /// Parses the phrase, checking the words and the checksum.
+    pub fn parse(phrase: &str) -> Result<Self, Error> {
+        Ok(Self {
+            mnemonic: bip39::Mnemonic::parse_in(bip39::Language::English, phrase)
+                .map_err(|e| Error::InvalidMnemonic(e.to_string()))?,
+        })
+    }
+
+    /// Returns the words separated by spaces.
+    pub fn phrase(&self) -> String {
+        self.mnemonic.to_string()
+    }
+
+    /// Returns the 64-byte seed with the optional passphrase (which may be empty).
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        self.mnemonic.to_seed(passphrase)
+    }
+
+    /// Derives the keypair of the given scheme at the path.
+    pub fn derive_keypair(
+        &self,
+        passphrase: &str,
+        path: &DerivationPath,
+        scheme: SignatureScheme,
+    ) -> Result<(PublicKey, PrivateKey), Error> {
+        derive_keypair_from_seed(&self.to_seed(passphrase), path, scheme)
+    }
+}
+
+/// The phrase is never printed in the debug format.
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mnemonic([omitted])")
+    }
+}
+
+/// A derivation path like `m/44'/0'/0'`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DerivationPath {
+    pub indices: Vec<u32>,
+}
+
+impl FromStr for DerivationPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut components = s.split('/');
+        if components.next() != Some("m") {
+            return Err(Error::InvalidPath(s.to_owned()));
+        }
+        let indices = components
+            .map(|component| {
+                let (index, hardened) = if let Some(index) = component.strip_suffix('\'') {
+                    (index, true)
+                } else {
+                    (component, false)
+                };
+                let index = index
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|index| *index < HARDENED)
+                    .ok_or_else(|| Error::InvalidPath(s.to_owned()))?;
+                Ok(if hardened { index | HARDENED } else { index })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { indices })
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.indices {
+            if index & HARDENED != 0 {
+                write!(f, "/{}'", index & !HARDENED)?;
+            } else {
+                write!(f, "/{}", index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a member's key is used for, so that each of them can be rotated separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyPurpose {
+    Consensus,
+    Governance,
+    Network,
+}
+
+impl KeyPurpose {
+    /// Returns `m/44'/SIMPERBY_COIN_TYPE'/account'/purpose'`.
+    pub fn derivation_path(&self, account: u32) -> DerivationPath {
+        let purpose = match self {
+            KeyPurpose::Consensus => 0,
+            KeyPurpose::Governance => 1,
+            KeyPurpose::Network => 2,
+        };
+        DerivationPath {
+            indices: vec![
+                44 | HARDENED,
+                SIMPERBY_COIN_TYPE | HARDENED,
+                account | HARDENED,
+                purpose | HARDENED,
+            ],
+        }
+    }
+}
+
+impl FromStr for KeyPurpose {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "consensus" => Ok(KeyPurpose::Consensus),
+            "governance" => Ok(KeyPurpose::Governance),
+            "network" => Ok(KeyPurpose::Network),
+            _ => Err(format!("unknown key purpose: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for KeyPurpose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyPurpose::Consensus => write!(f, "consensus"),
+            KeyPurpose::Governance => write!(f, "governance"),
+            KeyPurpose::Network => write!(f, "network"),
+        }
+    }
+}
+
+/// Derives the keypair of the given scheme at the path from the seed.
+pub fn derive_keypair_from_seed(
+    seed: &[u8],
+    path: &DerivationPath,
+    scheme: SignatureScheme,
+) -> Result<(PublicKey, PrivateKey), Error> {
+    let failed = |e: CryptoError| Error::DerivationFailed(e.to_string());
+    let private_key = match scheme {
+        SignatureScheme::Secp256k1 => {
+            PrivateKey::from_array(derive_secp256k1(seed, path)?).map_err(failed)?
+        }
+        SignatureScheme::Ed25519 => {
+            PrivateKey::from_ed25519_secret(derive_ed25519(seed, path)?).map_err(failed)?
+        }
+        SignatureScheme::Bls12381 => {
+            PrivateKey::from_bls12381_secret(derive_bls12381(seed, path)?).map_err(failed)?
+        }
+    };
+    Ok((private_key.public_key(), private_key))
+}
+
+/// Returns the left and right halves of `HMAC-SHA512(key, data)`.
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes a key of any length");
+    for x in data {
+        mac.update(x);
+    }
+    let result = mac.finalize().into_bytes();
+    let mut left = [0; 32];
+    let mut right = [0; 32];
+    left.copy_from_slice(&result[0..32]);
+    right.copy_from_slice(&result[32..64]);
+    (left, right)
+}
+
+fn derive_secp256k1(seed: &[u8], path: &DerivationPath) -> Result<[u8; 32], Error> {
+    let failed = |e: secp256k1::Error| Error::DerivationFailed(e.to_string());
+    let (key, mut chain_code) = hmac_sha512(b"Bitcoin seed", &[seed]);
+    let mut key = SecretKey::from_slice(&key).map_err(failed)?;
+    let secp = Secp256k1::new();
+    for index in &path.indices {
+        let (tweak, next_chain_code) = if index & HARDENED != 0 {
+            hmac_sha512(
+                &chain_code,
+                &[&[0], &key.secret_bytes(), &index.to_be_bytes()],
+            )
+        } else {
+            hmac_sha512(
+                &chain_code,
+                &[&key.public_key(&secp).serialize(), &index.to_be_bytes()],
+            )
+        };
+        let tweak = Scalar::from_be_bytes(tweak)
+            .map_err(|_| Error::DerivationFailed("the tweak is out of range".to_owned()))?;
+        key = key.add_tweak(&tweak).map_err(failed)?;
+        chain_code = next_chain_code;
+    }
+    Ok(key.secret_bytes())
+}
+
+fn derive_ed25519(seed: &[u8], path: &DerivationPath) -> Result<[u8; 32], Error> {
+    let (mut key, mut chain_code) = hmac_sha512(b"ed25519 seed", &[seed]);
+    for index in &path.indices {
+        if index & HARDENED == 0 {
+            return Err(Error::InvalidPath(format!(
+                "Ed25519 only allows hardened indices: {}",
+                path
+            )));
+        }
+        (key, chain_code) = hmac_sha512(&chain_code, &[&[0], &key, &index.to_be_bytes()]);
+    }
+    Ok(key)
+}
+
+fn derive_bls12381(seed: &[u8], path: &DerivationPath) -> Result<[u8; 32], Error> {
+    let mut key = blst::min_pk::SecretKey::derive_master_eip2333(seed)
+        .map_err(|e| Error::DerivationFailed(format!("{:?}", e)))?;
+    for index in &path.indices {
+        key = key.derive_child_eip2333(*index);
+    }
+    Ok(key.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHRASE: &str = "abandon abandon abandon abandon abandon abandon \
+                          abandon abandon abandon abandon abandon about";
+
+    fn private_key_hex(private_key: &PrivateKey) -> String {
+        hex::encode(&private_key.as_ref()[0..32])
+    }
+
+    #[test]
+    fn mnemonic_seed() {
+        // The test vector of BIP-39.
+        let mnemonic = Mnemonic::parse(PHRASE).unwrap();
+        assert_eq!(mnemonic, Mnemonic::from_entropy(&[0; 16]).unwrap());
+        assert_eq!(
+            hex::encode(mnemonic.to_seed("TREZOR")),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        Mnemonic::parse(&PHRASE.replace("about", "abandon")).unwrap_err();
+        Mnemonic::parse(&PHRASE.replace("about", "simperby")).unwrap_err();
+    }
+
+    #[test]
+    fn generate_and_recover() {
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(mnemonic.phrase().split(' ').count(), 24);
+        let recovered = Mnemonic::parse(&mnemonic.phrase()).unwrap();
+        let path = KeyPurpose::Consensus.derivation_path(0);
+        assert_eq!(
+            mnemonic
+                .derive_keypair("", &path, SignatureScheme::Secp256k1)
+                .unwrap(),
+            recovered
+                .derive_keypair("", &path, SignatureScheme::Secp256k1)
+                .unwrap()
+        );
+        Mnemonic::generate(13).unwrap_err();
+    }
+
+    #[test]
+    fn derivation_path() {
+        let path: DerivationPath = "m/44'/0'/1/2'".parse().unwrap();
+        assert_eq!(path.indices, vec![44 | HARDENED, HARDENED, 1, 2 | HARDENED]);
+        assert_eq!(path.to_string(), "m/44'/0'/1/2'");
+        assert!("m".parse::<DerivationPath>().unwrap().indices.is_empty());
+        "44'/0'".parse::<DerivationPath>().unwrap_err();
+        "m/x".parse::<DerivationPath>().unwrap_err();
+        "m/2147483648".parse::<DerivationPath>().unwrap_err();
+    }
+
+    #[test]
+    fn derivation_test_vectors() {
+        // The first test vectors of BIP-32, SLIP-10 and EIP-2333.
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let path: DerivationPath = "m/0'".parse().unwrap();
+        let (_, private_key) =
+            derive_keypair_from_seed(&seed, &path, SignatureScheme::Secp256k1).unwrap();
+        assert_eq!(
+            private_key_hex(&private_key),
+            "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea"
+        );
+        let (_, private_key) =
+            derive_keypair_from_seed(&seed, &path, SignatureScheme::Ed25519).unwrap();
+        assert_eq!(
+            private_key_hex(&private_key),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+
+        let seed = Mnemonic::parse(PHRASE).unwrap().to_seed("TREZOR");
+        let (_, private_key) =
+            derive_keypair_from_seed(&seed, &"m".parse().unwrap(), SignatureScheme::Bls12381)
+                .unwrap();
+        assert_eq!(
+            private_key_hex(&private_key),
+            "0d7359d57963ab8fbbde1852dcf553fedbc31f464d80ee7d40ae683122b45070"
+        );
+        let (_, private_key) =
+            derive_keypair_from_seed(&seed, &"m/0".parse().unwrap(), SignatureScheme::Bls12381)
+                .unwrap();
+        assert_eq!(
+            private_key_hex(&private_key),
+            "2d18bd6c14e6d15bf8b5085c9b74f3daae3b03cc2014770a599d8c1539e50f8e"
+        );
+    }
+
+    #[test]
+    fn separate_keys_for_purposes() {
+        let mnemonic = Mnemonic::parse(PHRASE).unwrap();
+        for scheme in [
+            SignatureScheme::Secp256k1,
+            SignatureScheme::Ed25519,
+            SignatureScheme::Bls12381,
+        ] {
+            let keys = [
+                KeyPurpose::Consensus,
+                KeyPurpose::Governance,
+                KeyPurpose::Network,
+            ]
+            .iter()
+            .map(|purpose| {
+                let (public_key, private_key) = mnemonic
+                    .derive_keypair("", &purpose.derivation_path(0), scheme)
+                    .unwrap();
+                assert_eq!(private_key.scheme(), scheme);
+                check_keypair_match(&public_key, &private_key).unwrap();
+                public_key
+            })
+            .collect::<std::collections::BTreeSet<_>>();
+            assert_eq!(keys.len(), 3);
+        }
+        derive_keypair_from_seed(
+            &mnemonic.to_seed(""),
+            &"m/0".parse().unwrap(),
+            SignatureScheme::Ed25519,
+        )
+        .unwrap_err();
+    }
+}
@@ -1,6 +1,14 @@
+use crate::serde_spb::HashEncoding;
 use crate::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use thiserror::Error;
+
+/// The directory of the repository where the reserved state is stored.
+pub const RESERVED_DIRECTORY: &str = "reserved";
+
+/// The number of the latest heights of which the misbehaviors can be reported.
+pub const REPORT_EVIDENCE_WINDOW: BlockHeight = 16;
 
 /// The partial set of the blockchain state which is reserved and protected.
 ///
@@ -20,11 +28,149 @@ pub struct ReservedState {
     pub version: String,
 }
 
+/// The reason why a `ReservedState` is invalid.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReservedStateError {
+    #[error("duplicate member name: {0}")]
+    DuplicateMemberName(MemberName),
+    #[error("duplicate public key: {0}")]
+    DuplicatePublicKey(PublicKey),
+    #[error("the consensus leader order is not sorted or has a duplicate: {0} is followed by {1}")]
+    UnsortedLeaderOrder(MemberName, MemberName),
+    #[error("unknown member in the consensus leader order: {0}")]
+    UnknownLeader(MemberName),
+    #[error("{0} delegates to an unknown member {1}")]
+    UnknownDelegatee(MemberName, MemberName),
+    /// When a member delegates to a member who has delegated (including itself).
+    #[error("{0} delegates to {1}, who has delegated its voting power")]
+    DelegationToDelegator(MemberName, MemberName),
+    /// When a member has a BLS12-381 public key without a valid proof of possession,
+    /// or has a proof of possession for the other public key.
+    #[error("invalid proof of possession of {0}")]
+    InvalidProofOfPossession(MemberName),
+    #[error("the total consensus voting power is zero")]
+    ZeroConsensusVotingPower,
+    #[error("the total governance voting power is zero")]
+    ZeroGovernanceVotingPower,
+    #[error("invalid version: {0}")]
+    InvalidVersion(String),
+    #[error("inconsistent genesis info: {0}")]
+    InconsistentGenesisInfo(String),
+}
+
 impl ReservedState {
-    pub fn get_validator_set(&self) -> Result<Vec<(PublicKey, VotingPower)>, String> {
+    /// Checks whether the state is well-formed so that every query on it is well-defined.
+    ///
+    /// Note that it doesn't evaluate the delegation conditions;
+    /// delegations are checked regardless of whether they are released.
+    pub fn validate(&self) -> Result<(), ReservedStateError> {
+        let mut names = BTreeSet::new();
+        let mut public_keys = BTreeSet::new();
+        for member in &self.members {
+            if !names.insert(&member.name) {
+                return Err(ReservedStateError::DuplicateMemberName(member.name.clone()));
+            }
+            if !public_keys.insert(&member.public_key) {
+                return Err(ReservedStateError::DuplicatePublicKey(
+                    member.public_key.clone(),
+                ));
+            }
+            let possession_proven = match &member.proof_of_possession {
+                Some(proof) => proof.verify_possession(&member.public_key).is_ok(),
+                None => member.public_key.scheme() != SignatureScheme::Bls12381,
+            };
+            if !possession_proven {
+                return Err(ReservedStateError::InvalidProofOfPossession(
+                    member.name.clone(),
+                ));
+            }
+        }
+        for pair in self.consensus_leader_order.windows(2) {
+            if pair[0] >= pair[1] {
+                return Err(ReservedStateError::UnsortedLeaderOrder(
+                    pair[0].clone(),
+                    pair[1].clone(),
+                ));
+            }
+        }
+        for name in &self.consensus_leader_order {
+            if !names.contains(name) {
+                return Err(ReservedStateError::UnknownLeader(name.clone()));
+            }
+        }
+        let delegators = self
+            .members
+            .iter()
+            .filter(|member| {
+                member.consensus_delegations.is_some() || member.governance_delegations.is_some()
+            })
+            .map(|member| &member.name)
+            .collect::<BTreeSet<_>>();
+        for member in &self.members {
+            for delegatee in member
+                .consensus_delegations
+                .iter()
+                .chain(member.governance_delegations.iter())
+            {
+                if !names.contains(delegatee) {
+                    return Err(ReservedStateError::UnknownDelegatee(
+                        member.name.clone(),
+                        delegatee.clone(),
+                    ));
+                }
+                if delegators.contains(delegatee) {
+                    return Err(ReservedStateError::DelegationToDelegator(
+                        member.name.clone(),
+                        delegatee.clone(),
+                    ));
+                }
+            }
+        }
+        if self
+            .members
+            .iter()
+            .all(|member| member.consensus_voting_power == 0)
+        {
+            return Err(ReservedStateError::ZeroConsensusVotingPower);
+        }
+        if self
+            .members
+            .iter()
+            .all(|member| member.governance_voting_power == 0)
+        {
+            return Err(ReservedStateError::ZeroGovernanceVotingPower);
+        }
+        serde_spb::parse_version(&self.version).map_err(ReservedStateError::InvalidVersion)?;
+        let genesis_header = &self.genesis_info.header;
+        if genesis_header.height != 0 {
+            return Err(ReservedStateError::InconsistentGenesisInfo(format!(
+                "the genesis header has height {}",
+                genesis_header.height
+            )));
+        }
+        serde_spb::parse_version(&genesis_header.version)
+            .map_err(ReservedStateError::InconsistentGenesisInfo)?;
+        verify::verify_finalization_proof(genesis_header, &self.genesis_info.genesis_proof)
+            .map_err(|e| ReservedStateError::InconsistentGenesisInfo(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Returns the effective validator set at the given block height and timestamp,
+    /// with the delegations applied.
+    ///
+    /// Delegations whose conditions are met fall back to the delegator.
+    /// `last_signers` are the validators who signed the finalization proof of the last finalized block,
+    /// which tell whether the delegatees are active; no delegatee is inactive if it is `None`.
+    pub fn get_validator_set(
+        &self,
+        height: BlockHeight,
+        timestamp: Timestamp,
+        last_signers: Option<&BTreeSet<PublicKey>>,
+    ) -> Result<Vec<(PublicKey, VotingPower)>, String> {
+        let context = self.delegation_context(height, timestamp, last_signers);
         let mut validator_set = HashMap::new();
         for member in &self.members {
-            if let Some(delegatee) = &member.consensus_delegations {
+            if let Some(delegatee) = self.effective_consensus_delegatee(member, &context) {
                 validator_set
                     .entry(delegatee.clone())
                     .and_modify(|v| *v += member.consensus_voting_power)
@@ -36,41 +182,479 @@ impl ReservedState {
                     .or_insert(member.consensus_voting_power);
             }
         }
-        // TODO handle error
-        Ok(self
-            .consensus_leader_order
-            .iter()
-            .map(|name| (self.query_public_key(name).unwrap(), validator_set[name]))
-            .collect())
+        // Members who delegated their consensus voting power
+        // or have no voting power are not validators.
+        let mut result = Vec::new();
+        for name in &self.consensus_leader_order {
+            if let Some(power) = validator_set.get(name).filter(|power| **power > 0) {
+                let public_key = self
+                    .query_public_key(name)
+                    .ok_or_else(|| format!("unknown member in the leader order: {}", name))?;
+                result.push((public_key, *power));
+            }
+        }
+        Ok(result)
     }
 
-    pub fn get_governance_set(&self) -> Result<Vec<(PublicKey, VotingPower)>, String> {
+    /// Returns the effective governance set at the given block height and timestamp,
+    /// with the delegations applied.
+    ///
+    /// Delegations whose conditions are met fall back to the delegator.
+    pub fn get_governance_set(
+        &self,
+        height: BlockHeight,
+        timestamp: Timestamp,
+    ) -> Result<Vec<(PublicKey, VotingPower)>, String> {
+        let context = self.delegation_context(height, timestamp, None);
         let mut governance_set = HashMap::new();
         for member in &self.members {
-            if let Some(delegatee) = &member.governance_delegations {
+            if let Some(delegatee) = self.effective_governance_delegatee(member, &context) {
                 governance_set
                     .entry(delegatee.clone())
-                    .and_modify(|v| *v += member.consensus_voting_power)
-                    .or_insert(member.consensus_voting_power);
+                    .and_modify(|v| *v += member.governance_voting_power)
+                    .or_insert(member.governance_voting_power);
             } else {
                 governance_set
                     .entry(member.name.clone())
-                    .and_modify(|v| *v += member.consensus_voting_power)
-                    .or_insert(member.consensus_voting_power);
+                    .and_modify(|v| *v += member.governance_voting_power)
+                    .or_insert(member.governance_voting_power);
+            }
+        }
+        governance_set
+            .iter()
+            .map(|(name, voting_power)| {
+                self.query_public_key(name)
+                    .map(|public_key| (public_key, *voting_power))
+                    .ok_or_else(|| format!("unknown delegatee: {}", name))
+            })
+            .collect()
+    }
+
+    /// Returns the hash of the validator set without the delegations applied,
+    /// which `DelegationCondition::UnlockIfValidatorSetChanges` refers to.
+    ///
+    /// It is the members in the leader order with their own consensus voting power,
+    /// so it changes only with the members and their voting power, not with the delegations.
+    pub fn get_base_validator_set_hash(&self) -> Hash256 {
+        let validator_set = self
+            .consensus_leader_order
+            .iter()
+            .filter_map(|name| self.members.iter().find(|member| &member.name == name))
+            .filter(|member| member.consensus_voting_power > 0)
+            .map(|member| (member.public_key.clone(), member.consensus_voting_power))
+            .collect::<Vec<_>>();
+        Hash256::hash(serde_spb::to_canonical_vec(&validator_set).unwrap())
+    }
+
+    /// Applies the given delegation transaction, returning the updated state.
+    ///
+    /// The consensus voting power is always delegated, and the governance voting power
+    /// is delegated too if `tx.governance` is set.
+    /// Delegation chains (delegating to a delegator, or delegating while being a delegatee) are not allowed.
+    ///
+    /// Existing delegations are evaluated at `tx.block_height` and `tx.timestamp`,
+    /// so released ones don't prevent the new delegation; they are cleared instead.
+    /// The activity of the delegatees is not evaluated here,
+    /// so the delegations released only by `UnlockIfDelegateeInactive` are kept.
+    ///
+    /// The proof is verified with `encoding`, which is the one of the last finalized header.
+    pub fn apply_delegate(&self, tx: &TxDelegate, encoding: HashEncoding) -> Result<Self, String> {
+        if tx.proof.signer() != &tx.delegator {
+            return Err(format!(
+                "invalid proof: signed by {}, not by the delegator {}",
+                tx.proof.signer(),
+                tx.delegator
+            ));
+        }
+        tx.proof
+            .verify_with(
+                &(
+                    tx.delegator.clone(),
+                    tx.delegatee.clone(),
+                    tx.governance,
+                    tx.conditions.clone(),
+                    tx.block_height,
+                ),
+                encoding,
+            )
+            .map_err(|e| format!("invalid proof: {}", e))?;
+        let delegator = self
+            .query_name(&tx.delegator)
+            .ok_or_else(|| format!("unknown delegator: {}", tx.delegator))?;
+        let delegatee = self
+            .query_name(&tx.delegatee)
+            .ok_or_else(|| format!("unknown delegatee: {}", tx.delegatee))?;
+        if delegator == delegatee {
+            return Err(format!("self-delegation of {} is not allowed", delegator));
+        }
+        let context = self.delegation_context(tx.block_height, tx.timestamp, None);
+        if self.is_released(&tx.conditions, &delegatee, &context) {
+            return Err("the delegation would be released immediately".to_string());
+        }
+        for member in &self.members {
+            let consensus_delegatee = self.effective_consensus_delegatee(member, &context);
+            let governance_delegatee = self.effective_governance_delegatee(member, &context);
+            if member.name == delegator && consensus_delegatee.is_some() {
+                return Err(format!(
+                    "{} has already delegated its voting power; undelegate first",
+                    delegator
+                ));
+            }
+            if member.name == delegatee
+                && (consensus_delegatee.is_some()
+                    || (tx.governance && governance_delegatee.is_some()))
+            {
+                return Err(format!(
+                    "{} has delegated its voting power and can't be a delegatee",
+                    delegatee
+                ));
+            }
+            if consensus_delegatee == Some(&delegator) || governance_delegatee == Some(&delegator) {
+                return Err(format!(
+                    "{} is a delegatee of {} and can't delegate",
+                    delegator, member.name
+                ));
+            }
+        }
+        let mut state = self.clone();
+        for member in &mut state.members {
+            // Clear the released delegations so that they can't form a delegation chain.
+            if self
+                .effective_consensus_delegatee(member, &context)
+                .is_none()
+            {
+                member.consensus_delegations = None;
+                member.consensus_delegation_conditions = vec![];
+            }
+            if self
+                .effective_governance_delegatee(member, &context)
+                .is_none()
+            {
+                member.governance_delegations = None;
+                member.governance_delegation_conditions = vec![];
             }
+            if member.name == delegator {
+                member.consensus_delegations = Some(delegatee.clone());
+                member.consensus_delegation_conditions = tx.conditions.clone();
+                if tx.governance {
+                    member.governance_delegations = Some(delegatee.clone());
+                    member.governance_delegation_conditions = tx.conditions.clone();
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Applies the given undelegation transaction, returning the updated state.
+    ///
+    /// It undelegates both the consensus and the governance voting power.
+    /// Delegations that are already released by their conditions can be undelegated too,
+    /// which simply clears them.
+    ///
+    /// The proof is verified with `encoding`, which is the one of the last finalized header.
+    pub fn apply_undelegate(
+        &self,
+        tx: &TxUndelegate,
+        encoding: HashEncoding,
+    ) -> Result<Self, String> {
+        if tx.proof.signer() != &tx.delegator {
+            return Err(format!(
+                "invalid proof: signed by {}, not by the delegator {}",
+                tx.proof.signer(),
+                tx.delegator
+            ));
+        }
+        tx.proof
+            .verify_with(&(tx.delegator.clone(), tx.block_height), encoding)
+            .map_err(|e| format!("invalid proof: {}", e))?;
+        let delegator = self
+            .query_name(&tx.delegator)
+            .ok_or_else(|| format!("unknown delegator: {}", tx.delegator))?;
+        let mut state = self.clone();
+        let member = state
+            .members
+            .iter_mut()
+            .find(|member| member.name == delegator)
+            .expect("already queried");
+        if member.consensus_delegations.is_none() && member.governance_delegations.is_none() {
+            return Err(format!("{} has not delegated its voting power", delegator));
+        }
+        member.consensus_delegations = None;
+        member.consensus_delegation_conditions = vec![];
+        member.governance_delegations = None;
+        member.governance_delegation_conditions = vec![];
+        Ok(state)
+    }
+
+    /// Applies the given report transaction, returning the updated state.
+    ///
+    /// The evidence must be from the consensus of one of the last `REPORT_EVIDENCE_WINDOW` heights
+    /// up to `last_header`, and the offender must be one of its validators;
+    /// the consensus of a height runs on the validator set of the previous header,
+    /// which is given by `tx.headers`.
+    ///
+    /// The consensus voting power of the offender becomes zero,
+    /// and the consensus delegations to the offender are released.
+    pub fn apply_report(&self, tx: &TxReport, last_header: &BlockHeader) -> Result<Self, String> {
+        let offender = tx
+            .misbehavior
+            .verify(&self.genesis_info.header.to_hash256())?;
+        let height = tx.misbehavior.height();
+        if height == 0
+            || height > last_header.height
+            || last_header.height - height >= REPORT_EVIDENCE_WINDOW
+        {
+            return Err(format!(
+                "the evidence must be from the consensus of the last {} heights up to {}, but it is from {}",
+                REPORT_EVIDENCE_WINDOW, last_header.height, height
+            ));
         }
-        Ok(governance_set
+        if tx.headers.len() as BlockHeight != last_header.height - height + 1 {
+            return Err(format!(
+                "expected the headers from height {} to {}, but got {}",
+                height - 1,
+                last_header.height - 1,
+                tx.headers.len()
+            ));
+        }
+        for (header, next_header) in tx.headers.iter().zip(
+            tx.headers
+                .iter()
+                .skip(1)
+                .chain(std::iter::once(last_header)),
+        ) {
+            if header.height + 1 != next_header.height
+                || header.to_hash256() != next_header.previous_hash
+            {
+                return Err(format!(
+                    "the header of height {} is not linked to the last finalized header",
+                    header.height
+                ));
+            }
+        }
+        if !tx.headers[0]
+            .validator_set
             .iter()
-            .map(|(name, voting_power)| (self.query_public_key(name).unwrap(), *voting_power))
-            .collect())
+            .any(|(public_key, _)| public_key == offender)
+        {
+            return Err(format!(
+                "{} is not a validator of height {}",
+                offender, height
+            ));
+        }
+        let offender = self
+            .query_name(offender)
+            .ok_or_else(|| format!("unknown offender: {}", offender))?;
+        let mut state = self.clone();
+        for member in &mut state.members {
+            if member.name == offender {
+                if member.consensus_voting_power == 0 {
+                    return Err(format!("{} has already lost its voting power", offender));
+                }
+                member.consensus_voting_power = 0;
+            }
+            if member.consensus_delegations.as_ref() == Some(&offender) {
+                member.consensus_delegations = None;
+                member.consensus_delegation_conditions = vec![];
+            }
+        }
+        Ok(state)
     }
 
-    pub fn apply_delegate(&mut self, _tx: &TxDelegate) -> Result<Self, String> {
-        unimplemented!()
+    /// Applies the given operation, returning the updated state.
+    ///
+    /// It doesn't validate the resulting state, which may be valid only after the following operations;
+    /// use `apply_operations()` to apply a complete sequence.
+    pub fn apply_operation(&self, operation: &ReservedStateOperation) -> Result<Self, String> {
+        let mut state = self.clone();
+        match operation {
+            ReservedStateOperation::AddMember(member) => {
+                if self.query_public_key(&member.name).is_some() {
+                    return Err(format!("member {} already exists", member.name));
+                }
+                let index = state
+                    .members
+                    .iter()
+                    .position(|m| m.name > member.name)
+                    .unwrap_or(state.members.len());
+                state.members.insert(index, member.as_ref().clone());
+            }
+            ReservedStateOperation::RemoveMember(name) => {
+                if self.query_public_key(name).is_none() {
+                    return Err(format!("unknown member: {}", name));
+                }
+                state.members.retain(|m| &m.name != name);
+                state.consensus_leader_order.retain(|n| n != name);
+            }
+            ReservedStateOperation::ChangeVotingPower {
+                name,
+                governance_voting_power,
+                consensus_voting_power,
+            } => {
+                let member = state
+                    .members
+                    .iter_mut()
+                    .find(|m| &m.name == name)
+                    .ok_or_else(|| format!("unknown member: {}", name))?;
+                member.governance_voting_power = *governance_voting_power;
+                member.consensus_voting_power = *consensus_voting_power;
+            }
+            ReservedStateOperation::ReorderLeaders(order) => {
+                state.consensus_leader_order = order.clone();
+            }
+            ReservedStateOperation::UpgradeVersion(version) => {
+                if serde_spb::parse_version(version)? <= serde_spb::parse_version(&self.version)? {
+                    return Err(format!(
+                        "invalid version upgrade: {} to {}",
+                        self.version, version
+                    ));
+                }
+                state.version = version.clone();
+            }
+        }
+        Ok(state)
+    }
+
+    /// Applies the given operations in order, returning the updated state which must be valid.
+    pub fn apply_operations(&self, operations: &[ReservedStateOperation]) -> Result<Self, String> {
+        let mut state = self.clone();
+        for operation in operations {
+            state = state.apply_operation(operation)?;
+        }
+        state.validate().map_err(|e| e.to_string())?;
+        Ok(state)
+    }
+
+    /// Derives the operations that turn this state into `next`,
+    /// so that `self.apply_operations(&operations)` is `next`.
+    ///
+    /// A member with any change other than the voting power is removed and added again.
+    /// It fails if the genesis info is changed, the version is downgraded,
+    /// the members of `next` are not sorted by their names
+    /// or the operations don't turn this state into `next` exactly.
+    pub fn operations_to(
+        &self,
+        next: &ReservedState,
+    ) -> Result<Vec<ReservedStateOperation>, String> {
+        if self.genesis_info != next.genesis_info {
+            return Err("the genesis info must never be changed".to_string());
+        }
+        if next
+            .members
+            .windows(2)
+            .any(|pair| pair[0].name >= pair[1].name)
+        {
+            return Err("the members must be sorted by their names".to_string());
+        }
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        for member in &self.members {
+            match next.members.iter().find(|m| m.name == member.name) {
+                None => removed.push(ReservedStateOperation::RemoveMember(member.name.clone())),
+                Some(next_member) => {
+                    let mut with_next_power = member.clone();
+                    with_next_power.governance_voting_power = next_member.governance_voting_power;
+                    with_next_power.consensus_voting_power = next_member.consensus_voting_power;
+                    if &with_next_power != next_member {
+                        removed.push(ReservedStateOperation::RemoveMember(member.name.clone()));
+                        added.push(ReservedStateOperation::AddMember(Box::new(
+                            next_member.clone(),
+                        )));
+                    } else if member != next_member {
+                        changed.push(ReservedStateOperation::ChangeVotingPower {
+                            name: member.name.clone(),
+                            governance_voting_power: next_member.governance_voting_power,
+                            consensus_voting_power: next_member.consensus_voting_power,
+                        });
+                    }
+                }
+            }
+        }
+        for member in &next.members {
+            if self.query_public_key(&member.name).is_none() {
+                added.push(ReservedStateOperation::AddMember(Box::new(member.clone())));
+            }
+        }
+        let mut operations = removed;
+        operations.append(&mut added);
+        operations.append(&mut changed);
+        // Removing members also removes them from the leader order.
+        let intermediate = operations
+            .iter()
+            .try_fold(self.clone(), |state, operation| {
+                state.apply_operation(operation)
+            })?;
+        if intermediate.consensus_leader_order != next.consensus_leader_order {
+            operations.push(ReservedStateOperation::ReorderLeaders(
+                next.consensus_leader_order.clone(),
+            ));
+        }
+        if self.version != next.version {
+            let operation = ReservedStateOperation::UpgradeVersion(next.version.clone());
+            intermediate.apply_operation(&operation)?;
+            operations.push(operation);
+        }
+        // The order of the members may not round-trip (e.g., if this state is not sorted).
+        if self.apply_operations(&operations)? != *next {
+            return Err("the change can't be represented by the operations".to_string());
+        }
+        Ok(operations)
     }
 
-    pub fn apply_undelegate(&mut self, _tx: &TxUndelegate) -> Result<Self, String> {
-        unimplemented!()
+    /// Renders the change from this state to `next` as a human-readable diff,
+    /// one operation per line.
+    pub fn render_diff(&self, next: &ReservedState) -> Result<String, String> {
+        let mut lines = Vec::new();
+        for operation in self.operations_to(next)? {
+            lines.push(match operation {
+                ReservedStateOperation::AddMember(member) => {
+                    let mut line = format!(
+                        "+ member {} ({}): governance voting power {}, consensus voting power {}",
+                        member.name,
+                        member.public_key,
+                        member.governance_voting_power,
+                        member.consensus_voting_power
+                    );
+                    if let Some(delegatee) = &member.governance_delegations {
+                        line += &format!(", governance delegated to {}", delegatee);
+                    }
+                    if let Some(delegatee) = &member.consensus_delegations {
+                        line += &format!(", consensus delegated to {}", delegatee);
+                    }
+                    line
+                }
+                ReservedStateOperation::RemoveMember(name) => format!("- member {}", name),
+                ReservedStateOperation::ChangeVotingPower {
+                    name,
+                    governance_voting_power,
+                    consensus_voting_power,
+                } => {
+                    let member = self
+                        .members
+                        .iter()
+                        .find(|m| m.name == name)
+                        .expect("derived from the members");
+                    format!(
+                        "~ member {}: governance voting power {} -> {}, consensus voting power {} -> {}",
+                        name,
+                        member.governance_voting_power,
+                        governance_voting_power,
+                        member.consensus_voting_power,
+                        consensus_voting_power
+                    )
+                }
+                ReservedStateOperation::ReorderLeaders(order) => format!(
+                    "~ consensus leader order: [{}] -> [{}]",
+                    self.consensus_leader_order.join(", "),
+                    order.join(", ")
+                ),
+                ReservedStateOperation::UpgradeVersion(version) => {
+                    format!("~ version: {} -> {}", self.version, version)
+                }
+            });
+        }
+        Ok(lines.join("\n"))
     }
 
     pub fn query_name(&self, public_key: &PublicKey) -> Option<MemberName> {
@@ -90,6 +674,185 @@ impl ReservedState {
         }
         None
     }
+
+    /// Returns the files of the reserved directory as `(path, content)`,
+    /// where the path is relative to the root of the repository.
+    ///
+    /// This is the single source of the on-disk format of the reserved state,
+    /// which is also committed in `BlockHeader::repository_merkle_root`.
+    pub fn to_files(&self) -> Vec<(String, Vec<u8>)> {
+        let mut files = vec![
+            (
+                format!("{}/genesis_info.json", RESERVED_DIRECTORY),
+                serde_spb::to_string(&self.genesis_info).unwrap(),
+            ),
+            (
+                format!("{}/consensus_leader_order.json", RESERVED_DIRECTORY),
+                serde_spb::to_string(&self.consensus_leader_order).unwrap(),
+            ),
+            (
+                format!("{}/version", RESERVED_DIRECTORY),
+                serde_spb::to_string(&self.version).unwrap(),
+            ),
+        ];
+        for member in &self.members {
+            files.push((
+                format!("{}/members/{}.json", RESERVED_DIRECTORY, member.name),
+                serde_spb::to_string(member).unwrap(),
+            ));
+        }
+        files
+            .into_iter()
+            .map(|(path, content)| (path, content.into_bytes()))
+            .collect()
+    }
+}
+
+/// What the delegation conditions are evaluated against.
+#[derive(Debug, Clone, Copy)]
+struct DelegationContext<'a> {
+    height: BlockHeight,
+    timestamp: Timestamp,
+    base_validator_set_hash: Hash256,
+    /// The validators who signed the finalization proof of the last finalized block, if known.
+    last_signers: Option<&'a BTreeSet<PublicKey>>,
+}
+
+impl ReservedState {
+    fn delegation_context<'a>(
+        &self,
+        height: BlockHeight,
+        timestamp: Timestamp,
+        last_signers: Option<&'a BTreeSet<PublicKey>>,
+    ) -> DelegationContext<'a> {
+        DelegationContext {
+            height,
+            timestamp,
+            base_validator_set_hash: self.get_base_validator_set_hash(),
+            last_signers,
+        }
+    }
+
+    /// Checks whether any of the conditions of the delegation to `delegatee` is met.
+    fn is_released(
+        &self,
+        conditions: &[DelegationCondition],
+        delegatee: &MemberName,
+        context: &DelegationContext,
+    ) -> bool {
+        conditions.iter().any(|condition| match condition {
+            DelegationCondition::UnlockAtHeight(h) => context.height >= *h,
+            DelegationCondition::UnlockAtTimestamp(t) => context.timestamp >= *t,
+            DelegationCondition::UnlockIfDelegateeInactive => {
+                match (context.last_signers, self.query_public_key(delegatee)) {
+                    (Some(signers), Some(public_key)) => !signers.contains(&public_key),
+                    _ => false,
+                }
+            }
+            DelegationCondition::UnlockIfValidatorSetChanges(hash) => {
+                &context.base_validator_set_hash != hash
+            }
+        })
+    }
+
+    /// Returns the delegatee of the consensus voting power of the member,
+    /// or `None` if it is not delegated or the delegation is released.
+    fn effective_consensus_delegatee<'a>(
+        &self,
+        member: &'a Member,
+        context: &DelegationContext,
+    ) -> Option<&'a MemberName> {
+        member.consensus_delegations.as_ref().filter(|delegatee| {
+            !self.is_released(&member.consensus_delegation_conditions, delegatee, context)
+        })
+    }
+
+    /// Returns the delegatee of the governance voting power of the member,
+    /// or `None` if it is not delegated or the delegation is released.
+    ///
+    /// The activity of the delegatee is not evaluated, since it is about the consensus.
+    fn effective_governance_delegatee<'a>(
+        &self,
+        member: &'a Member,
+        context: &DelegationContext,
+    ) -> Option<&'a MemberName> {
+        let context = DelegationContext {
+            last_signers: None,
+            ..*context
+        };
+        member.governance_delegations.as_ref().filter(|delegatee| {
+            !self.is_released(
+                &member.governance_delegation_conditions,
+                delegatee,
+                &context,
+            )
+        })
+    }
+}
+
+impl Misbehavior {
+    /// Returns the height of the consensus where the misbehavior happened.
+    pub fn height(&self) -> BlockHeight {
+        match self {
+            Misbehavior::DoubleVote(vote, _) => vote.vote.height,
+            Misbehavior::DoubleProposal(proposal, _) => proposal.proposal.height,
+        }
+    }
+
+    /// Verifies the evidence for the chain of the given genesis block hash, returning the offender.
+    pub fn verify(&self, genesis_hash: &Hash256) -> Result<&PublicKey, String> {
+        match self {
+            Misbehavior::DoubleVote(first, second) => {
+                for signed_vote in [first, second] {
+                    if &signed_vote.vote.genesis_hash != genesis_hash {
+                        return Err("the vote is for another chain".to_string());
+                    }
+                    signed_vote
+                        .signature
+                        .verify(&signed_vote.vote)
+                        .map_err(|e| format!("invalid vote signature: {}", e))?;
+                }
+                let offender = first.signature.signer();
+                if offender != second.signature.signer() {
+                    return Err("the votes are signed by different validators".to_string());
+                }
+                let (first, second) = (&first.vote, &second.vote);
+                if first.kind != second.kind
+                    || first.height != second.height
+                    || first.round != second.round
+                {
+                    return Err("the votes are not for the same step".to_string());
+                }
+                if first.block_hash == second.block_hash {
+                    return Err("the votes are not conflicting".to_string());
+                }
+                Ok(offender)
+            }
+            Misbehavior::DoubleProposal(first, second) => {
+                for signed_proposal in [first, second] {
+                    if &signed_proposal.proposal.genesis_hash != genesis_hash {
+                        return Err("the proposal is for another chain".to_string());
+                    }
+                    signed_proposal
+                        .signature
+                        .verify(&signed_proposal.proposal)
+                        .map_err(|e| format!("invalid proposal signature: {}", e))?;
+                }
+                let offender = first.signature.signer();
+                if offender != second.signature.signer() {
+                    return Err("the proposals are signed by different validators".to_string());
+                }
+                let (first, second) = (&first.proposal, &second.proposal);
+                if first.height != second.height || first.round != second.round {
+                    return Err("the proposals are not for the same round".to_string());
+                }
+                if first.block_hash == second.block_hash {
+                    return Err("the proposals are not conflicting".to_string());
+                }
+                Ok(offender)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -98,6 +861,9 @@ mod tests {
     use simperby_test_suite::setup_test;
     use std::collections::HashSet;
 
+    /// The encoding of the test chains, whose version is `0.1.0`.
+    const ENCODING: HashEncoding = HashEncoding::Json;
+
     fn create_member(keys: Vec<(PublicKey, PrivateKey)>, member_num: u8) -> Member {
         Member {
             public_key: keys[member_num as usize].0.clone(),
@@ -106,6 +872,9 @@ mod tests {
             consensus_voting_power: 1,
             governance_delegations: None,
             consensus_delegations: None,
+            governance_delegation_conditions: vec![],
+            consensus_delegation_conditions: vec![],
+            proof_of_possession: None,
         }
     }
 
@@ -121,6 +890,9 @@ mod tests {
             consensus_voting_power: 1,
             governance_delegations: None,
             consensus_delegations: Some(format!("member-{:04}", delegatee_member_num)),
+            governance_delegation_conditions: vec![],
+            consensus_delegation_conditions: vec![],
+            proof_of_possession: None,
         }
     }
 
@@ -136,6 +908,9 @@ mod tests {
             consensus_voting_power: 1,
             governance_delegations: Some(format!("member-{:04}", delegatee_member_num)),
             consensus_delegations: None,
+            governance_delegation_conditions: vec![],
+            consensus_delegation_conditions: vec![],
+            proof_of_possession: None,
         }
     }
 
@@ -154,7 +929,7 @@ mod tests {
         ];
         let genesis_header = BlockHeader {
             author: PublicKey::zero(),
-            prev_block_finalization_proof: Vec::new(),
+            prev_block_finalization_proof: FinalizationProof::default(),
             previous_hash: Hash256::zero(),
             height: 0,
             timestamp: 0,
@@ -171,7 +946,8 @@ mod tests {
             genesis_proof: keys
                 .iter()
                 .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
-                .collect::<Vec<_>>(),
+                .collect::<Vec<_>>()
+                .into(),
             chain_name: "test-chain".to_string(),
         };
         let reserved_state = ReservedState {
@@ -181,7 +957,7 @@ mod tests {
             version: "0.1.0".to_string(),
         };
         assert_eq!(
-            reserved_state.get_validator_set().unwrap(),
+            reserved_state.get_validator_set(0, 0, None).unwrap(),
             vec![(keys[3].0.clone(), 4),]
         );
     }
@@ -201,7 +977,7 @@ mod tests {
         ];
         let genesis_header = BlockHeader {
             author: PublicKey::zero(),
-            prev_block_finalization_proof: Vec::new(),
+            prev_block_finalization_proof: FinalizationProof::default(),
             previous_hash: Hash256::zero(),
             height: 0,
             timestamp: 0,
@@ -218,7 +994,8 @@ mod tests {
             genesis_proof: keys
                 .iter()
                 .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
-                .collect::<Vec<_>>(),
+                .collect::<Vec<_>>()
+                .into(),
             chain_name: "test-chain".to_string(),
         };
         let reserved_state = ReservedState {
@@ -228,7 +1005,7 @@ mod tests {
             version: "0.1.0".to_string(),
         };
         assert_eq!(
-            reserved_state.get_validator_set().unwrap(),
+            reserved_state.get_validator_set(0, 0, None).unwrap(),
             vec![(keys[1].0.clone(), 2), (keys[3].0.clone(), 2),]
         );
     }
@@ -248,7 +1025,7 @@ mod tests {
         ];
         let genesis_header = BlockHeader {
             author: PublicKey::zero(),
-            prev_block_finalization_proof: Vec::new(),
+            prev_block_finalization_proof: FinalizationProof::default(),
             previous_hash: Hash256::zero(),
             height: 0,
             timestamp: 0,
@@ -265,7 +1042,8 @@ mod tests {
             genesis_proof: keys
                 .iter()
                 .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
-                .collect::<Vec<_>>(),
+                .collect::<Vec<_>>()
+                .into(),
             chain_name: "test-chain".to_string(),
         };
         let reserved_state = ReservedState {
@@ -278,7 +1056,7 @@ mod tests {
             version: "0.1.0".to_string(),
         };
         assert_eq!(
-            reserved_state.get_governance_set().unwrap(),
+            reserved_state.get_governance_set(0, 0).unwrap(),
             vec![(keys[3].0.clone(), 4),]
         );
     }
@@ -298,7 +1076,7 @@ mod tests {
         ];
         let genesis_header = BlockHeader {
             author: PublicKey::zero(),
-            prev_block_finalization_proof: Vec::new(),
+            prev_block_finalization_proof: FinalizationProof::default(),
             previous_hash: Hash256::zero(),
             height: 0,
             timestamp: 0,
@@ -315,7 +1093,8 @@ mod tests {
             genesis_proof: keys
                 .iter()
                 .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
-                .collect::<Vec<_>>(),
+                .collect::<Vec<_>>()
+                .into(),
             chain_name: "test-chain".to_string(),
         };
         let reserved_state = ReservedState {
@@ -329,7 +1108,7 @@ mod tests {
         };
         assert_eq!(
             reserved_state
-                .get_governance_set()
+                .get_governance_set(0, 0)
                 .unwrap()
                 .into_iter()
                 .collect::<HashSet<_>>(),
@@ -338,4 +1117,895 @@ mod tests {
                 .collect::<HashSet<_>>()
         );
     }
+
+    fn create_reserved_state(
+        keys: &[(PublicKey, PrivateKey)],
+        members: Vec<Member>,
+        consensus_leader_order: Vec<MemberName>,
+    ) -> ReservedState {
+        let genesis_header = BlockHeader {
+            author: PublicKey::zero(),
+            prev_block_finalization_proof: FinalizationProof::default(),
+            previous_hash: Hash256::zero(),
+            height: 0,
+            timestamp: 0,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: members
+                .iter()
+                .map(|member| (member.public_key.clone(), member.consensus_voting_power))
+                .collect::<Vec<_>>(),
+            version: "0.1.0".to_string(),
+        };
+        let genesis_info = GenesisInfo {
+            header: genesis_header.clone(),
+            genesis_proof: keys
+                .iter()
+                .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
+                .collect::<Vec<_>>()
+                .into(),
+            chain_name: "test-chain".to_string(),
+        };
+        ReservedState {
+            genesis_info,
+            members,
+            consensus_leader_order,
+            version: "0.1.0".to_string(),
+        }
+    }
+
+    fn create_delegate_tx(
+        keys: &[(PublicKey, PrivateKey)],
+        delegator: usize,
+        delegatee: usize,
+        governance: bool,
+        conditions: Vec<DelegationCondition>,
+    ) -> TxDelegate {
+        let data = (
+            keys[delegator].0.clone(),
+            keys[delegatee].0.clone(),
+            governance,
+            conditions.clone(),
+            1,
+        );
+        TxDelegate {
+            delegator: keys[delegator].0.clone(),
+            delegatee: keys[delegatee].0.clone(),
+            governance,
+            conditions,
+            block_height: 1,
+            proof: TypedSignature::sign_with(&data, ENCODING, &keys[delegator].1).unwrap(),
+            timestamp: 0,
+        }
+    }
+
+    fn create_undelegate_tx(keys: &[(PublicKey, PrivateKey)], delegator: usize) -> TxUndelegate {
+        let data = (keys[delegator].0.clone(), 1);
+        TxUndelegate {
+            delegator: keys[delegator].0.clone(),
+            block_height: 1,
+            proof: TypedSignature::sign_with(&data, ENCODING, &keys[delegator].1).unwrap(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn delegate_and_undelegate() {
+        setup_test();
+        let keys = (0..4)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = (0..4)
+            .map(|i| create_member(keys.clone(), i))
+            .collect::<Vec<_>>();
+        let reserved_state = create_reserved_state(
+            &keys,
+            members,
+            vec!["member-0001".to_string(), "member-0003".to_string()],
+        );
+        let delegated = reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 0, 1, true, vec![]), ENCODING)
+            .unwrap()
+            .apply_delegate(&create_delegate_tx(&keys, 2, 3, false, vec![]), ENCODING)
+            .unwrap();
+        assert_eq!(
+            delegated.get_validator_set(0, 0, None).unwrap(),
+            vec![(keys[1].0.clone(), 2), (keys[3].0.clone(), 2)]
+        );
+        assert_eq!(
+            delegated.members[0].governance_delegations,
+            Some("member-0001".to_string())
+        );
+        assert_eq!(delegated.members[2].governance_delegations, None);
+        let undelegated = delegated
+            .apply_undelegate(&create_undelegate_tx(&keys, 0), ENCODING)
+            .unwrap()
+            .apply_undelegate(&create_undelegate_tx(&keys, 2), ENCODING)
+            .unwrap();
+        assert_eq!(undelegated, reserved_state);
+    }
+
+    #[test]
+    fn invalid_delegations() {
+        setup_test();
+        let keys = (0..4)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = vec![
+            create_member_with_consensus_delegation(keys.clone(), 0, 1),
+            create_member(keys.clone(), 1),
+            create_member(keys.clone(), 2),
+        ];
+        let reserved_state = create_reserved_state(
+            &keys[0..3],
+            members,
+            vec!["member-0001".to_string(), "member-0002".to_string()],
+        );
+        // Self-delegation
+        reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 2, 2, false, vec![]), ENCODING)
+            .unwrap_err();
+        // Delegation to a delegator
+        reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 2, 0, false, vec![]), ENCODING)
+            .unwrap_err();
+        // Delegation from a delegatee
+        reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 1, 2, false, vec![]), ENCODING)
+            .unwrap_err();
+        // Delegation cycle
+        reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 1, 0, false, vec![]), ENCODING)
+            .unwrap_err();
+        // Delegation to an unknown member
+        reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 2, 3, false, vec![]), ENCODING)
+            .unwrap_err();
+        // Delegation signed by someone else
+        let mut tx = create_delegate_tx(&keys, 2, 1, false, vec![]);
+        tx.proof = create_delegate_tx(&keys, 1, 1, false, vec![]).proof;
+        reserved_state.apply_delegate(&tx, ENCODING).unwrap_err();
+        // Delegation signed in the encoding of another protocol version
+        reserved_state
+            .apply_delegate(
+                &create_delegate_tx(&keys, 2, 1, false, vec![]),
+                HashEncoding::Canonical,
+            )
+            .unwrap_err();
+        // Undelegation of a member that has not delegated
+        reserved_state
+            .apply_undelegate(&create_undelegate_tx(&keys, 2), ENCODING)
+            .unwrap_err();
+        // Valid one
+        reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 2, 1, false, vec![]), ENCODING)
+            .unwrap();
+    }
+
+    #[test]
+    fn expiring_delegations() {
+        setup_test();
+        let keys = (0..3)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = (0..3)
+            .map(|i| create_member(keys.clone(), i))
+            .collect::<Vec<_>>();
+        let reserved_state = create_reserved_state(
+            &keys,
+            members,
+            (0..3).map(|i| format!("member-{:04}", i)).collect(),
+        );
+        // Delegations that are released immediately are rejected.
+        reserved_state
+            .apply_delegate(
+                &create_delegate_tx(
+                    &keys,
+                    0,
+                    1,
+                    true,
+                    vec![DelegationCondition::UnlockAtHeight(1)],
+                ),
+                ENCODING,
+            )
+            .unwrap_err();
+        let delegated = reserved_state
+            .apply_delegate(
+                &create_delegate_tx(
+                    &keys,
+                    0,
+                    1,
+                    true,
+                    vec![DelegationCondition::UnlockAtHeight(10)],
+                ),
+                ENCODING,
+            )
+            .unwrap()
+            .apply_delegate(
+                &create_delegate_tx(
+                    &keys,
+                    2,
+                    1,
+                    false,
+                    vec![DelegationCondition::UnlockAtTimestamp(1000)],
+                ),
+                ENCODING,
+            )
+            .unwrap();
+        assert_eq!(
+            delegated.get_validator_set(9, 999, None).unwrap(),
+            vec![(keys[1].0.clone(), 3)]
+        );
+        assert_eq!(
+            delegated.get_validator_set(9, 1000, None).unwrap(),
+            vec![(keys[1].0.clone(), 2), (keys[2].0.clone(), 1)]
+        );
+        assert_eq!(
+            delegated.get_validator_set(10, 1000, None).unwrap(),
+            reserved_state.get_validator_set(10, 1000, None).unwrap()
+        );
+        assert_eq!(
+            delegated
+                .get_governance_set(9, 0)
+                .unwrap()
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            vec![(keys[1].0.clone(), 2), (keys[2].0.clone(), 1)]
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+        assert_eq!(
+            delegated
+                .get_governance_set(10, 0)
+                .unwrap()
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            reserved_state
+                .get_governance_set(10, 0)
+                .unwrap()
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn conditional_delegations() {
+        setup_test();
+        let keys = (0..3)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = (0..3)
+            .map(|i| create_member(keys.clone(), i))
+            .collect::<Vec<_>>();
+        let reserved_state = create_reserved_state(
+            &keys,
+            members,
+            (0..3).map(|i| format!("member-{:04}", i)).collect(),
+        );
+        let undelegated = reserved_state.get_validator_set(1, 0, None).unwrap();
+
+        // Released while the delegatee doesn't sign the finalization proof.
+        let delegated = reserved_state
+            .apply_delegate(
+                &create_delegate_tx(
+                    &keys,
+                    0,
+                    1,
+                    true,
+                    vec![DelegationCondition::UnlockIfDelegateeInactive],
+                ),
+                ENCODING,
+            )
+            .unwrap();
+        let signers = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|i| keys[*i].0.clone())
+                .collect::<BTreeSet<_>>()
+        };
+        assert_eq!(
+            delegated.get_validator_set(1, 0, None).unwrap(),
+            vec![(keys[1].0.clone(), 2), (keys[2].0.clone(), 1)]
+        );
+        assert_eq!(
+            delegated
+                .get_validator_set(1, 0, Some(&signers(&[1, 2])))
+                .unwrap(),
+            vec![(keys[1].0.clone(), 2), (keys[2].0.clone(), 1)]
+        );
+        assert_eq!(
+            delegated
+                .get_validator_set(1, 0, Some(&signers(&[0, 2])))
+                .unwrap(),
+            undelegated
+        );
+        // The governance voting power stays delegated.
+        assert_eq!(
+            delegated
+                .get_governance_set(1, 0)
+                .unwrap()
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            vec![(keys[1].0.clone(), 2), (keys[2].0.clone(), 1)]
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+
+        // Released once the validator set changes.
+        let hash = reserved_state.get_base_validator_set_hash();
+        reserved_state
+            .apply_delegate(
+                &create_delegate_tx(
+                    &keys,
+                    2,
+                    1,
+                    false,
+                    vec![DelegationCondition::UnlockIfValidatorSetChanges(
+                        Hash256::hash("other"),
+                    )],
+                ),
+                ENCODING,
+            )
+            .unwrap_err();
+        let delegated = delegated
+            .apply_delegate(
+                &create_delegate_tx(
+                    &keys,
+                    2,
+                    1,
+                    false,
+                    vec![DelegationCondition::UnlockIfValidatorSetChanges(hash)],
+                ),
+                ENCODING,
+            )
+            .unwrap();
+        // Delegations don't change the validator set of the condition.
+        assert_eq!(delegated.get_base_validator_set_hash(), hash);
+        assert_eq!(
+            delegated.get_validator_set(1, 0, None).unwrap(),
+            vec![(keys[1].0.clone(), 3)]
+        );
+        let changed = delegated
+            .apply_operation(&ReservedStateOperation::ChangeVotingPower {
+                name: "member-0001".to_string(),
+                governance_voting_power: 1,
+                consensus_voting_power: 2,
+            })
+            .unwrap();
+        assert_ne!(changed.get_base_validator_set_hash(), hash);
+        assert_eq!(
+            changed.get_validator_set(1, 0, None).unwrap(),
+            vec![(keys[1].0.clone(), 3), (keys[2].0.clone(), 1)]
+        );
+    }
+
+    #[test]
+    fn validate_reserved_state() {
+        setup_test();
+        let keys = (0..4)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = vec![
+            create_member_with_consensus_delegation(keys.clone(), 0, 1),
+            create_member(keys.clone(), 1),
+            create_member(keys.clone(), 2),
+        ];
+        let reserved_state = create_reserved_state(
+            &keys[0..3],
+            members,
+            vec!["member-0001".to_string(), "member-0002".to_string()],
+        );
+        reserved_state.validate().unwrap();
+
+        let mut state = reserved_state.clone();
+        state.members[2].name = "member-0001".to_string();
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::DuplicateMemberName(
+                "member-0001".to_string()
+            ))
+        );
+        let mut state = reserved_state.clone();
+        state.members[2].public_key = keys[1].0.clone();
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::DuplicatePublicKey(keys[1].0.clone()))
+        );
+        let mut state = reserved_state.clone();
+        state.consensus_leader_order.reverse();
+        assert!(matches!(
+            state.validate(),
+            Err(ReservedStateError::UnsortedLeaderOrder(_, _))
+        ));
+        let mut state = reserved_state.clone();
+        state.consensus_leader_order.push("member-0002".to_string());
+        assert!(matches!(
+            state.validate(),
+            Err(ReservedStateError::UnsortedLeaderOrder(_, _))
+        ));
+        let mut state = reserved_state.clone();
+        state.consensus_leader_order.push("member-0003".to_string());
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::UnknownLeader("member-0003".to_string()))
+        );
+        // The validator set can't be resolved either, instead of panicking.
+        state.members.push(create_member(keys.clone(), 3));
+        state.members[3].name = "member-0004".to_string();
+        state.members[0].consensus_delegations = Some("member-0003".to_string());
+        state.get_validator_set(0, 0, None).unwrap_err();
+        let mut state = reserved_state.clone();
+        state.members[0].governance_delegations = Some("member-0003".to_string());
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::UnknownDelegatee(
+                "member-0000".to_string(),
+                "member-0003".to_string()
+            ))
+        );
+        let mut state = reserved_state.clone();
+        state.members[2].consensus_delegations = Some("member-0000".to_string());
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::DelegationToDelegator(
+                "member-0002".to_string(),
+                "member-0000".to_string()
+            ))
+        );
+        let mut state = reserved_state.clone();
+        state.members[0].consensus_delegations = Some("member-0000".to_string());
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::DelegationToDelegator(
+                "member-0000".to_string(),
+                "member-0000".to_string()
+            ))
+        );
+        let mut state = reserved_state.clone();
+        for member in &mut state.members {
+            member.consensus_voting_power = 0;
+        }
+        assert_eq!(
+            state.validate(),
+            Err(ReservedStateError::ZeroConsensusVotingPower)
+        );
+        let mut state = reserved_state.clone();
+        state.version = "0.1".to_string();
+        assert!(matches!(
+            state.validate(),
+            Err(ReservedStateError::InvalidVersion(_))
+        ));
+        let mut state = reserved_state.clone();
+        if let FinalizationProof::Signatures(signatures) = &mut state.genesis_info.genesis_proof {
+            signatures.pop();
+            signatures.pop();
+        }
+        assert!(matches!(
+            state.validate(),
+            Err(ReservedStateError::InconsistentGenesisInfo(_))
+        ));
+        let mut state = reserved_state;
+        state.genesis_info.header.height = 1;
+        assert!(matches!(
+            state.validate(),
+            Err(ReservedStateError::InconsistentGenesisInfo(_))
+        ));
+    }
+
+    #[test]
+    fn delegation_to_released_delegator() {
+        setup_test();
+        let keys = (0..3)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let mut members = vec![
+            create_member_with_consensus_delegation(keys.clone(), 0, 1),
+            create_member(keys.clone(), 1),
+            create_member(keys.clone(), 2),
+        ];
+        members[0].consensus_delegation_conditions = vec![DelegationCondition::UnlockAtHeight(1)];
+        let reserved_state = create_reserved_state(
+            &keys,
+            members,
+            (0..3).map(|i| format!("member-{:04}", i)).collect(),
+        );
+        // The released delegation of member-0000 is cleared, keeping the state valid.
+        let delegated = reserved_state
+            .apply_delegate(&create_delegate_tx(&keys, 2, 0, false, vec![]), ENCODING)
+            .unwrap();
+        delegated.validate().unwrap();
+        assert_eq!(delegated.members[0].consensus_delegations, None);
+    }
+
+    #[test]
+    fn reserved_state_operations() {
+        setup_test();
+        let keys = (0..5)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = (0..4)
+            .map(|i| create_member(keys.clone(), i))
+            .collect::<Vec<_>>();
+        let reserved_state = create_reserved_state(
+            &keys[0..4],
+            members,
+            (0..4).map(|i| format!("member-{:04}", i)).collect(),
+        );
+        let operations =
+            vec![
+                ReservedStateOperation::RemoveMember("member-0002".to_string()),
+                ReservedStateOperation::AddMember(Box::new(
+                    create_member_with_consensus_delegation(keys.clone(), 4, 1),
+                )),
+                ReservedStateOperation::ChangeVotingPower {
+                    name: "member-0001".to_string(),
+                    governance_voting_power: 1,
+                    consensus_voting_power: 5,
+                },
+                ReservedStateOperation::ReorderLeaders(vec![
+                    "member-0001".to_string(),
+                    "member-0003".to_string(),
+                ]),
+                ReservedStateOperation::UpgradeVersion("0.2.0".to_string()),
+            ];
+        let next = reserved_state.apply_operations(&operations).unwrap();
+        assert_eq!(
+            next.members
+                .iter()
+                .map(|m| m.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["member-0000", "member-0001", "member-0003", "member-0004"]
+        );
+        assert_eq!(
+            next.get_validator_set(0, 0, None).unwrap(),
+            vec![(keys[1].0.clone(), 6), (keys[3].0.clone(), 1)]
+        );
+        assert_eq!(reserved_state.operations_to(&next).unwrap(), operations);
+        assert_eq!(
+            reserved_state.render_diff(&next).unwrap(),
+            format!(
+                "- member member-0002\n\
+                 + member member-0004 ({}): governance voting power 1, consensus voting power 1, \
+                 consensus delegated to member-0001\n\
+                 ~ member member-0001: governance voting power 1 -> 1, consensus voting power 1 -> 5\n\
+                 ~ consensus leader order: [member-0000, member-0001, member-0002, member-0003] \
+                 -> [member-0001, member-0003]\n\
+                 ~ version: 0.1.0 -> 0.2.0",
+                keys[4].0
+            )
+        );
+        assert!(reserved_state
+            .operations_to(&reserved_state)
+            .unwrap()
+            .is_empty());
+
+        // A member with a changed public key is removed and added again.
+        let mut rotated = reserved_state.clone();
+        rotated.members[0].public_key = keys[4].0.clone();
+        let operations = reserved_state.operations_to(&rotated).unwrap();
+        assert_eq!(
+            operations,
+            vec![
+                ReservedStateOperation::RemoveMember("member-0000".to_string()),
+                ReservedStateOperation::AddMember(Box::new(rotated.members[0].clone())),
+                ReservedStateOperation::ReorderLeaders(rotated.consensus_leader_order.clone()),
+            ]
+        );
+        assert_eq!(
+            reserved_state.apply_operations(&operations).unwrap(),
+            rotated
+        );
+
+        // Invalid operations
+        reserved_state
+            .apply_operation(&ReservedStateOperation::AddMember(Box::new(create_member(
+                keys.clone(),
+                0,
+            ))))
+            .unwrap_err();
+        reserved_state
+            .apply_operation(&ReservedStateOperation::RemoveMember(
+                "member-0004".to_string(),
+            ))
+            .unwrap_err();
+        reserved_state
+            .apply_operation(&ReservedStateOperation::UpgradeVersion("0.0.1".to_string()))
+            .unwrap_err();
+        reserved_state
+            .apply_operations(&[ReservedStateOperation::RemoveMember(
+                "member-0001".to_string(),
+            )])
+            .unwrap();
+        reserved_state
+            .apply_operations(&[ReservedStateOperation::ReorderLeaders(vec![
+                "member-0004".to_string()
+            ])])
+            .unwrap_err();
+        let mut downgraded = reserved_state.clone();
+        downgraded.version = "0.0.1".to_string();
+        reserved_state.operations_to(&downgraded).unwrap_err();
+        // The members of an unsorted state don't round-trip.
+        let mut unsorted = reserved_state.clone();
+        unsorted.members.swap(0, 1);
+        let mut added = reserved_state.clone();
+        added.members.push(create_member(keys.clone(), 4));
+        unsorted.operations_to(&added).unwrap_err();
+    }
+
+    fn create_signed_vote(
+        keys: &[(PublicKey, PrivateKey)],
+        genesis_hash: Hash256,
+        voter: usize,
+        kind: ConsensusVoteKind,
+        height: BlockHeight,
+        block_hash: Option<Hash256>,
+    ) -> SignedConsensusVote {
+        let vote = ConsensusVote {
+            genesis_hash,
+            kind,
+            height,
+            round: 0,
+            block_hash,
+        };
+        SignedConsensusVote {
+            signature: TypedSignature::sign(&vote, &keys[voter].1).unwrap(),
+            vote,
+        }
+    }
+
+    /// Creates the headers following the genesis header, with the given validator sets.
+    fn create_headers(
+        genesis_header: &BlockHeader,
+        validator_sets: Vec<Vec<(PublicKey, VotingPower)>>,
+    ) -> Vec<BlockHeader> {
+        let mut headers = vec![genesis_header.clone()];
+        for validator_set in validator_sets {
+            let previous_header = headers.last().unwrap();
+            let header = BlockHeader {
+                previous_hash: previous_header.to_hash256(),
+                height: previous_header.height + 1,
+                validator_set,
+                ..previous_header.clone()
+            };
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn report_double_vote() {
+        setup_test();
+        let keys = (0..3)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = vec![
+            create_member_with_consensus_delegation(keys.clone(), 0, 1),
+            create_member(keys.clone(), 1),
+            create_member(keys.clone(), 2),
+        ];
+        let reserved_state = create_reserved_state(
+            &keys,
+            members,
+            (0..3).map(|i| format!("member-{:04}", i)).collect(),
+        );
+        let genesis_header = reserved_state.genesis_info.header.clone();
+        let genesis_hash = genesis_header.to_hash256();
+        // The member 1 is not a validator of the header 1, so neither of the consensus of height 2.
+        let headers = create_headers(
+            &genesis_header,
+            (1..=REPORT_EVIDENCE_WINDOW + 1)
+                .map(|height| {
+                    let mut validator_set = genesis_header.validator_set.clone();
+                    if height == 1 {
+                        validator_set.remove(1);
+                    }
+                    validator_set
+                })
+                .collect(),
+        );
+        // Reports the votes of the given height, when the header of `last_height` is the last one.
+        let report = |first, second, height: BlockHeight, last_height: BlockHeight| {
+            let report = TxReport {
+                misbehavior: Misbehavior::DoubleVote(first, second),
+                headers: headers[height as usize - 1..last_height as usize].to_vec(),
+                timestamp: 0,
+            };
+            reserved_state.apply_report(&report, &headers[last_height as usize])
+        };
+        let (a, b) = (Some(Hash256::hash("a")), Some(Hash256::hash("b")));
+        let prevote = |voter, height, block_hash| {
+            create_signed_vote(
+                &keys,
+                genesis_hash,
+                voter,
+                ConsensusVoteKind::Prevote,
+                height,
+                block_hash,
+            )
+        };
+        let precommit = |voter, height, block_hash| {
+            create_signed_vote(
+                &keys,
+                genesis_hash,
+                voter,
+                ConsensusVoteKind::Precommit,
+                height,
+                block_hash,
+            )
+        };
+        // Not conflicting
+        report(prevote(1, 1, a), prevote(1, 1, a), 1, 1).unwrap_err();
+        // Different rounds
+        let mut other_round = prevote(1, 1, b);
+        other_round.vote.round = 1;
+        other_round.signature = TypedSignature::sign(&other_round.vote, &keys[1].1).unwrap();
+        report(prevote(1, 1, a), other_round, 1, 1).unwrap_err();
+        // Different kinds
+        report(prevote(1, 1, a), precommit(1, 1, b), 1, 1).unwrap_err();
+        // Different voters
+        report(prevote(1, 1, a), prevote(2, 1, b), 1, 1).unwrap_err();
+        // Different heights
+        report(prevote(1, 1, a), prevote(1, 2, b), 1, 2).unwrap_err();
+        // Forged signature
+        let mut forged = prevote(1, 1, b);
+        forged.signature = prevote(1, 1, a).signature;
+        report(prevote(1, 1, a), forged, 1, 1).unwrap_err();
+        // Votes for another chain
+        let other_chain = |block_hash| {
+            create_signed_vote(
+                &keys,
+                Hash256::hash("other chain"),
+                1,
+                ConsensusVoteKind::Prevote,
+                1,
+                block_hash,
+            )
+        };
+        report(other_chain(a), other_chain(b), 1, 1).unwrap_err();
+        // Evidence of the consensus that is not finalized yet
+        reserved_state
+            .apply_report(
+                &TxReport {
+                    misbehavior: Misbehavior::DoubleVote(prevote(1, 2, a), prevote(1, 2, b)),
+                    headers: vec![headers[1].clone()],
+                    timestamp: 0,
+                },
+                &headers[1],
+            )
+            .unwrap_err();
+        // Headers not linked to the last header
+        let mut unlinked_headers = headers[0..2].to_vec();
+        unlinked_headers[0].timestamp = 1;
+        reserved_state
+            .apply_report(
+                &TxReport {
+                    misbehavior: Misbehavior::DoubleVote(prevote(1, 1, a), prevote(1, 1, b)),
+                    headers: unlinked_headers,
+                    timestamp: 0,
+                },
+                &headers[2],
+            )
+            .unwrap_err();
+        // Not a validator of the consensus, even though it is one of the last header
+        report(prevote(1, 2, a), prevote(1, 2, b), 2, 2).unwrap_err();
+        // Expired evidence
+        report(
+            prevote(1, 1, a),
+            prevote(1, 1, b),
+            1,
+            REPORT_EVIDENCE_WINDOW + 1,
+        )
+        .unwrap_err();
+        // Valid evidence, even though the offender is not a validator of the last header
+        report(prevote(1, 1, a), prevote(1, 1, b), 1, 1).unwrap();
+        // Valid evidence at the end of the window
+        let reported = report(
+            precommit(1, 1, a),
+            precommit(1, 1, None),
+            1,
+            REPORT_EVIDENCE_WINDOW,
+        )
+        .unwrap();
+        assert_eq!(reported.members[1].consensus_voting_power, 0);
+        assert_eq!(reported.members[0].consensus_delegations, None);
+        assert_eq!(
+            reported.get_validator_set(0, 0, None).unwrap(),
+            vec![(keys[0].0.clone(), 1), (keys[2].0.clone(), 1)]
+        );
+        // Reporting again
+        reported
+            .apply_report(
+                &TxReport {
+                    misbehavior: Misbehavior::DoubleVote(prevote(1, 1, a), prevote(1, 1, b)),
+                    headers: vec![genesis_header.clone()],
+                    timestamp: 0,
+                },
+                &headers[1],
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn report_double_proposal() {
+        setup_test();
+        let keys = (0..3)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let members = (0..3)
+            .map(|i| create_member(keys.clone(), i))
+            .collect::<Vec<_>>();
+        let reserved_state = create_reserved_state(
+            &keys,
+            members,
+            (0..3).map(|i| format!("member-{:04}", i)).collect(),
+        );
+        let genesis_header = reserved_state.genesis_info.header.clone();
+        let genesis_hash = genesis_header.to_hash256();
+        let headers = create_headers(&genesis_header, vec![genesis_header.validator_set.clone()]);
+        let propose = |proposer: usize, genesis_hash, round, block_hash| {
+            let proposal = ConsensusProposal {
+                genesis_hash,
+                height: 1,
+                round,
+                valid_round: None,
+                block_hash,
+            };
+            SignedConsensusProposal {
+                signature: TypedSignature::sign(&proposal, &keys[proposer].1).unwrap(),
+                proposal,
+            }
+        };
+        let report = |first, second| {
+            reserved_state.apply_report(
+                &TxReport {
+                    misbehavior: Misbehavior::DoubleProposal(first, second),
+                    headers: vec![genesis_header.clone()],
+                    timestamp: 0,
+                },
+                &headers[1],
+            )
+        };
+        let (a, b) = (Hash256::hash("a"), Hash256::hash("b"));
+        // Not conflicting
+        report(
+            propose(1, genesis_hash, 0, a),
+            propose(1, genesis_hash, 0, a),
+        )
+        .unwrap_err();
+        // Different rounds
+        report(
+            propose(1, genesis_hash, 0, a),
+            propose(1, genesis_hash, 1, b),
+        )
+        .unwrap_err();
+        // Different proposers
+        report(
+            propose(1, genesis_hash, 0, a),
+            propose(2, genesis_hash, 0, b),
+        )
+        .unwrap_err();
+        // Forged signature
+        let mut forged = propose(1, genesis_hash, 0, b);
+        forged.signature = propose(1, genesis_hash, 0, a).signature;
+        report(propose(1, genesis_hash, 0, a), forged).unwrap_err();
+        // Proposals for another chain
+        let other_chain = Hash256::hash("other chain");
+        report(propose(1, other_chain, 0, a), propose(1, other_chain, 0, b)).unwrap_err();
+        // Valid evidence
+        let reported = report(
+            propose(1, genesis_hash, 0, a),
+            propose(1, genesis_hash, 0, b),
+        )
+        .unwrap();
+        assert_eq!(reported.members[1].consensus_voting_power, 0);
+    }
 }
@@ -1,6 +1,28 @@
+//! The serialization formats of Simperby.
+//!
+//! - The human-readable JSON (`to_string`, `to_vec`, ...) is for the on-disk files and messages.
+//! - The canonical binary encoding (`to_canonical_vec`) is for hashing and signing
+//! the consensus-critical data, from `CANONICAL_ENCODING_PROTOCOL_VERSION`.
+//!
+//! A block header is hashed with the encoding of its own `version`, and the other data
+//! of a chain with the encoding of the last finalized header (see `HashEncoding`);
+//! thus the encoding switches at the block boundary where the version is upgraded.
+//!
+//! The canonical encoding is [BCS](https://github.com/diem/bcs), which is specified
+//! and reproducible in other languages:
+//! - integers are fixed-width little-endian and `bool` is a single byte (`0` or `1`).
+//! - sequences, strings and byte arrays are prefixed with their length in ULEB128.
+//! - `Option` is a tag byte (`0` for `None`, `1` for `Some`) followed by the value.
+//! - structs and tuples are their fields concatenated in the declaration order.
+//! - enums are the variant index in ULEB128 followed by the variant's fields.
+//! - hashes, public keys and signatures are byte arrays (not hex strings).
+use crate::SIMPERBY_CORE_PROTOCOL_VERSION;
 use serde::{de::DeserializeOwned, ser::Serialize};
 use serde_json::Error;
 
+/// The first protocol version that hashes (and so signs) the data with the canonical encoding.
+pub const CANONICAL_ENCODING_PROTOCOL_VERSION: &str = "0.2.0";
+
 pub fn to_string<T: Serialize>(t: &T) -> Result<String, Error> {
     serde_json::to_string_pretty(t)
 }
@@ -16,3 +38,178 @@ pub fn to_vec<T: Serialize>(t: &T) -> Result<Vec<u8>, Error> {
 pub fn from_slice<T: DeserializeOwned>(s: &[u8]) -> Result<T, Error> {
     serde_json::from_slice(s)
 }
+
+/// Encodes the data in the canonical binary encoding.
+pub fn to_canonical_vec<T: Serialize>(t: &T) -> Result<Vec<u8>, bcs::Error> {
+    bcs::to_bytes(t)
+}
+
+/// Decodes the data from the canonical binary encoding.
+pub fn from_canonical_slice<T: DeserializeOwned>(s: &[u8]) -> Result<T, bcs::Error> {
+    bcs::from_bytes(s)
+}
+
+/// The encoding of the data to be hashed and signed.
+///
+/// Use `BlockHeader::hash_encoding()` of the last finalized header to hash the data of the chain
+/// (e.g., with `ToHash256::to_hash256_with()` and `TypedSignature::sign_with()`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashEncoding {
+    /// The pretty JSON, same as `to_vec()`.
+    Json,
+    /// The canonical binary encoding, same as `to_canonical_vec()`.
+    Canonical,
+}
+
+impl HashEncoding {
+    /// Returns the encoding used by the given protocol version (e.g., `0.2.3`).
+    pub fn for_protocol_version(version: &str) -> Result<Self, String> {
+        if parse_version(version)? >= parse_version(CANONICAL_ENCODING_PROTOCOL_VERSION)? {
+            Ok(HashEncoding::Canonical)
+        } else {
+            Ok(HashEncoding::Json)
+        }
+    }
+}
+
+/// Parses a semantic version (e.g., `0.2.3`) into `(major, minor, patch)`.
+pub(crate) fn parse_version(version: &str) -> Result<(u64, u64, u64), String> {
+    let numbers = version
+        .split('.')
+        .map(|x| x.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid protocol version: {}", version))?;
+    if let [major, minor, patch] = numbers[..] {
+        Ok((major, minor, patch))
+    } else {
+        Err(format!("invalid protocol version: {}", version))
+    }
+}
+
+/// Encodes the data to be hashed with the given encoding.
+pub fn to_hashable_vec_with<T: Serialize>(
+    t: &T,
+    encoding: HashEncoding,
+) -> Result<Vec<u8>, String> {
+    match encoding {
+        HashEncoding::Json => to_vec(t).map_err(|e| e.to_string()),
+        HashEncoding::Canonical => to_canonical_vec(t).map_err(|e| e.to_string()),
+    }
+}
+
+/// Encodes the data to be hashed with the encoding of `SIMPERBY_CORE_PROTOCOL_VERSION`.
+///
+/// This is only for the data that doesn't belong to a chain (e.g., network messages).
+/// The data of a chain must be hashed with the encoding of the chain's protocol version instead;
+/// see `to_hashable_vec_with()`.
+pub fn to_hashable_vec<T: Serialize>(t: &T) -> Result<Vec<u8>, String> {
+    to_hashable_vec_with(
+        t,
+        HashEncoding::for_protocol_version(SIMPERBY_CORE_PROTOCOL_VERSION)?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn hash_encoding_by_protocol_version() {
+        assert_eq!(
+            HashEncoding::for_protocol_version("0.1.0").unwrap(),
+            HashEncoding::Json
+        );
+        assert_eq!(
+            HashEncoding::for_protocol_version("0.2.0").unwrap(),
+            HashEncoding::Canonical
+        );
+        assert_eq!(
+            HashEncoding::for_protocol_version("0.10.0").unwrap(),
+            HashEncoding::Canonical
+        );
+        HashEncoding::for_protocol_version("0.2").unwrap_err();
+        HashEncoding::for_protocol_version("0.2.x").unwrap_err();
+    }
+
+    #[test]
+    fn canonical_encoding_layout() {
+        let data: (u64, String, Option<bool>, Hash256) =
+            (1, "ab".to_owned(), Some(true), Hash256::zero());
+        let encoded = to_canonical_vec(&data).unwrap();
+        let expected = [
+            vec![1, 0, 0, 0, 0, 0, 0, 0],
+            vec![2, b'a', b'b'],
+            vec![1, 1],
+            vec![32],
+            vec![0; 32],
+        ]
+        .concat();
+        assert_eq!(encoded, expected);
+        assert_eq!(
+            from_canonical_slice::<(u64, String, Option<bool>, Hash256)>(&encoded).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn canonical_encoding_enum_variant() {
+        let encoded = to_canonical_vec(&DelegationCondition::UnlockAtTimestamp(-1)).unwrap();
+        assert_eq!(encoded, [vec![1], vec![0xff; 8]].concat());
+    }
+
+    #[test]
+    fn canonical_encoding_key_and_signature() {
+        let (public_key, private_key) = generate_keypair("hello world");
+        let signature = TypedSignature::sign(&"hello world".to_owned(), &private_key).unwrap();
+        let encoded = to_canonical_vec(&public_key).unwrap();
+        assert_eq!(encoded.len(), 1 + 33);
+        assert_eq!(
+            from_canonical_slice::<PublicKey>(&encoded).unwrap(),
+            public_key
+        );
+        let encoded = to_canonical_vec(&signature).unwrap();
+        assert_eq!(encoded.len(), 1 + 65 + 1 + 33);
+        assert_eq!(
+            from_canonical_slice::<TypedSignature<String>>(&encoded).unwrap(),
+            signature
+        );
+    }
+
+    #[test]
+    fn finalization_proof_encoding() {
+        let signatures: Vec<TypedSignature<BlockHeader>> =
+            vec![TypedSignature::new(Signature::zero(), PublicKey::zero())];
+        let proof = FinalizationProof::from(signatures.clone());
+        // The bare list in JSON as before, and a tagged enum in the canonical encoding.
+        assert_eq!(to_string(&proof).unwrap(), to_string(&signatures).unwrap());
+        assert_eq!(
+            from_str::<FinalizationProof>(&to_string(&proof).unwrap()).unwrap(),
+            proof
+        );
+        let encoded = to_canonical_vec(&proof).unwrap();
+        assert_eq!(
+            encoded,
+            [vec![0], to_canonical_vec(&signatures).unwrap()].concat()
+        );
+        assert_eq!(
+            from_canonical_slice::<FinalizationProof>(&encoded).unwrap(),
+            proof
+        );
+
+        let proof = FinalizationProof::Aggregated(AggregatedFinalizationProof {
+            signers: vec![0b101],
+            signature: Signature::zero(),
+        });
+        assert_eq!(
+            from_str::<FinalizationProof>(&to_string(&proof).unwrap()).unwrap(),
+            proof
+        );
+        let encoded = to_canonical_vec(&proof).unwrap();
+        assert_eq!(encoded[0], 1);
+        assert_eq!(
+            from_canonical_slice::<FinalizationProof>(&encoded).unwrap(),
+            proof
+        );
+    }
+}
@@ -7,7 +7,6 @@ pub type Timestamp = i64;
 /// A block height. The genesis block is at height 0.
 pub type BlockHeight = u64;
 pub type ConsensusRound = u64;
-pub type FinalizationProof = Vec<TypedSignature<BlockHeader>>;
 pub type MemberName = String;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -24,11 +23,197 @@ pub struct Member {
     /// If this member delegated its governance consensus power to another member,
     /// the delegatee.
     pub consensus_delegations: Option<MemberName>,
-    // TODO: add various conditions for each delegation.
-    // - Unlock-Automatically-After-N-Blocks
-    // - Unlock-Automatically-After-T-Seconds
-    // - Unlock-If-The-Delegatee-Is-Not-Active
-    // - Unlock-If-The-Validator-Set-Changes
+    /// The conditions that automatically release `governance_delegations`.
+    ///
+    /// The delegation is released if any of them is met.
+    #[serde(default)]
+    pub governance_delegation_conditions: Vec<DelegationCondition>,
+    /// The conditions that automatically release `consensus_delegations`.
+    ///
+    /// The delegation is released if any of them is met.
+    #[serde(default)]
+    pub consensus_delegation_conditions: Vec<DelegationCondition>,
+    /// The proof of possession of the private key (see `Signature::prove_possession()`).
+    ///
+    /// It is required for a BLS12-381 public key so that the signatures can be aggregated safely.
+    #[serde(default)]
+    pub proof_of_possession: Option<Signature>,
+}
+
+/// A condition that automatically releases a delegation,
+/// giving the voting power back to the delegator.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum DelegationCondition {
+    /// Released from the given block height (inclusive).
+    UnlockAtHeight(BlockHeight),
+    /// Released from the given timestamp (inclusive).
+    UnlockAtTimestamp(Timestamp),
+    /// Released while the delegatee is not active, i.e., it didn't sign
+    /// the finalization proof of the last finalized block.
+    ///
+    /// It releases only the consensus voting power, and the delegation comes back
+    /// once the delegatee signs again.
+    UnlockIfDelegateeInactive,
+    /// Released while the validator set without the delegations
+    /// (see `ReservedState::get_base_validator_set_hash()`) differs from the one of the given hash,
+    /// which is the one at the time of the delegation.
+    UnlockIfValidatorSetChanges(Hash256),
+}
+
+/// A proof that a block header is finalized by its validators.
+///
+/// In human-readable formats it is the bare list of signatures or the bare aggregated proof,
+/// so that the existing files remain valid. Otherwise it is a tagged enum.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FinalizationProof {
+    /// The signatures of the signers, one for each.
+    Signatures(Vec<TypedSignature<BlockHeader>>),
+    /// One aggregated signature of the signers, which doesn't grow with the validator set.
+    Aggregated(AggregatedFinalizationProof),
+}
+
+impl Default for FinalizationProof {
+    fn default() -> Self {
+        FinalizationProof::Signatures(Vec::new())
+    }
+}
+
+impl From<Vec<TypedSignature<BlockHeader>>> for FinalizationProof {
+    fn from(signatures: Vec<TypedSignature<BlockHeader>>) -> Self {
+        FinalizationProof::Signatures(signatures)
+    }
+}
+
+impl FromIterator<TypedSignature<BlockHeader>> for FinalizationProof {
+    fn from_iter<I: IntoIterator<Item = TypedSignature<BlockHeader>>>(iter: I) -> Self {
+        FinalizationProof::Signatures(iter.into_iter().collect())
+    }
+}
+
+impl Serialize for FinalizationProof {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        match (self, serializer.is_human_readable()) {
+            (FinalizationProof::Signatures(x), true) => x.serialize(serializer),
+            (FinalizationProof::Aggregated(x), true) => x.serialize(serializer),
+            (FinalizationProof::Signatures(x), false) => {
+                serializer.serialize_newtype_variant("FinalizationProof", 0, "Signatures", x)
+            }
+            (FinalizationProof::Aggregated(x), false) => {
+                serializer.serialize_newtype_variant("FinalizationProof", 1, "Aggregated", x)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FinalizationProof {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Bare {
+            Signatures(Vec<TypedSignature<BlockHeader>>),
+            Aggregated(AggregatedFinalizationProof),
+        }
+        #[derive(Deserialize)]
+        #[serde(rename = "FinalizationProof")]
+        enum Tagged {
+            Signatures(Vec<TypedSignature<BlockHeader>>),
+            Aggregated(AggregatedFinalizationProof),
+        }
+        if deserializer.is_human_readable() {
+            Ok(match Bare::deserialize(deserializer)? {
+                Bare::Signatures(x) => FinalizationProof::Signatures(x),
+                Bare::Aggregated(x) => FinalizationProof::Aggregated(x),
+            })
+        } else {
+            Ok(match Tagged::deserialize(deserializer)? {
+                Tagged::Signatures(x) => FinalizationProof::Signatures(x),
+                Tagged::Aggregated(x) => FinalizationProof::Aggregated(x),
+            })
+        }
+    }
+}
+
+/// A finalization proof with one aggregated BLS12-381 signature and the bitmap of the signers.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AggregatedFinalizationProof {
+    /// The bitmap of the signers over the `validator_set` of the header, where the `i`-th validator
+    /// is the `i % 8`-th least significant bit of the `i / 8`-th byte.
+    pub signers: Vec<u8>,
+    /// The aggregated signature of the signers on the header.
+    pub signature: Signature,
+}
+
+impl AggregatedFinalizationProof {
+    /// Aggregates the signatures of the validators in `validator_set`.
+    ///
+    /// The signatures must be of BLS12-381, and it doesn't verify them.
+    /// Duplicate signatures of the same signer are ignored.
+    pub fn aggregate(
+        validator_set: &[(PublicKey, VotingPower)],
+        signatures: &[TypedSignature<BlockHeader>],
+    ) -> Result<Self, CryptoError> {
+        let mut signers = vec![0; Self::bitmap_len(validator_set.len())];
+        let mut raw_signatures = Vec::new();
+        for signature in signatures {
+            let index = validator_set
+                .iter()
+                .position(|(public_key, _)| public_key == signature.signer())
+                .ok_or_else(|| {
+                    CryptoError::InvalidFormat(format!(
+                        "signer {} is not in the validator set",
+                        signature.signer()
+                    ))
+                })?;
+            if signers[index / 8] & (1 << (index % 8)) != 0 {
+                continue;
+            }
+            signers[index / 8] |= 1 << (index % 8);
+            raw_signatures.push(signature.get_raw_signature());
+        }
+        Ok(Self {
+            signers,
+            signature: Signature::aggregate(&raw_signatures)?,
+        })
+    }
+
+    /// Returns the public keys of the signers in `validator_set`, in its order.
+    pub fn signer_keys(
+        &self,
+        validator_set: &[(PublicKey, VotingPower)],
+    ) -> Result<Vec<PublicKey>, String> {
+        if self.signers.len() != Self::bitmap_len(validator_set.len()) {
+            return Err(format!(
+                "invalid signer bitmap length: expected {}, got {}",
+                Self::bitmap_len(validator_set.len()),
+                self.signers.len()
+            ));
+        }
+        let is_signer = |index: usize| self.signers[index / 8] & (1 << (index % 8)) != 0;
+        if (validator_set.len()..self.signers.len() * 8).any(is_signer) {
+            return Err("invalid signer bitmap: marks a validator out of the set".to_string());
+        }
+        Ok(validator_set
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| is_signer(*index))
+            .map(|(_, (public_key, _))| public_key.clone())
+            .collect())
+    }
+
+    fn bitmap_len(validator_count: usize) -> usize {
+        let full_bytes = validator_count / 8;
+        if validator_count > full_bytes * 8 {
+            full_bytes + 1
+        } else {
+            full_bytes
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -65,9 +250,24 @@ pub struct Agenda {
     pub transactions_hash: Hash256,
 }
 
+/// An archive of the off-chain discussion among the members.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ChatLog {
-    // TODO
+    /// The height of the block that this chat log is included in.
+    pub height: BlockHeight,
+    /// The messages in chronological order.
+    pub messages: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ChatMessage {
+    pub author: PublicKey,
+    pub timestamp: Timestamp,
+    pub content: String,
+    /// The signature of the author on `(genesis block hash, height, timestamp, content)`.
+    ///
+    /// The genesis block hash keeps the message from being replayed on another chain.
+    pub signature: TypedSignature<(Hash256, BlockHeight, Timestamp, String)>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -97,6 +297,27 @@ pub enum Diff {
     General(Box<ReservedState>, Hash256),
 }
 
+/// A governance operation on the reserved state.
+///
+/// A `Diff::Reserved` can be described as a sequence of these
+/// (see `ReservedState::operations_to()`), which is easier to review than the whole state.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum ReservedStateOperation {
+    /// Adds a new member, keeping the members sorted by their names.
+    AddMember(Box<Member>),
+    /// Removes the member, also from the consensus leader order.
+    RemoveMember(MemberName),
+    ChangeVotingPower {
+        name: MemberName,
+        governance_voting_power: VotingPower,
+        consensus_voting_power: VotingPower,
+    },
+    /// Replaces the consensus leader order.
+    ReorderLeaders(Vec<MemberName>),
+    /// Upgrades the protocol version to a higher one.
+    UpgradeVersion(String),
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Transaction {
     pub author: PublicKey,
@@ -107,6 +328,7 @@ pub struct Transaction {
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum ExtraAgendaTransaction {
     Delegate(TxDelegate),
     Undelegate(TxUndelegate),
@@ -119,20 +341,98 @@ pub struct TxDelegate {
     pub delegatee: PublicKey,
     /// Whether to delegate the governance voting power too.
     pub governance: bool,
-    pub proof: TypedSignature<(PublicKey, PublicKey, bool, BlockHeight)>,
+    /// The conditions that automatically release this delegation.
+    pub conditions: Vec<DelegationCondition>,
+    /// The height of the block that this transaction is included in.
+    pub block_height: BlockHeight,
+    /// The signature of the delegator on `(delegator, delegatee, governance, conditions, block_height)`.
+    pub proof: TypedSignature<(
+        PublicKey,
+        PublicKey,
+        bool,
+        Vec<DelegationCondition>,
+        BlockHeight,
+    )>,
     pub timestamp: Timestamp,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct TxUndelegate {
     pub delegator: PublicKey,
+    /// The height of the block that this transaction is included in.
+    pub block_height: BlockHeight,
+    /// The signature of the delegator on `(delegator, block_height)`.
     pub proof: TypedSignature<(PublicKey, BlockHeight)>,
     pub timestamp: Timestamp,
 }
 
+/// A report of a misbehaving validator.
+///
+/// Once accepted, the consensus voting power of the offender becomes zero
+/// and the delegations to the offender are released.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct TxReport {
-    // TODO
+    /// The self-contained evidence of the misbehavior.
+    pub misbehavior: Misbehavior,
+    /// The finalized headers from the one right before the height of the misbehavior
+    /// to the one right before the last finalized header, in order of height.
+    ///
+    /// The first one has the validator set of the consensus where the misbehavior happened,
+    /// and they are linked to the last finalized header by `previous_hash`.
+    pub headers: Vec<BlockHeader>,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum Misbehavior {
+    /// Two conflicting votes of the same kind, for the same height and round, from the same validator.
+    DoubleVote(SignedConsensusVote, SignedConsensusVote),
+    /// Two proposals of different blocks, for the same height and round, from the same validator.
+    DoubleProposal(SignedConsensusProposal, SignedConsensusProposal),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum ConsensusVoteKind {
+    Prevote,
+    Precommit,
+}
+
+/// A vote in the consensus, which is bound to a specific height and round.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ConsensusVote {
+    /// The hash of the genesis block, which keeps the vote from being replayed on another chain.
+    pub genesis_hash: Hash256,
+    pub kind: ConsensusVoteKind,
+    pub height: BlockHeight,
+    pub round: ConsensusRound,
+    /// The hash of the voted block, or `None` for a nil vote.
+    pub block_hash: Option<Hash256>,
+}
+
+/// A consensus vote with the signature of the voter.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedConsensusVote {
+    pub vote: ConsensusVote,
+    pub signature: TypedSignature<ConsensusVote>,
+}
+
+/// A block proposal in the consensus, which is bound to a specific height and round.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ConsensusProposal {
+    /// The hash of the genesis block, which keeps the proposal from being replayed on another chain.
+    pub genesis_hash: Hash256,
+    pub height: BlockHeight,
+    pub round: ConsensusRound,
+    /// The round where the proposed block got a quorum of prevotes, if it is proposed again.
+    pub valid_round: Option<ConsensusRound>,
+    pub block_hash: Hash256,
+}
+
+/// A consensus proposal with the signature of the proposer.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedConsensusProposal {
+    pub proposal: ConsensusProposal,
+    pub signature: TypedSignature<ConsensusProposal>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -143,6 +443,7 @@ pub struct GenesisInfo {
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commit {
     Block(BlockHeader),
     Transaction(Transaction),
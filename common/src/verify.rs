@@ -1,7 +1,10 @@
-use crate::reserved::ReservedState;
+use crate::reserved::{ReservedState, ReservedStateError};
+use crate::serde_spb::HashEncoding;
 use crate::*;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone)]
@@ -14,6 +17,17 @@ pub enum Error {
     CryptoError(String, CryptoError),
     #[error("invalid commit: applied {0} commit cannot be applied at {1} phase")]
     PhaseMismatch(String, String),
+    #[error("invalid reserved state: {0}")]
+    InvalidReservedState(ReservedStateError),
+    /// When the protocol version required from the given height is newer than this node supports.
+    #[error("protocol version {1} is required from height {0}, but this node supports up to {2}; please upgrade the node")]
+    UnsupportedProtocolVersion(BlockHeight, String, String),
+    /// When the trusted validators don't have enough voting power to trust the given header.
+    #[error("insufficient trust: {0}")]
+    InsufficientTrust(String),
+    /// When the trusted header is older than the trusting period.
+    #[error("trust expired: {0}")]
+    TrustExpired(String),
 }
 
 /// Verifies whether `h2` can be the direct child of `h1`.
@@ -23,6 +37,17 @@ pub enum Error {
 /// 2. finalization proof
 /// 3. protocol version of the node binary.
 pub fn verify_header_to_header(h1: &BlockHeader, h2: &BlockHeader) -> Result<(), Error> {
+    verify_header_to_header_with(h1, h2, &HashSet::new())
+}
+
+/// The signatures that have already been verified, as `(data, signature, signer)`.
+type VerifiedSignatures = HashSet<(Hash256, Signature, PublicKey)>;
+
+fn verify_header_to_header_with(
+    h1: &BlockHeader,
+    h2: &BlockHeader,
+    verified: &VerifiedSignatures,
+) -> Result<(), Error> {
     if h2.height != h1.height + 1 {
         return Err(Error::InvalidArgument(format!(
             "invalid height: expected {}, got {}",
@@ -53,7 +78,38 @@ pub fn verify_header_to_header(h1: &BlockHeader, h2: &BlockHeader) -> Result<(),
             h1.timestamp, h2.timestamp
         )));
     }
-    verify_finalization_proof(h1, &h2.prev_block_finalization_proof)?;
+    verify_version_upgrade(h1, h2)?;
+    let voted_validators = finalization_signers(h1, &h2.prev_block_finalization_proof, verified)?;
+    check_finalization_voting_power(h1, &voted_validators)?;
+    Ok(())
+}
+
+/// Verifies that the protocol version of `h2`, which is later than `h1`, is valid and doesn't go down.
+fn verify_version_upgrade(h1: &BlockHeader, h2: &BlockHeader) -> Result<(), Error> {
+    let version = serde_spb::parse_version(&h2.version).map_err(Error::InvalidArgument)?;
+    let previous_version = serde_spb::parse_version(&h1.version).map_err(Error::InvalidArgument)?;
+    if version < previous_version {
+        return Err(Error::InvalidArgument(format!(
+            "invalid version: expected larger than or equal to {}, got {}",
+            h1.version, h2.version
+        )));
+    }
+    Ok(())
+}
+
+/// Verifies that this node supports the given protocol version,
+/// which is required to verify or produce the block of the given height.
+pub fn verify_protocol_version_supported(version: &str, height: BlockHeight) -> Result<(), Error> {
+    let parsed_version = serde_spb::parse_version(version).map_err(Error::InvalidArgument)?;
+    let supported_version = serde_spb::parse_version(SIMPERBY_CORE_PROTOCOL_VERSION)
+        .expect("the protocol version of the node must be valid");
+    if parsed_version > supported_version {
+        return Err(Error::UnsupportedProtocolVersion(
+            height,
+            version.to_string(),
+            SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+        ));
+    }
     Ok(())
 }
 
@@ -62,21 +118,140 @@ pub fn verify_finalization_proof(
     header: &BlockHeader,
     block_finalization_proof: &FinalizationProof,
 ) -> Result<(), Error> {
-    let total_voting_power: VotingPower = header.validator_set.iter().map(|(_, v)| v).sum();
-    // TODO: change to `HashSet` after `PublicKey` supports `Hash`.
-    let mut voted_validators = BTreeSet::new();
-    for signature in block_finalization_proof {
-        signature
-            .verify(header)
-            .map_err(|e| Error::CryptoError("invalid finalization proof".to_string(), e))?;
-        voted_validators.insert(signature.signer());
-    }
-    let voted_voting_power: VotingPower = header
-        .validator_set
+    let voted_validators = finalization_signers(header, block_finalization_proof, &HashSet::new())?;
+    check_finalization_voting_power(header, &voted_validators)
+}
+
+/// Verifies the finalization proof of the given block header, which must also be signed by
+/// the validators of `trusted_header` that have more than 1/3 of its total voting power.
+///
+/// Since the validator set of `header` is not trusted by itself, the latter ensures that
+/// at least one honest validator that we trust has signed it.
+/// It fails with `Error::InsufficientTrust` if only the latter doesn't hold.
+pub fn verify_trusted_finalization_proof(
+    trusted_header: &BlockHeader,
+    header: &BlockHeader,
+    block_finalization_proof: &FinalizationProof,
+) -> Result<(), Error> {
+    let voted_validators = finalization_signers(header, block_finalization_proof, &HashSet::new())?;
+    check_finalization_voting_power(header, &voted_validators)?;
+    let (voted_voting_power, total_voting_power) =
+        voting_power_of(&trusted_header.validator_set, &voted_validators);
+    if voted_voting_power * 3 <= total_voting_power {
+        return Err(Error::InsufficientTrust(format!(
+            "voting power of the trusted validators is too low: {} / {}",
+            voted_voting_power, total_voting_power
+        )));
+    }
+    Ok(())
+}
+
+/// Verifies whether `header`, which is later than `trusted_header` but not necessarily the direct child,
+/// can be trusted with its finalization proof (see `verify_trusted_finalization_proof()`).
+///
+/// It fails with `Error::InsufficientTrust` if the validators of `trusted_header` don't have enough
+/// voting power in the proof, which means that some intermediate header must be verified first.
+/// It fails with `Error::TrustExpired` if `trusted_header` is older than `trusting_period` at `now`,
+/// since its validators may have left and no longer be accountable.
+pub fn verify_skipping_header(
+    trusted_header: &BlockHeader,
+    header: &BlockHeader,
+    block_finalization_proof: &FinalizationProof,
+    trusting_period: Timestamp,
+    now: Timestamp,
+) -> Result<(), Error> {
+    if trusted_header.timestamp.saturating_add(trusting_period) < now {
+        return Err(Error::TrustExpired(format!(
+            "the trusted header at {} is older than the trusting period {} at {}",
+            trusted_header.timestamp, trusting_period, now
+        )));
+    }
+    if header.height <= trusted_header.height {
+        return Err(Error::InvalidArgument(format!(
+            "invalid height: expected larger than {}, got {}",
+            trusted_header.height, header.height
+        )));
+    }
+    if header.timestamp < trusted_header.timestamp {
+        return Err(Error::InvalidArgument(format!(
+            "invalid timestamp: expected larger than or equal to {}, got {}",
+            trusted_header.timestamp, header.timestamp
+        )));
+    }
+    verify_version_upgrade(trusted_header, header)?;
+    verify_trusted_finalization_proof(trusted_header, header, block_finalization_proof)
+}
+
+/// Verifies the signatures of the finalization proof of the header and returns the signers.
+///
+/// It doesn't check whether they have enough voting power (see `verify_finalization_proof()`).
+pub fn verify_finalization_signers(
+    header: &BlockHeader,
+    block_finalization_proof: &FinalizationProof,
+) -> Result<BTreeSet<PublicKey>, Error> {
+    finalization_signers(header, block_finalization_proof, &HashSet::new())
+}
+
+/// Verifies the signatures of the finalization proof and returns the signers.
+fn finalization_signers(
+    header: &BlockHeader,
+    block_finalization_proof: &FinalizationProof,
+    verified: &VerifiedSignatures,
+) -> Result<BTreeSet<PublicKey>, Error> {
+    match block_finalization_proof {
+        FinalizationProof::Signatures(signatures) => {
+            verify_typed_signatures(header.to_hash256(), signatures, verified)
+                .map_err(|e| Error::CryptoError("invalid finalization proof".to_string(), e))?;
+            // TODO: change to `HashSet` after `PublicKey` supports `Hash`.
+            Ok(signatures
+                .iter()
+                .map(|signature| signature.signer().clone())
+                .collect())
+        }
+        FinalizationProof::Aggregated(proof) => {
+            // The validators have proven the possession of their BLS keys
+            // in the reserved state (see `ReservedState::validate()`).
+            let signers = proof
+                .signer_keys(&header.validator_set)
+                .map_err(Error::InvalidProof)?;
+            proof
+                .signature
+                .verify_aggregated(header.to_hash256(), &signers)
+                .map_err(|e| Error::CryptoError("invalid finalization proof".to_string(), e))?;
+            Ok(signers.into_iter().collect())
+        }
+    }
+}
+
+/// Verifies the signatures on the same data in a batch, skipping the ones already verified.
+fn verify_typed_signatures<T: ToHash256>(
+    data: Hash256,
+    signatures: &[TypedSignature<T>],
+    verified: &VerifiedSignatures,
+) -> Result<(), CryptoError> {
+    let items = signatures
         .iter()
-        .filter(|(v, _)| voted_validators.contains(v))
-        .map(|(_, power)| power)
-        .sum();
+        .filter(|s| !verified.contains(&(data, s.signature().clone(), s.signer().clone())))
+        .map(|s| (data, s.signature(), s.signer()))
+        .collect::<Vec<_>>();
+    verify_batch(&items).map_err(|invalid| {
+        CryptoError::InvalidFormat(format!(
+            "invalid signatures by {}",
+            invalid
+                .into_iter()
+                .map(|i| items[i].2.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })
+}
+
+fn check_finalization_voting_power(
+    header: &BlockHeader,
+    voted_validators: &BTreeSet<PublicKey>,
+) -> Result<(), Error> {
+    let (voted_voting_power, total_voting_power) =
+        voting_power_of(&header.validator_set, voted_validators);
     if voted_voting_power * 3 <= total_voting_power * 2 {
         return Err(Error::InvalidProof(format!(
             "invalid finalization proof - voted voting power is too low: {} / {}",
@@ -86,6 +261,77 @@ pub fn verify_finalization_proof(
     Ok(())
 }
 
+/// Returns the voting power of the voted validators and the total voting power of the validator set.
+fn voting_power_of(
+    validator_set: &[(PublicKey, VotingPower)],
+    voted_validators: &BTreeSet<PublicKey>,
+) -> (VotingPower, VotingPower) {
+    let total_voting_power: VotingPower = validator_set.iter().map(|(_, v)| v).sum();
+    let voted_voting_power: VotingPower = validator_set
+        .iter()
+        .filter(|(v, _)| voted_validators.contains(v))
+        .map(|(_, power)| power)
+        .sum();
+    (voted_voting_power, total_voting_power)
+}
+
+/// Verifies the messages of the chat log and returns the timestamp of the last message.
+///
+/// The messages must be signed by the members for this chain
+/// and be later than or equal to `last_timestamp`.
+fn verify_chat_log(
+    header: &BlockHeader,
+    reserved_state: &ReservedState,
+    chat_log: &ChatLog,
+    last_timestamp: Timestamp,
+) -> Result<Timestamp, Error> {
+    let next_height = header.height + 1;
+    if chat_log.height != next_height {
+        return Err(Error::InvalidArgument(format!(
+            "invalid chat log height: expected {}, got {}",
+            next_height, chat_log.height
+        )));
+    }
+    let genesis_hash = reserved_state.genesis_info.header.to_hash256();
+    let encoding = header.hash_encoding().map_err(Error::InvalidArgument)?;
+    let mut last_timestamp = last_timestamp;
+    for message in &chat_log.messages {
+        if message.timestamp < last_timestamp {
+            return Err(Error::InvalidArgument(format!(
+                "invalid chat message timestamp: expected larger than or equal to the last message timestamp {}, got {}",
+                last_timestamp, message.timestamp
+            )));
+        }
+        if reserved_state.query_name(&message.author).is_none() {
+            return Err(Error::InvalidArgument(format!(
+                "invalid chat message author: {} is not a member",
+                message.author
+            )));
+        }
+        if message.signature.signer() != &message.author {
+            return Err(Error::InvalidArgument(format!(
+                "invalid chat message signature: signed by {} instead of the author {}",
+                message.signature.signer(),
+                message.author
+            )));
+        }
+        message
+            .signature
+            .verify_with(
+                &(
+                    genesis_hash,
+                    chat_log.height,
+                    message.timestamp,
+                    message.content.clone(),
+                ),
+                encoding,
+            )
+            .map_err(|e| Error::CryptoError("invalid chat message signature".to_string(), e))?;
+        last_timestamp = message.timestamp;
+    }
+    Ok(last_timestamp)
+}
+
 // Phases of the `CommitSequenceVerifier`.
 //
 // Note that `Phase::X` is agenda phase where `Commit::X` is the last commit.
@@ -109,44 +355,258 @@ enum Phase {
     // Extra phase consists of `ExtraAgendaTransaction`s and `ChatLog`s.
     ExtraAgendaTransaction {
         last_extra_agenda_timestamp: Timestamp,
-        // TODO: add `ChatLog` here.
+        last_chat_log_timestamp: Timestamp,
     },
     // The block phase.
     Block,
 }
 
+/// The default maximum drift of a block timestamp from the local clock (in milliseconds).
+pub const DEFAULT_MAX_CLOCK_DRIFT: Timestamp = 10 * 1000;
+/// The default maximum gap between a block and the commits it contains (in milliseconds).
+pub const DEFAULT_MAX_COMMIT_GAP: Timestamp = 30 * 24 * 60 * 60 * 1000;
+/// The default period in which a header can be trusted to skip to a later header (in milliseconds).
+pub const DEFAULT_TRUSTING_PERIOD: Timestamp = 14 * 24 * 60 * 60 * 1000;
+
+/// The timestamp bounds that `CommitSequenceVerifier` checks for each block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifierOptions {
+    /// How far a block timestamp may be ahead of the local clock,
+    /// or `None` to skip the wall-clock check (e.g., when re-verifying the history).
+    pub max_clock_drift: Option<Timestamp>,
+    /// How far the timestamps of the commits in a block may be from the block timestamp,
+    /// or `None` to skip the check.
+    pub max_commit_gap: Option<Timestamp>,
+}
+
+impl Default for VerifierOptions {
+    fn default() -> Self {
+        Self {
+            max_clock_drift: Some(DEFAULT_MAX_CLOCK_DRIFT),
+            max_commit_gap: Some(DEFAULT_MAX_COMMIT_GAP),
+        }
+    }
+}
+
+impl VerifierOptions {
+    /// The options for re-verifying blocks that have already been finalized,
+    /// which may be arbitrarily older than the local clock and may predate the commit gap rule.
+    pub fn historical() -> Self {
+        Self {
+            max_clock_drift: None,
+            max_commit_gap: None,
+        }
+    }
+}
+
+/// Returns the local clock in the same unit as the block timestamps.
+pub(crate) fn local_timestamp() -> Timestamp {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("the local clock is before the unix epoch")
+        .as_millis() as Timestamp
+}
+
+/// Returns the timestamps that the given commit carries, if any.
+fn commit_timestamps(commit: &Commit) -> Vec<Timestamp> {
+    match commit {
+        Commit::Transaction(tx) => vec![tx.timestamp],
+        Commit::Agenda(agenda) => vec![agenda.timestamp],
+        Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Delegate(tx)) => vec![tx.timestamp],
+        Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Undelegate(tx)) => {
+            vec![tx.timestamp]
+        }
+        Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Report(tx)) => vec![tx.timestamp],
+        Commit::ChatLog(chat_log) => chat_log.messages.iter().map(|m| m.timestamp).collect(),
+        // The previous block header is checked by `verify_header_to_header`.
+        Commit::Block(_) | Commit::AgendaProof(_) => vec![],
+    }
+}
+
+/// The state of `CommitSequenceVerifier` at a block boundary, from which the verification can resume.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct VerifierCheckpoint {
+    /// The last verified block header.
+    pub header: BlockHeader,
+    pub reserved_state: ReservedState,
+    pub non_reserved_state_root: Option<Hash256>,
+    /// The commits that count for the commit Merkle root of the next block.
+    pub next_block_commits: Vec<Commit>,
+}
+
 /// Verifies whether the given sequence of commits can be agenda subset of agenda finalized chain.
 ///
 /// It may accept sequences that contain more than one `BlockHeader`.
+///
+/// In the streaming mode (see `new_streaming()`), it drops the commits of the verified blocks
+/// so that the memory usage doesn't grow with the length of the sequence.
 #[derive(Debug, Clone)]
 pub struct CommitSequenceVerifier {
     header: BlockHeader,
     phase: Phase,
+    options: VerifierOptions,
     reserved_state: ReservedState,
+    /// The Merkle root of the non-reserved files at the current position of the sequence,
+    /// or `None` if it is not provided yet or invalidated by a non-reserved diff.
+    non_reserved_state_root: Option<Hash256>,
+    /// The Merkle root of the actual reserved directory for the next block commit, if provided.
+    reserved_state_root: Option<Hash256>,
     next_block_commits: Vec<Commit>,
+    /// The commits received so far, or since the last block header in the streaming mode.
     total_commits: Vec<Commit>,
+    streaming: bool,
+    /// The signatures verified in advance by `preverify_signatures()`.
+    verified_signatures: VerifiedSignatures,
 }
 
 impl CommitSequenceVerifier {
     /// Creates agenda new `CommitSequenceVerifier` with the given block header.
-    pub fn new(start_header: BlockHeader, reserved_state: ReservedState) -> Result<Self, Error> {
+    pub fn new(
+        start_header: BlockHeader,
+        reserved_state: ReservedState,
+        options: VerifierOptions,
+    ) -> Result<Self, Error> {
         Ok(Self {
             header: start_header.clone(),
             phase: Phase::Block,
+            options,
             reserved_state,
+            non_reserved_state_root: None,
+            reserved_state_root: None,
             next_block_commits: vec![],
             total_commits: vec![Commit::Block(start_header)],
+            streaming: false,
+            verified_signatures: HashSet::new(),
+        })
+    }
+
+    /// Creates a new `CommitSequenceVerifier` in the streaming mode,
+    /// which keeps only the commits since the last block header.
+    pub fn new_streaming(
+        start_header: BlockHeader,
+        reserved_state: ReservedState,
+        options: VerifierOptions,
+    ) -> Result<Self, Error> {
+        let mut verifier = Self::new(start_header, reserved_state, options)?;
+        verifier.streaming = true;
+        Ok(verifier)
+    }
+
+    /// Resumes the verification from the checkpoint in the streaming mode.
+    pub fn from_checkpoint(
+        checkpoint: VerifierCheckpoint,
+        options: VerifierOptions,
+    ) -> Result<Self, Error> {
+        let mut verifier =
+            Self::new_streaming(checkpoint.header, checkpoint.reserved_state, options)?;
+        verifier.non_reserved_state_root = checkpoint.non_reserved_state_root;
+        verifier.next_block_commits = checkpoint.next_block_commits;
+        Ok(verifier)
+    }
+
+    /// Takes the checkpoint of the current state, which must be right after a block header.
+    pub fn checkpoint(&self) -> Result<VerifierCheckpoint, Error> {
+        if !matches!(self.phase, Phase::Block) {
+            return Err(Error::InvalidArgument(format!(
+                "a checkpoint can be taken only at a block boundary, but the phase is {:?}",
+                self.phase
+            )));
+        }
+        Ok(VerifierCheckpoint {
+            header: self.header.clone(),
+            reserved_state: self.reserved_state.clone(),
+            non_reserved_state_root: self.non_reserved_state_root,
+            next_block_commits: self.next_block_commits.clone(),
         })
     }
 
+    /// Verifies the signatures of the finalization proofs and the agenda proofs in the given commits
+    /// in a single batch, so that `apply_commit()` doesn't verify them one by one later.
+    ///
+    /// This is for verifying a long sequence of commits (e.g., catching up the chain),
+    /// which are supposed to be applied right after this.
+    /// It fails if any of the signatures is invalid, regardless of whether the commits are valid otherwise.
+    pub fn preverify_signatures<'a>(
+        &mut self,
+        commits: impl IntoIterator<Item = &'a Commit>,
+    ) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        for commit in commits {
+            match commit {
+                // The finalization proof signs the previous header, which must be `previous_hash`.
+                Commit::Block(header) => {
+                    if let FinalizationProof::Signatures(signatures) =
+                        &header.prev_block_finalization_proof
+                    {
+                        entries.extend(
+                            signatures
+                                .iter()
+                                .map(|s| (header.previous_hash, s.signature(), s.signer())),
+                        );
+                    }
+                }
+                Commit::AgendaProof(agenda_proof) => entries.extend(
+                    agenda_proof
+                        .proof
+                        .iter()
+                        .map(|s| (agenda_proof.agenda_hash, s.signature(), s.signer())),
+                ),
+                _ => (),
+            }
+        }
+        verify_batch(&entries).map_err(|invalid| {
+            Error::InvalidProof(format!(
+                "invalid signatures by {}",
+                invalid
+                    .into_iter()
+                    .map(|i| entries[i].2.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })?;
+        self.verified_signatures.extend(
+            entries
+                .into_iter()
+                .map(|(data, signature, signer)| (data, signature.clone(), signer.clone())),
+        );
+        Ok(())
+    }
+
+    /// Provides the Merkle root of the non-reserved files at the current position of the sequence.
+    ///
+    /// The verifier can't track the non-reserved state by itself because a diff only contains
+    /// the hash of it. Thus this must be provided before applying a block commit,
+    /// if it has never been provided or any transaction after that has changed the non-reserved state.
+    pub fn set_non_reserved_state_root(&mut self, root: Hash256) {
+        self.non_reserved_state_root = Some(root);
+    }
+
+    /// Provides the Merkle root of the actual files in the reserved directory
+    /// for the next block commit.
+    ///
+    /// If provided, the block commit is rejected unless the reserved directory
+    /// matches the reserved state tracked by the verifier.
+    /// It is consumed by the next block commit.
+    pub fn set_reserved_state_root(&mut self, root: Hash256) {
+        self.reserved_state_root = Some(root);
+    }
+
     /// Returns the commits received so far.
+    ///
+    /// In the streaming mode, it returns the commits since the last block header (inclusive).
     pub fn get_total_commits(&self) -> &[Commit] {
         &self.total_commits
     }
 
+    /// Returns the reserved state after applying the commits received so far.
+    pub fn get_reserved_state(&self) -> &ReservedState {
+        &self.reserved_state
+    }
+
     /// Returns the block headers received so far.
     ///
     /// It returns `[start_header]` if no block header has been received.
+    /// In the streaming mode, it returns only the last block header.
     pub fn get_block_headers(&self) -> Vec<BlockHeader> {
         self.total_commits
             .iter()
@@ -165,31 +625,175 @@ impl CommitSequenceVerifier {
         verify_finalization_proof(&self.header, proof)
     }
 
+    /// Returns the encoding of the data to be hashed, which is the one of the last header.
+    fn hash_encoding(&self) -> Result<HashEncoding, Error> {
+        self.header.hash_encoding().map_err(Error::InvalidArgument)
+    }
+
+    /// Checks the `repository_merkle_root` of the given block header against the current state.
+    fn verify_repository_merkle_root(&self, block_header: &BlockHeader) -> Result<(), Error> {
+        let non_reserved_state_root = self.non_reserved_state_root.ok_or_else(|| {
+            Error::InvalidArgument(
+                "unknown non-reserved state root: it must be provided before a block commit"
+                    .to_string(),
+            )
+        })?;
+        if let Some(reserved_state_root) = self.reserved_state_root {
+            let expected =
+                crate::merkle_tree::FileMerkleTree::create(self.reserved_state.to_files()).root();
+            if reserved_state_root != expected {
+                return Err(Error::InvalidArgument(format!(
+                    "reserved directory doesn't match the reserved state: expected root {}, got {}",
+                    expected, reserved_state_root
+                )));
+            }
+        }
+        let repository_merkle_root = BlockHeader::calculate_repository_merkle_root(
+            &self.reserved_state,
+            non_reserved_state_root,
+        );
+        if repository_merkle_root != block_header.repository_merkle_root {
+            return Err(Error::InvalidArgument(format!(
+                "invalid repository merkle root: expected {}, got {}",
+                repository_merkle_root, block_header.repository_merkle_root
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the timestamp of the given block header against the local clock
+    /// and the commits it contains, as bounded by the options.
+    fn verify_block_timestamp(&self, block_header: &BlockHeader) -> Result<(), Error> {
+        if let Some(max_clock_drift) = self.options.max_clock_drift {
+            let now = local_timestamp();
+            if block_header.timestamp > now.saturating_add(max_clock_drift) {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid block timestamp: {} is ahead of the local clock {} by more than {}",
+                    block_header.timestamp, now, max_clock_drift
+                )));
+            }
+        }
+        if let Some(max_commit_gap) = self.options.max_commit_gap {
+            for timestamp in self.next_block_commits.iter().flat_map(commit_timestamps) {
+                let gap = (block_header.timestamp as i128 - timestamp as i128).abs();
+                if gap > max_commit_gap as i128 {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid block timestamp: {} is apart from the commit timestamp {} by more than {}",
+                        block_header.timestamp, timestamp, max_commit_gap
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the version of the given block header is the one of the reserved state,
+    /// so that the version changes only by an agenda-approved reserved-diff transaction.
+    fn verify_version(&self, block_header: &BlockHeader) -> Result<(), Error> {
+        if block_header.version != self.reserved_state.version {
+            return Err(Error::InvalidArgument(format!(
+                "invalid version: expected {} (of the reserved state), got {}",
+                self.reserved_state.version, block_header.version
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the reserved state of the diff, if any, is valid and keeps the genesis info.
+    fn verify_reserved_diff(
+        reserved_state_before: &ReservedState,
+        diff: &Diff,
+    ) -> Result<(), Error> {
+        let reserved_state = match diff {
+            Diff::Reserved(rs) | Diff::General(rs, _) => rs,
+            Diff::None | Diff::NonReserved(_) => return Ok(()),
+        };
+        reserved_state
+            .validate()
+            .map_err(Error::InvalidReservedState)?;
+        if reserved_state.genesis_info != reserved_state_before.genesis_info {
+            return Err(Error::InvalidReservedState(
+                ReservedStateError::InconsistentGenesisInfo(
+                    "the genesis info must never be changed".to_string(),
+                ),
+            ));
+        }
+        Ok(())
+    }
+
     /// Verifies the given commit and updates the internal reserved_state of CommitSequenceVerifier.
     pub fn apply_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        let encoding = self.hash_encoding()?;
         match (commit, &mut self.phase) {
             (Commit::Block(block_header), Phase::AgendaProof { agenda_proof: _ }) => {
-                verify_header_to_header(&self.header, block_header)?;
+                verify_protocol_version_supported(&block_header.version, block_header.height)?;
+                verify_protocol_version_supported(
+                    &self.reserved_state.version,
+                    block_header.height,
+                )?;
+                verify_header_to_header_with(
+                    &self.header,
+                    block_header,
+                    &self.verified_signatures,
+                )?;
                 // Verify commit merkle root
                 let commit_merkle_root =
-                    BlockHeader::calculate_commit_merkle_root(&self.next_block_commits);
+                    BlockHeader::calculate_commit_merkle_root(&self.next_block_commits, encoding);
                 if commit_merkle_root != block_header.commit_merkle_root {
                     return Err(Error::InvalidArgument(format!(
                         "invalid commit merkle root: expected {}, got {}",
                         commit_merkle_root, block_header.commit_merkle_root
                     )));
                 };
+                self.verify_block_timestamp(block_header)?;
+                // Verify repository merkle root
+                self.verify_repository_merkle_root(block_header)?;
+                self.verify_version(block_header)?;
+                // Verify the validator set for the next block,
+                // where the signers of the last block tell whether the delegatees are active
+                let last_signers = finalization_signers(
+                    &self.header,
+                    &block_header.prev_block_finalization_proof,
+                    &self.verified_signatures,
+                )?;
+                let validator_set = self
+                    .reserved_state
+                    .get_validator_set(
+                        block_header.height,
+                        block_header.timestamp,
+                        Some(&last_signers),
+                    )
+                    .map_err(|e| {
+                        Error::InvalidArgument(format!("invalid reserved state: {}", e))
+                    })?;
+                if validator_set != block_header.validator_set {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid validator set: expected {:?}, got {:?}",
+                        validator_set, block_header.validator_set
+                    )));
+                }
                 self.header = block_header.clone();
                 self.phase = Phase::Block;
+                self.reserved_state_root = None;
                 self.next_block_commits = vec![];
             }
             (
                 Commit::Block(block_header),
                 Phase::ExtraAgendaTransaction {
                     last_extra_agenda_timestamp,
+                    last_chat_log_timestamp,
                 },
             ) => {
-                verify_header_to_header(&self.header, block_header)?;
+                verify_protocol_version_supported(&block_header.version, block_header.height)?;
+                verify_protocol_version_supported(
+                    &self.reserved_state.version,
+                    block_header.height,
+                )?;
+                verify_header_to_header_with(
+                    &self.header,
+                    block_header,
+                    &self.verified_signatures,
+                )?;
                 // Check if the block contains all the extra-agenda transactions.
                 if block_header.timestamp < *last_extra_agenda_timestamp {
                     return Err(Error::InvalidArgument(format!(
@@ -197,24 +801,64 @@ impl CommitSequenceVerifier {
                         last_extra_agenda_timestamp, block_header.timestamp
                     )));
                 }
+                // Check if the block contains all the chat logs.
+                if block_header.timestamp < *last_chat_log_timestamp {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid block timestamp: expected larger than or equal to the last chat log timestamp {}, got {}",
+                        last_chat_log_timestamp, block_header.timestamp
+                    )));
+                }
                 // Verify commit hash
                 let commit_merkle_root =
-                    BlockHeader::calculate_commit_merkle_root(&self.next_block_commits);
+                    BlockHeader::calculate_commit_merkle_root(&self.next_block_commits, encoding);
                 if commit_merkle_root != block_header.commit_merkle_root {
                     return Err(Error::InvalidArgument(format!(
                         "invalid commit merkle root: expected {}, got {}",
                         commit_merkle_root, block_header.commit_merkle_root
                     )));
                 };
+                self.verify_block_timestamp(block_header)?;
+                // Verify repository merkle root
+                self.verify_repository_merkle_root(block_header)?;
+                self.verify_version(block_header)?;
+                // Verify the validator set for the next block,
+                // where the signers of the last block tell whether the delegatees are active
+                let last_signers = finalization_signers(
+                    &self.header,
+                    &block_header.prev_block_finalization_proof,
+                    &self.verified_signatures,
+                )?;
+                let validator_set = self
+                    .reserved_state
+                    .get_validator_set(
+                        block_header.height,
+                        block_header.timestamp,
+                        Some(&last_signers),
+                    )
+                    .map_err(|e| {
+                        Error::InvalidArgument(format!("invalid reserved state: {}", e))
+                    })?;
+                if validator_set != block_header.validator_set {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid validator set: expected {:?}, got {:?}",
+                        validator_set, block_header.validator_set
+                    )));
+                }
                 self.header = block_header.clone();
                 self.phase = Phase::Block;
+                self.reserved_state_root = None;
                 self.next_block_commits = vec![];
             }
             (Commit::Transaction(tx), Phase::Block) => {
+                Self::verify_reserved_diff(&self.reserved_state, &tx.diff)?;
                 // Update reserved_state for reserved-diff transactions.
                 if let Diff::Reserved(rs) = &tx.diff {
                     self.reserved_state = *rs.clone();
                 }
+                // The non-reserved state root is unknown after a non-reserved diff.
+                if let Diff::NonReserved(_) | Diff::General(_, _) = &tx.diff {
+                    self.non_reserved_state_root = None;
+                }
                 self.phase = Phase::Transaction {
                     last_transaction: tx.clone(),
                     preceding_transactions: vec![],
@@ -234,10 +878,15 @@ impl CommitSequenceVerifier {
                         last_transaction.timestamp, tx.timestamp
                     )));
                 }
+                Self::verify_reserved_diff(&self.reserved_state, &tx.diff)?;
                 // Update reserved_state for reserved-diff transactions.
                 if let Diff::Reserved(rs) = &tx.diff {
                     self.reserved_state = *rs.clone();
                 }
+                // The non-reserved state root is unknown after a non-reserved diff.
+                if let Diff::NonReserved(_) | Diff::General(_, _) = &tx.diff {
+                    self.non_reserved_state_root = None;
+                }
                 preceding_transactions.push(last_transaction.clone());
                 *last_transaction = tx.clone();
             }
@@ -251,10 +900,10 @@ impl CommitSequenceVerifier {
                     )));
                 }
                 // Verify agenda without transactions
-                if agenda.transactions_hash != Agenda::calculate_transactions_hash(&[]) {
+                if agenda.transactions_hash != Agenda::calculate_transactions_hash(&[], encoding) {
                     return Err(Error::InvalidArgument(format!(
                         "invalid agenda transactions_hash: expected {}, got {}",
-                        Agenda::calculate_transactions_hash(&[]),
+                        Agenda::calculate_transactions_hash(&[], encoding),
                         agenda.transactions_hash
                     )));
                 }
@@ -289,10 +938,12 @@ impl CommitSequenceVerifier {
                     vec![last_transaction.clone()],
                 ]
                 .concat();
-                if agenda.transactions_hash != Agenda::calculate_transactions_hash(&transactions) {
+                if agenda.transactions_hash
+                    != Agenda::calculate_transactions_hash(&transactions, encoding)
+                {
                     return Err(Error::InvalidArgument(format!(
                         "invalid agenda transactions_hash: expected {}, got {}",
-                        Agenda::calculate_transactions_hash(&transactions),
+                        Agenda::calculate_transactions_hash(&transactions, encoding),
                         agenda.transactions_hash
                     )));
                 }
@@ -310,23 +961,26 @@ impl CommitSequenceVerifier {
                     )));
                 }
                 // Check if agenda hash matches
-                if agenda_proof.agenda_hash != agenda.to_hash256() {
+                let agenda_hash = agenda.to_hash256_with(encoding);
+                if agenda_proof.agenda_hash != agenda_hash {
                     return Err(Error::InvalidArgument(format!(
                         "invalid agenda proof: invalid agenda hash expected {}, got {}",
-                        agenda.to_hash256(),
-                        agenda_proof.agenda_hash
+                        agenda_hash, agenda_proof.agenda_hash
                     )));
                 }
                 // Verify the agenda proof
-                for signature in agenda_proof.proof.iter() {
-                    signature.verify(agenda).map_err(|e| {
-                        Error::CryptoError("invalid agenda proof: invalid signature".to_string(), e)
-                    })?;
-                }
+                verify_typed_signatures(
+                    agenda_proof.agenda_hash,
+                    &agenda_proof.proof,
+                    &self.verified_signatures,
+                )
+                .map_err(|e| {
+                    Error::CryptoError("invalid agenda proof: invalid signature".to_string(), e)
+                })?;
                 // Check if the agenda proof is signed by the majority of the governance participants
                 let governance_set = self
                     .reserved_state
-                    .get_governance_set()
+                    .get_governance_set(agenda.height, agenda.timestamp)
                     .unwrap()
                     .into_iter()
                     .collect::<HashMap<_, _>>();
@@ -359,51 +1013,115 @@ impl CommitSequenceVerifier {
             (Commit::ExtraAgendaTransaction(tx), Phase::AgendaProof { agenda_proof: _ }) => {
                 match tx {
                     ExtraAgendaTransaction::Delegate(tx) => {
+                        let next_height = self.header.height + 1;
+                        if tx.block_height != next_height {
+                            return Err(Error::InvalidArgument(format!(
+                                "invalid extra-agenda transaction block height: expected {}, got {}",
+                                next_height, tx.block_height
+                            )));
+                        }
                         // Update reserved reserved_state by applying delegation
-                        self.reserved_state.apply_delegate(tx).map_err(|e| {
-                            Error::InvalidArgument(format!("invalid delegation: {}", e))
-                        })?;
+                        self.reserved_state = self
+                            .reserved_state
+                            .apply_delegate(tx, encoding)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid delegation: {}", e))
+                            })?;
                         self.phase = Phase::ExtraAgendaTransaction {
                             last_extra_agenda_timestamp: tx.timestamp,
+                            last_chat_log_timestamp: 0,
                         };
                     }
                     ExtraAgendaTransaction::Undelegate(tx) => {
+                        let next_height = self.header.height + 1;
+                        if tx.block_height != next_height {
+                            return Err(Error::InvalidArgument(format!(
+                                "invalid extra-agenda transaction block height: expected {}, got {}",
+                                next_height, tx.block_height
+                            )));
+                        }
                         // Update reserved reserved_state by applying undelegation
-                        self.reserved_state.apply_undelegate(tx).map_err(|e| {
-                            Error::InvalidArgument(format!("invalid undelegation: {}", e))
-                        })?;
+                        self.reserved_state = self
+                            .reserved_state
+                            .apply_undelegate(tx, encoding)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid undelegation: {}", e))
+                            })?;
+                        self.phase = Phase::ExtraAgendaTransaction {
+                            last_extra_agenda_timestamp: tx.timestamp,
+                            last_chat_log_timestamp: 0,
+                        };
+                    }
+                    ExtraAgendaTransaction::Report(tx) => {
+                        // Update reserved reserved_state by applying the report
+                        self.reserved_state = self
+                            .reserved_state
+                            .apply_report(tx, &self.header)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid report: {}", e))
+                            })?;
                         self.phase = Phase::ExtraAgendaTransaction {
                             last_extra_agenda_timestamp: tx.timestamp,
+                            last_chat_log_timestamp: 0,
                         };
                     }
-                    ExtraAgendaTransaction::Report(_tx) => unimplemented!(),
                 }
             }
             (
                 Commit::ExtraAgendaTransaction(tx),
                 Phase::ExtraAgendaTransaction {
                     last_extra_agenda_timestamp,
+                    ..
                 },
             ) => {
                 match tx {
                     ExtraAgendaTransaction::Delegate(tx) => {
-                        // Update reserved reserved_state by applying delegation
-                        self.reserved_state.apply_delegate(tx).map_err(|e| {
-                            Error::InvalidArgument(format!("invalid delegation: {}", e))
-                        })?;
                         // Check if extra-agenda transactions are in chronological order
                         if tx.timestamp < *last_extra_agenda_timestamp {
                             return Err(Error::InvalidArgument(
                                 format!("invalid extra-agenda transaction timestamp: expected larger than or equal to the last transaction timestamp {}, got {}", last_extra_agenda_timestamp, tx.timestamp)
                             ));
                         }
+                        let next_height = self.header.height + 1;
+                        if tx.block_height != next_height {
+                            return Err(Error::InvalidArgument(format!(
+                                "invalid extra-agenda transaction block height: expected {}, got {}",
+                                next_height, tx.block_height
+                            )));
+                        }
+                        // Update reserved reserved_state by applying delegation
+                        self.reserved_state = self
+                            .reserved_state
+                            .apply_delegate(tx, encoding)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid delegation: {}", e))
+                            })?;
                         *last_extra_agenda_timestamp = tx.timestamp;
                     }
                     ExtraAgendaTransaction::Undelegate(tx) => {
+                        // Check if extra-agenda transactions are in chronological order
+                        if tx.timestamp < *last_extra_agenda_timestamp {
+                            return Err(Error::InvalidArgument(
+                                format!("invalid extra-agenda transaction timestamp: expected larger than or equal to the last transaction timestamp {}, got {}", last_extra_agenda_timestamp, tx.timestamp)
+                            ));
+                        }
+                        let next_height = self.header.height + 1;
+                        if tx.block_height != next_height {
+                            return Err(Error::InvalidArgument(format!(
+                                "invalid extra-agenda transaction block height: expected {}, got {}",
+                                next_height, tx.block_height
+                            )));
+                        }
                         // Update reserved reserved_state by applying undelegation
-                        self.reserved_state.apply_undelegate(tx).map_err(|e| {
-                            Error::InvalidArgument(format!("invalid undelegation: {}", e))
-                        })?;
+                        self.reserved_state = self
+                            .reserved_state
+                            .apply_undelegate(tx, encoding)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid undelegation: {}", e))
+                            })?;
+                        *last_extra_agenda_timestamp = tx.timestamp;
+                    }
+                    ExtraAgendaTransaction::Report(tx) => {
                         // Check if extra-agenda transactions are in chronological order
                         if tx.timestamp < *last_extra_agenda_timestamp {
                             return Err(Error::InvalidArgument(
@@ -411,11 +1129,38 @@ impl CommitSequenceVerifier {
                             ));
                         }
                         *last_extra_agenda_timestamp = tx.timestamp;
+                        // Update reserved reserved_state by applying the report
+                        self.reserved_state = self
+                            .reserved_state
+                            .apply_report(tx, &self.header)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid report: {}", e))
+                            })?;
                     }
-                    ExtraAgendaTransaction::Report(_tx) => unimplemented!(),
                 }
             }
-            (Commit::ChatLog(_chat_log), _) => unimplemented!(),
+            (Commit::ChatLog(chat_log), Phase::AgendaProof { agenda_proof: _ }) => {
+                let last_chat_log_timestamp =
+                    verify_chat_log(&self.header, &self.reserved_state, chat_log, 0)?;
+                self.phase = Phase::ExtraAgendaTransaction {
+                    last_extra_agenda_timestamp: 0,
+                    last_chat_log_timestamp,
+                };
+            }
+            (
+                Commit::ChatLog(chat_log),
+                Phase::ExtraAgendaTransaction {
+                    last_chat_log_timestamp,
+                    ..
+                },
+            ) => {
+                *last_chat_log_timestamp = verify_chat_log(
+                    &self.header,
+                    &self.reserved_state,
+                    chat_log,
+                    *last_chat_log_timestamp,
+                )?;
+            }
             (commit, phase) => {
                 return Err(Error::PhaseMismatch(
                     format!("{:?}", commit),
@@ -424,6 +1169,20 @@ impl CommitSequenceVerifier {
             }
         }
         self.next_block_commits.push(commit.clone());
+        if self.streaming {
+            // Drop what the following commits will never refer to.
+            match commit {
+                Commit::Block(header) => {
+                    self.total_commits.clear();
+                    self.verified_signatures
+                        .retain(|(data, _, _)| *data != header.previous_hash);
+                }
+                Commit::AgendaProof(agenda_proof) => self
+                    .verified_signatures
+                    .retain(|(data, _, _)| *data != agenda_proof.agenda_hash),
+                _ => (),
+            }
+        }
         self.total_commits.push(commit.clone());
         Ok(())
     }
@@ -432,9 +1191,12 @@ impl CommitSequenceVerifier {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::merkle_tree::OneshotMerkleTree;
+    use crate::merkle_tree::{FileMerkleTree, OneshotMerkleTree};
     use serde_json::json;
 
+    /// The encoding of the test chains, whose version is `SIMPERBY_CORE_PROTOCOL_VERSION`.
+    const ENCODING: HashEncoding = HashEncoding::Canonical;
+
     fn generate_validator_keypair(size: u8) -> Vec<(PublicKey, PrivateKey)> {
         let mut validator_keypair: Vec<(PublicKey, PrivateKey)> = vec![];
         for i in 0..size {
@@ -479,6 +1241,9 @@ mod test {
                 consensus_voting_power: *voting_power,
                 governance_delegations: None,
                 consensus_delegations: None,
+                governance_delegation_conditions: vec![],
+                consensus_delegation_conditions: vec![],
+                proof_of_possession: None,
             });
         }
         members
@@ -491,7 +1256,7 @@ mod test {
     ) -> ReservedState {
         let genesis_header: BlockHeader = BlockHeader {
             author: validator_keypair[author_index].0.clone(),
-            prev_block_finalization_proof: vec![],
+            prev_block_finalization_proof: FinalizationProof::default(),
             previous_hash: Hash256::zero(),
             height: 0,
             timestamp: time,
@@ -570,10 +1335,13 @@ mod test {
             consensus_voting_power: 1,
             governance_delegations: None,
             consensus_delegations: None,
+            governance_delegation_conditions: vec![],
+            consensus_delegation_conditions: vec![],
+            proof_of_possession: None,
         });
         reserved_state
             .consensus_leader_order
-            .push("Dave".to_string());
+            .push(format!("member{}", validator_keypair.len()));
         reserved_state.consensus_leader_order.sort();
         Commit::Transaction(Transaction {
             author: validator_keypair[2].0.clone(),
@@ -612,7 +1380,7 @@ mod test {
         for (_, private_key) in validator_keypair {
             finalization_proof.push(TypedSignature::sign(header, private_key).unwrap());
         }
-        finalization_proof
+        finalization_proof.into()
     }
 
     fn generate_block_commit(
@@ -654,15 +1422,20 @@ mod test {
         let start_header: BlockHeader = generate_block_header(
             &validator_keypair,
             0,
-            vec![],
+            FinalizationProof::default(),
             Hash256::zero(),
             0,
             0,
             OneshotMerkleTree::create(vec![]).root(),
         );
         let reserved_state: ReservedState = generate_reserved_state(&validator_keypair, 0, 0);
-        let csv: CommitSequenceVerifier =
-            CommitSequenceVerifier::new(start_header, reserved_state.clone()).unwrap();
+        let mut csv: CommitSequenceVerifier = CommitSequenceVerifier::new(
+            start_header,
+            reserved_state.clone(),
+            VerifierOptions::default(),
+        )
+        .unwrap();
+        csv.set_non_reserved_state_root(OneshotMerkleTree::EMPTY_HASH);
         (validator_keypair, reserved_state, csv)
     }
 
@@ -678,9 +1451,10 @@ mod test {
                     vec![last_transaction.clone()],
                 ]
                 .concat(),
+                ENCODING,
             )
         } else {
-            Agenda::calculate_transactions_hash(&[])
+            Agenda::calculate_transactions_hash(&[], ENCODING)
         }
     }
 
@@ -775,7 +1549,10 @@ mod test {
             previous_hash: Commit::Block(csv.header.clone()).to_hash256(),
             height: csv.header.height + 2,
             timestamp: 2,
-            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits),
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
+                &csv.next_block_commits,
+                ENCODING,
+            ),
             repository_merkle_root: Hash256::zero(),
             validator_set: validator_keypair
                 .iter()
@@ -816,7 +1593,10 @@ mod test {
             previous_hash: Hash256::zero(),
             height: csv.header.height + 1,
             timestamp: 2,
-            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits),
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
+                &csv.next_block_commits,
+                ENCODING,
+            ),
             repository_merkle_root: Hash256::zero(),
             validator_set: validator_keypair
                 .iter()
@@ -857,7 +1637,10 @@ mod test {
             previous_hash: Commit::Block(csv.header.clone()).to_hash256(),
             height: csv.header.height + 1,
             timestamp: 2,
-            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits),
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
+                &csv.next_block_commits,
+                ENCODING,
+            ),
             repository_merkle_root: Hash256::zero(),
             validator_set: validator_keypair
                 .iter()
@@ -898,7 +1681,10 @@ mod test {
             previous_hash: Commit::Block(csv.header.clone()).to_hash256(),
             height: csv.header.height + 1,
             timestamp: -1,
-            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits),
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
+                &csv.next_block_commits,
+                ENCODING,
+            ),
             repository_merkle_root: Hash256::zero(),
             validator_set: validator_keypair
                 .iter()
@@ -938,7 +1724,7 @@ mod test {
                 &generate_block_header(
                     &validator_keypair[1..],
                     0,
-                    vec![],
+                    FinalizationProof::default(),
                     csv.header.to_hash256(),
                     csv.header.height + 1,
                     2,
@@ -976,17 +1762,19 @@ mod test {
         // Apply block commit with invalid finalization proof for low voting power
         csv.apply_commit(&Commit::Block(BlockHeader {
             author: validator_keypair[0].0.clone(),
-            prev_block_finalization_proof: vec![generate_unanimous_finalization_proof(
-                &validator_keypair,
+            prev_block_finalization_proof: vec![TypedSignature::sign(
                 &csv.header,
+                &validator_keypair[0].1,
             )
-            .first()
-            .unwrap()
-            .clone()],
+            .unwrap()]
+            .into(),
             previous_hash: Commit::Block(csv.header.clone()).to_hash256(),
             height: csv.header.height + 1,
             timestamp: 2,
-            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits),
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
+                &csv.next_block_commits,
+                ENCODING,
+            ),
             repository_merkle_root: Hash256::zero(),
             validator_set: validator_keypair
                 .iter()
@@ -1203,7 +1991,7 @@ mod test {
         let agenda_transactions_hash = if let Commit::Transaction(transaction) =
             generate_empty_transaction_commit(&validator_keypair, 0, 0)
         {
-            Agenda::calculate_transactions_hash(&[transaction])
+            Agenda::calculate_transactions_hash(&[transaction], ENCODING)
         } else {
             panic!("generate_empty_transaction_commit should return Commit::Transaction type value")
         };
@@ -1225,7 +2013,7 @@ mod test {
         csv.apply_commit(&generate_empty_transaction_commit(&validator_keypair, 0, 1))
             .unwrap();
         // Apply agenda commit with invalid agenda hash
-        let agenda_transactions_hash = Agenda::calculate_transactions_hash(&[]);
+        let agenda_transactions_hash = Agenda::calculate_transactions_hash(&[], ENCODING);
         let agenda: Agenda = Agenda {
             author: validator_keypair[0].0.clone(),
             timestamp: 2,
@@ -1247,7 +2035,7 @@ mod test {
         let agenda: Agenda = Agenda {
             author: validator_keypair[0].0.clone(),
             timestamp: 0,
-            transactions_hash: Agenda::calculate_transactions_hash(&[]),
+            transactions_hash: Agenda::calculate_transactions_hash(&[], ENCODING),
             height: csv.header.height + 1,
         };
         csv.apply_commit(&generate_agenda_commit(&agenda))
@@ -1450,5 +2238,1118 @@ mod test {
         .unwrap_err();
     }
 
-    // TODO: add test case where extra-agenda transactions are invalid.
+    fn generate_delegate_commit(
+        validator_keypair: &[(PublicKey, PrivateKey)],
+        delegator_index: usize,
+        delegatee_index: usize,
+        conditions: Vec<DelegationCondition>,
+        block_height: BlockHeight,
+        time: Timestamp,
+    ) -> Commit {
+        let (delegator, private_key) = validator_keypair[delegator_index].clone();
+        let delegatee = validator_keypair[delegatee_index].0.clone();
+        let proof = TypedSignature::sign(
+            &(
+                delegator.clone(),
+                delegatee.clone(),
+                true,
+                conditions.clone(),
+                block_height,
+            ),
+            &private_key,
+        )
+        .unwrap();
+        Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Delegate(TxDelegate {
+            delegator,
+            delegatee,
+            governance: true,
+            conditions,
+            block_height,
+            proof,
+            timestamp: time,
+        }))
+    }
+
+    fn generate_undelegate_commit(
+        validator_keypair: &[(PublicKey, PrivateKey)],
+        delegator_index: usize,
+        block_height: BlockHeight,
+        time: Timestamp,
+    ) -> Commit {
+        let (delegator, private_key) = validator_keypair[delegator_index].clone();
+        let proof = TypedSignature::sign(&(delegator.clone(), block_height), &private_key).unwrap();
+        Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Undelegate(TxUndelegate {
+            delegator,
+            block_height,
+            proof,
+            timestamp: time,
+        }))
+    }
+
+    #[test]
+    /// Test the case where the delegation changes the validator set of the next block.
+    fn correct_commit_sequence_with_delegation() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        // Apply extra-agenda transaction commits
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![],
+            1,
+            2,
+        ))
+        .unwrap();
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            2,
+            1,
+            vec![],
+            1,
+            3,
+        ))
+        .unwrap();
+        csv.apply_commit(&generate_undelegate_commit(&validator_keypair, 2, 1, 4))
+            .unwrap();
+        // Apply block commit with the validator set not reflecting the delegation
+        let mut block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            5,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        csv.apply_commit(&block_commit).unwrap_err();
+        // Apply block commit with the delegated validator set
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = vec![
+                (validator_keypair[1].0.clone(), 2),
+                (validator_keypair[2].0.clone(), 1),
+            ];
+        }
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    #[test]
+    /// Test the case where the extra-agenda transactions are invalid.
+    fn invalid_extra_agenda_transaction_commits() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply extra-agenda transaction commit at agenda phase
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![],
+            1,
+            2,
+        ))
+        .unwrap_err();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        // Apply delegation with invalid block height
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![],
+            2,
+            2,
+        ))
+        .unwrap_err();
+        // Apply self-delegation
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            0,
+            vec![],
+            1,
+            2,
+        ))
+        .unwrap_err();
+        // Apply undelegation of a member that has not delegated
+        csv.apply_commit(&generate_undelegate_commit(&validator_keypair, 0, 1, 2))
+            .unwrap_err();
+        // Apply delegation
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![],
+            1,
+            3,
+        ))
+        .unwrap();
+        // Apply delegation chain
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            2,
+            0,
+            vec![],
+            1,
+            3,
+        ))
+        .unwrap_err();
+        // Apply undelegation with invalid timestamp
+        csv.apply_commit(&generate_undelegate_commit(&validator_keypair, 0, 1, 2))
+            .unwrap_err();
+    }
+
+    #[test]
+    /// Test the case where the delegation is released before the next block.
+    fn correct_commit_sequence_with_expired_delegation() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        // Apply delegation which is released at timestamp 5
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![DelegationCondition::UnlockAtTimestamp(5)],
+            1,
+            2,
+        ))
+        .unwrap();
+        // Apply block commit with the delegated validator set
+        let mut block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            5,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = vec![
+                (validator_keypair[1].0.clone(), 2),
+                (validator_keypair[2].0.clone(), 1),
+            ];
+        }
+        csv.clone().apply_commit(&block_commit).unwrap_err();
+        // Apply block commit with the validator set where the voting power fell back to the delegator
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = validator_keypair
+                .iter()
+                .map(|(public_key, _)| (public_key.clone(), 1))
+                .collect();
+        }
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    #[test]
+    /// Test the case where the delegation is released because the delegatee is inactive.
+    fn correct_commit_sequence_with_inactive_delegatee() {
+        let (validator_keypair, _, mut csv) = setup_test(4);
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        // Apply delegation which is released while the delegatee is inactive
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![DelegationCondition::UnlockIfDelegateeInactive],
+            1,
+            2,
+        ))
+        .unwrap();
+        let mut block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            5,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        let delegated_validator_set = vec![
+            (validator_keypair[1].0.clone(), 2),
+            (validator_keypair[2].0.clone(), 1),
+            (validator_keypair[3].0.clone(), 1),
+        ];
+        // Apply block commit with the delegated validator set, where the delegatee signed the last block
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = delegated_validator_set.clone();
+        }
+        csv.clone().apply_commit(&block_commit).unwrap();
+        // Apply block commit with the delegated validator set, where the delegatee didn't sign the last block
+        if let Commit::Block(header) = &mut block_commit {
+            header.prev_block_finalization_proof = generate_unanimous_finalization_proof(
+                &[
+                    validator_keypair[0].clone(),
+                    validator_keypair[2].clone(),
+                    validator_keypair[3].clone(),
+                ],
+                &csv.header,
+            );
+        }
+        csv.clone().apply_commit(&block_commit).unwrap_err();
+        // Apply block commit with the validator set where the voting power fell back to the delegator
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = validator_keypair
+                .iter()
+                .map(|(public_key, _)| (public_key.clone(), 1))
+                .collect();
+        }
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    fn generate_double_vote_report_commit(
+        validator_keypair: &[(PublicKey, PrivateKey)],
+        genesis_hash: Hash256,
+        voter_index: usize,
+        headers: Vec<BlockHeader>,
+        time: Timestamp,
+    ) -> Commit {
+        let height = headers[0].height + 1;
+        let sign = |block_hash| {
+            let vote = ConsensusVote {
+                genesis_hash,
+                kind: ConsensusVoteKind::Precommit,
+                height,
+                round: 0,
+                block_hash: Some(block_hash),
+            };
+            SignedConsensusVote {
+                signature: TypedSignature::sign(&vote, &validator_keypair[voter_index].1).unwrap(),
+                vote,
+            }
+        };
+        Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Report(TxReport {
+            misbehavior: Misbehavior::DoubleVote(
+                sign(Hash256::hash("a")),
+                sign(Hash256::hash("b")),
+            ),
+            headers,
+            timestamp: time,
+        }))
+    }
+
+    #[test]
+    /// Test the case where the reported validator is excluded from the validator set of the next block.
+    fn correct_commit_sequence_with_report() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        let genesis_hash = csv.reserved_state.genesis_info.header.to_hash256();
+        let start_header = csv.header.clone();
+        // Finalize the first block, of which the consensus is the evidence
+        apply_agenda_and_proof(&validator_keypair, &mut csv, 1);
+        csv.apply_commit(&generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            2,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        ))
+        .unwrap();
+        apply_agenda_and_proof(&validator_keypair, &mut csv, 3);
+        // Apply report with the evidence of a height that is not finalized yet
+        csv.apply_commit(&generate_double_vote_report_commit(
+            &validator_keypair,
+            genesis_hash,
+            2,
+            vec![csv.header.clone()],
+            4,
+        ))
+        .unwrap_err();
+        // Apply report
+        csv.apply_commit(&generate_double_vote_report_commit(
+            &validator_keypair,
+            genesis_hash,
+            2,
+            vec![start_header.clone()],
+            4,
+        ))
+        .unwrap();
+        // Apply report for the same validator again
+        csv.apply_commit(&generate_double_vote_report_commit(
+            &validator_keypair,
+            genesis_hash,
+            2,
+            vec![start_header],
+            5,
+        ))
+        .unwrap_err();
+        // Apply block commit with the validator set including the reported validator
+        let mut block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            6,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        csv.apply_commit(&block_commit).unwrap_err();
+        // Apply block commit with the validator set excluding the reported validator
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = vec![
+                (validator_keypair[0].0.clone(), 1),
+                (validator_keypair[1].0.clone(), 1),
+            ];
+        }
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    fn generate_chat_log_commit(
+        validator_keypair: &[(PublicKey, PrivateKey)],
+        genesis_hash: Hash256,
+        messages: Vec<(usize, Timestamp)>,
+        height: BlockHeight,
+    ) -> Commit {
+        Commit::ChatLog(ChatLog {
+            height,
+            messages: messages
+                .into_iter()
+                .map(|(author_index, timestamp)| {
+                    let (author, private_key) = validator_keypair[author_index].clone();
+                    let content = format!("message from {} at {}", author_index, timestamp);
+                    ChatMessage {
+                        author,
+                        timestamp,
+                        signature: TypedSignature::sign(
+                            &(genesis_hash, height, timestamp, content.clone()),
+                            &private_key,
+                        )
+                        .unwrap(),
+                        content,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    /// Test the case where the chat logs are archived along with the extra-agenda transactions.
+    fn correct_commit_sequence_with_chat_logs() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        let genesis_hash = csv.reserved_state.genesis_info.header.to_hash256();
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply chat log commit at agenda phase
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(0, 1)],
+            1,
+        ))
+        .unwrap_err();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        // Apply chat log commits interleaved with an extra-agenda transaction commit
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(0, 1), (1, 2), (0, 2)],
+            1,
+        ))
+        .unwrap();
+        csv.apply_commit(&generate_delegate_commit(
+            &validator_keypair,
+            0,
+            1,
+            vec![],
+            1,
+            2,
+        ))
+        .unwrap();
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(2, 3)],
+            1,
+        ))
+        .unwrap();
+        // Apply block commit
+        let mut block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            4,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        if let Commit::Block(header) = &mut block_commit {
+            header.validator_set = vec![
+                (validator_keypair[1].0.clone(), 2),
+                (validator_keypair[2].0.clone(), 1),
+            ];
+        }
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    #[test]
+    /// Test the case where the chat logs are invalid.
+    fn invalid_chat_log_commits() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        let genesis_hash = csv.reserved_state.genesis_info.header.to_hash256();
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        // Apply chat log with invalid height
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(0, 1)],
+            2,
+        ))
+        .unwrap_err();
+        // Apply chat log with messages going backward
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(0, 2), (1, 1)],
+            1,
+        ))
+        .unwrap_err();
+        // Apply chat log from a non-member
+        let mut keypair = validator_keypair.clone();
+        keypair.push(generate_keypair_random());
+        csv.apply_commit(&generate_chat_log_commit(
+            &keypair,
+            genesis_hash,
+            vec![(3, 1)],
+            1,
+        ))
+        .unwrap_err();
+        // Apply chat log with a message signed by another member
+        let mut chat_log =
+            generate_chat_log_commit(&validator_keypair, genesis_hash, vec![(0, 1)], 1);
+        if let Commit::ChatLog(chat_log) = &mut chat_log {
+            chat_log.messages[0].author = validator_keypair[1].0.clone();
+        }
+        csv.apply_commit(&chat_log).unwrap_err();
+        // Apply chat log signed for another chain
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            Hash256::hash("another chain"),
+            vec![(0, 1)],
+            1,
+        ))
+        .unwrap_err();
+        // Apply chat log with a tampered message
+        let mut chat_log =
+            generate_chat_log_commit(&validator_keypair, genesis_hash, vec![(0, 1)], 1);
+        if let Commit::ChatLog(chat_log) = &mut chat_log {
+            chat_log.messages[0].content = "tampered".to_string();
+        }
+        csv.apply_commit(&chat_log).unwrap_err();
+        // Apply chat log
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(0, 2)],
+            1,
+        ))
+        .unwrap();
+        // Apply chat log which is earlier than the last one
+        csv.apply_commit(&generate_chat_log_commit(
+            &validator_keypair,
+            genesis_hash,
+            vec![(1, 1)],
+            1,
+        ))
+        .unwrap_err();
+    }
+
+    #[test]
+    /// Test the case where a reserved-diff transaction has an invalid reserved state.
+    fn invalid_reserved_diff() {
+        let (validator_keypair, reserved_state, mut csv) = setup_test(3);
+        let transaction = |reserved_state: ReservedState| {
+            Commit::Transaction(Transaction {
+                author: validator_keypair[0].0.clone(),
+                timestamp: 1,
+                head: "Test reserved-diff commit".to_string(),
+                body: String::new(),
+                diff: Diff::General(Box::new(reserved_state), Hash256::zero()),
+            })
+        };
+        let mut state = reserved_state.clone();
+        state.consensus_leader_order.push("member9".to_string());
+        assert!(matches!(
+            csv.apply_commit(&transaction(state)),
+            Err(Error::InvalidReservedState(
+                ReservedStateError::UnknownLeader(_)
+            ))
+        ));
+        let mut state = reserved_state.clone();
+        state.genesis_info.chain_name = "Another Chain".to_string();
+        assert!(matches!(
+            csv.apply_commit(&transaction(state)),
+            Err(Error::InvalidReservedState(
+                ReservedStateError::InconsistentGenesisInfo(_)
+            ))
+        ));
+        csv.apply_commit(&transaction(reserved_state)).unwrap();
+    }
+
+    #[test]
+    /// Test the case where the repository merkle root is checked against the non-reserved state.
+    fn repository_merkle_root_with_non_reserved_diff() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        // Apply general-diff commit, which invalidates the non-reserved state root
+        csv.apply_commit(&generate_general_diff_transaction_commit(
+            &validator_keypair,
+            0,
+            1,
+        ))
+        .unwrap();
+        // Apply agenda commit
+        let agenda_transactions_hash = calculate_agenda_transactions_hash(csv.phase.clone());
+        let agenda: Agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 2,
+            transactions_hash: agenda_transactions_hash,
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply agenda-proof commit
+        csv.apply_commit(&generate_agenda_proof_commit(
+            &validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+        let non_reserved_state_root = FileMerkleTree::create(vec![(
+            "README.md".to_string(),
+            "The actual content of the diff".as_bytes().to_vec(),
+        )])
+        .root();
+        let block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            3,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                non_reserved_state_root,
+            ),
+        );
+        // Apply block commit without the non-reserved state root
+        csv.apply_commit(&block_commit).unwrap_err();
+        // Apply block commit with an invalid non-reserved state root
+        csv.set_non_reserved_state_root(OneshotMerkleTree::EMPTY_HASH);
+        csv.apply_commit(&block_commit).unwrap_err();
+        // Apply block commit with the valid non-reserved state root
+        csv.set_non_reserved_state_root(non_reserved_state_root);
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    #[test]
+    /// Test the case where the actual reserved directory doesn't match the reserved state.
+    fn repository_merkle_root_with_mismatched_reserved_directory() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        apply_agenda_and_proof(&validator_keypair, &mut csv, 1);
+        let block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            2,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        // The reserved directory is missing a file that the reserved state has
+        let mut files = csv.reserved_state.to_files();
+        files.pop();
+        csv.set_reserved_state_root(FileMerkleTree::create(files).root());
+        csv.apply_commit(&block_commit).unwrap_err();
+        // The reserved directory matches the reserved state
+        csv.set_reserved_state_root(FileMerkleTree::create(csv.reserved_state.to_files()).root());
+        csv.apply_commit(&block_commit).unwrap();
+    }
+
+    /// Applies an agenda and its proof for the current transactions.
+    fn apply_agenda_and_proof(
+        validator_keypair: &[(PublicKey, PrivateKey)],
+        csv: &mut CommitSequenceVerifier,
+        time: Timestamp,
+    ) {
+        let agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: time,
+            transactions_hash: calculate_agenda_transactions_hash(csv.phase.clone()),
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        csv.apply_commit(&generate_agenda_proof_commit(
+            validator_keypair,
+            &agenda,
+            agenda.to_hash256(),
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    /// Test the case where the protocol version is upgraded by a reserved-diff transaction.
+    fn protocol_version_upgrade() {
+        let (validator_keypair, mut reserved_state, mut csv) = setup_test(3);
+        reserved_state.version = "0.2.1".to_string();
+        csv.apply_commit(&Commit::Transaction(Transaction {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            head: "Upgrade the protocol".to_string(),
+            body: String::new(),
+            diff: Diff::Reserved(Box::new(reserved_state)),
+        }))
+        .unwrap();
+        apply_agenda_and_proof(&validator_keypair, &mut csv, 2);
+        let mut block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            3,
+            BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+            BlockHeader::calculate_repository_merkle_root(
+                &csv.reserved_state,
+                OneshotMerkleTree::EMPTY_HASH,
+            ),
+        );
+        // The upgraded reserved state requires the new version from this block,
+        // which this node doesn't support, even if the header doesn't follow it.
+        for version in ["0.2.0", "0.2.1"] {
+            if let Commit::Block(header) = &mut block_commit {
+                header.version = version.to_string();
+            }
+            let height = csv.header.height + 1;
+            match csv.apply_commit(&block_commit) {
+                Err(Error::UnsupportedProtocolVersion(h, required, _)) => {
+                    assert_eq!(h, height);
+                    assert_eq!(required, "0.2.1");
+                }
+                result => panic!("unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    /// Test a chain of the first protocol version, which hashes the data in JSON.
+    fn agenda_proof_on_json_encoding_chain() {
+        let (validator_keypair, _, mut csv) = setup_test(3);
+        csv.header.version = "0.1.0".to_string();
+        csv.reserved_state.version = "0.1.0".to_string();
+        let agenda = Agenda {
+            author: validator_keypair[0].0.clone(),
+            timestamp: 1,
+            transactions_hash: Agenda::calculate_transactions_hash(&[], HashEncoding::Json),
+            height: csv.header.height + 1,
+        };
+        csv.apply_commit(&generate_agenda_commit(&agenda)).unwrap();
+        // Apply agenda-proof commit on the canonical hash
+        csv.clone()
+            .apply_commit(&generate_agenda_proof_commit(
+                &validator_keypair,
+                &agenda,
+                agenda.to_hash256_with(HashEncoding::Canonical),
+            ))
+            .unwrap_err();
+        // Apply agenda-proof commit on the JSON hash
+        csv.apply_commit(&Commit::AgendaProof(AgendaProof {
+            agenda_hash: agenda.to_hash256_with(HashEncoding::Json),
+            proof: validator_keypair
+                .iter()
+                .map(|(_, private_key)| {
+                    TypedSignature::sign_with(&agenda, HashEncoding::Json, private_key).unwrap()
+                })
+                .collect(),
+            height: agenda.height,
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    /// Test the case where the protocol version of a block header goes down or is invalid.
+    fn invalid_header_version() {
+        let (validator_keypair, _, csv) = setup_test(3);
+        let block_commit = generate_block_commit(
+            &validator_keypair,
+            0,
+            csv.header.clone(),
+            1,
+            OneshotMerkleTree::EMPTY_HASH,
+            Hash256::zero(),
+        );
+        let mut header = match block_commit {
+            Commit::Block(header) => header,
+            _ => unreachable!(),
+        };
+        verify_header_to_header(&csv.header, &header).unwrap();
+        header.version = "0.1.0".to_string();
+        verify_header_to_header(&csv.header, &header).unwrap_err();
+        header.version = "0.2".to_string();
+        verify_header_to_header(&csv.header, &header).unwrap_err();
+    }
+
+    #[test]
+    /// Test the case where the block timestamp is far ahead of the local clock.
+    fn block_timestamp_ahead_of_local_clock() {
+        let (validator_keypair, _, csv) = setup_test(3);
+        let time = local_timestamp() + DEFAULT_MAX_CLOCK_DRIFT * 10;
+        for (options, valid) in [
+            (VerifierOptions::default(), false),
+            (VerifierOptions::historical(), true),
+        ] {
+            let mut csv = csv.clone();
+            csv.options = options;
+            apply_agenda_and_proof(&validator_keypair, &mut csv, time);
+            let block_commit = generate_block_commit(
+                &validator_keypair,
+                0,
+                csv.header.clone(),
+                time,
+                BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+                BlockHeader::calculate_repository_merkle_root(
+                    &csv.reserved_state,
+                    OneshotMerkleTree::EMPTY_HASH,
+                ),
+            );
+            assert_eq!(csv.apply_commit(&block_commit).is_ok(), valid);
+        }
+    }
+
+    #[test]
+    /// Test the case where the block timestamp is too far from the commits it contains.
+    fn block_timestamp_apart_from_commits() {
+        let (validator_keypair, _, csv) = setup_test(3);
+        let max_commit_gap = 100;
+        for (time, options, valid) in [
+            (1 + max_commit_gap, Some(max_commit_gap), true),
+            (2 + max_commit_gap, Some(max_commit_gap), false),
+            (
+                2 + max_commit_gap,
+                VerifierOptions::historical().max_commit_gap,
+                true,
+            ),
+        ] {
+            let mut csv = csv.clone();
+            csv.options.max_commit_gap = options;
+            csv.apply_commit(&generate_empty_transaction_commit(&validator_keypair, 0, 1))
+                .unwrap();
+            apply_agenda_and_proof(&validator_keypair, &mut csv, 2);
+            let block_commit = generate_block_commit(
+                &validator_keypair,
+                0,
+                csv.header.clone(),
+                time,
+                BlockHeader::calculate_commit_merkle_root(&csv.next_block_commits, ENCODING),
+                BlockHeader::calculate_repository_merkle_root(
+                    &csv.reserved_state,
+                    OneshotMerkleTree::EMPTY_HASH,
+                ),
+            );
+            assert_eq!(csv.apply_commit(&block_commit).is_ok(), valid);
+        }
+    }
+
+    #[test]
+    /// Test the case where the validators use different signature schemes.
+    fn finalization_proof_with_mixed_signature_schemes() {
+        let validator_keypair: Vec<(PublicKey, PrivateKey)> = (0..4)
+            .map(|i| {
+                let scheme = if i % 2 == 0 {
+                    SignatureScheme::Secp256k1
+                } else {
+                    SignatureScheme::Ed25519
+                };
+                generate_keypair_with_scheme([i], scheme)
+            })
+            .collect();
+        let header = generate_block_header(
+            &validator_keypair,
+            0,
+            FinalizationProof::default(),
+            Hash256::zero(),
+            0,
+            0,
+            OneshotMerkleTree::create(vec![]).root(),
+        );
+        let proof = generate_unanimous_finalization_proof(&validator_keypair, &header);
+        verify_finalization_proof(&header, &proof).unwrap();
+        let partial_proof =
+            vec![TypedSignature::sign(&header, &validator_keypair[1].1).unwrap()].into();
+        verify_finalization_proof(&header, &partial_proof).unwrap_err();
+        let reserved_state = generate_reserved_state(&validator_keypair, 0, 0);
+        reserved_state.validate().unwrap();
+    }
+
+    #[test]
+    /// Test the case where the finalization proof is in the aggregated form.
+    fn aggregated_finalization_proof() {
+        let validator_keypair: Vec<(PublicKey, PrivateKey)> = (0..10)
+            .map(|i| generate_keypair_with_scheme([i], SignatureScheme::Bls12381))
+            .collect();
+        let header = generate_block_header(
+            &validator_keypair,
+            0,
+            FinalizationProof::default(),
+            Hash256::zero(),
+            0,
+            0,
+            OneshotMerkleTree::create(vec![]).root(),
+        );
+        let aggregate = |keypair: &[(PublicKey, PrivateKey)]| {
+            let signatures = keypair
+                .iter()
+                .map(|(_, private_key)| TypedSignature::sign(&header, private_key).unwrap())
+                .collect::<Vec<_>>();
+            AggregatedFinalizationProof::aggregate(&header.validator_set, &signatures).unwrap()
+        };
+        let proof = aggregate(&validator_keypair[3..]);
+        assert_eq!(proof.signers, vec![0b1111_1000, 0b11]);
+        verify_finalization_proof(&header, &FinalizationProof::Aggregated(proof.clone())).unwrap();
+        // 6 of 10 is not enough.
+        let low_proof = aggregate(&validator_keypair[4..]);
+        assert!(matches!(
+            verify_finalization_proof(&header, &FinalizationProof::Aggregated(low_proof)),
+            Err(Error::InvalidProof(_))
+        ));
+        // The bitmap claims a signer that didn't sign.
+        let mut forged_proof = proof.clone();
+        forged_proof.signers[0] |= 1;
+        assert!(matches!(
+            verify_finalization_proof(&header, &FinalizationProof::Aggregated(forged_proof)),
+            Err(Error::CryptoError(_, _))
+        ));
+        // The bitmap marks a validator out of the set.
+        let mut forged_proof = proof.clone();
+        forged_proof.signers[1] |= 0b100;
+        verify_finalization_proof(&header, &FinalizationProof::Aggregated(forged_proof))
+            .unwrap_err();
+        let mut forged_proof = proof;
+        forged_proof.signers.push(0);
+        verify_finalization_proof(&header, &FinalizationProof::Aggregated(forged_proof))
+            .unwrap_err();
+        // A signer out of the validator set can't be aggregated.
+        let (_, outsider) = generate_keypair_with_scheme([10], SignatureScheme::Bls12381);
+        AggregatedFinalizationProof::aggregate(
+            &header.validator_set,
+            &[TypedSignature::sign(&header, &outsider).unwrap()],
+        )
+        .unwrap_err();
+        // The BLS members must prove the possession of their keys.
+        let mut reserved_state = generate_reserved_state(&validator_keypair, 0, 0);
+        assert!(matches!(
+            reserved_state.validate(),
+            Err(ReservedStateError::InvalidProofOfPossession(_))
+        ));
+        for (member, (_, private_key)) in reserved_state.members.iter_mut().zip(&validator_keypair)
+        {
+            member.proof_of_possession = Some(Signature::prove_possession(private_key).unwrap());
+        }
+        reserved_state.validate().unwrap();
+        let proof = reserved_state.members[1].proof_of_possession.clone();
+        reserved_state.members[0].proof_of_possession = proof;
+        assert!(matches!(
+            reserved_state.validate(),
+            Err(ReservedStateError::InvalidProofOfPossession(_))
+        ));
+    }
+
+    #[test]
+    /// Test the case where the signatures of the commit sequence are verified in advance in a batch.
+    fn preverified_commit_sequence() {
+        let (validator_keypair, _, mut csv) = setup_test(4);
+        let mut generator = csv.clone();
+        for i in 0..5 {
+            apply_agenda_and_proof(&validator_keypair, &mut generator, 2 * i + 1);
+            let block_commit = generate_block_commit(
+                &validator_keypair,
+                0,
+                generator.header.clone(),
+                2 * i + 2,
+                BlockHeader::calculate_commit_merkle_root(&generator.next_block_commits, ENCODING),
+                BlockHeader::calculate_repository_merkle_root(
+                    &generator.reserved_state,
+                    OneshotMerkleTree::EMPTY_HASH,
+                ),
+            );
+            generator.apply_commit(&block_commit).unwrap();
+        }
+        let commits = generator.get_total_commits()[1..].to_vec();
+
+        let mut forged_commits = commits.clone();
+        if let Commit::AgendaProof(agenda_proof) = &mut forged_commits[4] {
+            agenda_proof.proof[0] = TypedSignature::new(
+                agenda_proof.proof[1].get_raw_signature(),
+                agenda_proof.proof[0].signer().clone(),
+            );
+        } else {
+            panic!("expected an agenda proof");
+        }
+        assert!(matches!(
+            csv.clone().preverify_signatures(&forged_commits),
+            Err(Error::InvalidProof(_))
+        ));
+
+        csv.preverify_signatures(&commits).unwrap();
+        // 4 signatures for each of the agenda proofs and the finalization proofs.
+        assert_eq!(csv.verified_signatures.len(), 5 * 2 * 4);
+        for commit in &commits {
+            csv.apply_commit(commit).unwrap();
+        }
+        assert_eq!(csv.header, generator.header);
+    }
+
+    #[test]
+    /// Test the case where the verification is streamed and resumed from a checkpoint.
+    fn streaming_with_checkpoint() {
+        let (validator_keypair, _, csv) = setup_test(3);
+        let mut generator = csv.clone();
+        for i in 0..4 {
+            apply_agenda_and_proof(&validator_keypair, &mut generator, 2 * i + 1);
+            let block_commit = generate_block_commit(
+                &validator_keypair,
+                0,
+                generator.header.clone(),
+                2 * i + 2,
+                BlockHeader::calculate_commit_merkle_root(&generator.next_block_commits, ENCODING),
+                BlockHeader::calculate_repository_merkle_root(
+                    &generator.reserved_state,
+                    OneshotMerkleTree::EMPTY_HASH,
+                ),
+            );
+            generator.apply_commit(&block_commit).unwrap();
+        }
+        let commits = generator.get_total_commits()[1..].to_vec();
+        assert_eq!(commits.len(), 12);
+
+        let mut csv = CommitSequenceVerifier::new_streaming(
+            csv.header.clone(),
+            csv.reserved_state.clone(),
+            VerifierOptions::default(),
+        )
+        .unwrap();
+        csv.set_non_reserved_state_root(OneshotMerkleTree::EMPTY_HASH);
+        csv.preverify_signatures(&commits[0..5]).unwrap();
+        for commit in &commits[0..5] {
+            csv.apply_commit(commit).unwrap();
+        }
+        // Only the commits since the last block header are kept.
+        assert_eq!(csv.get_total_commits(), &commits[2..5]);
+        assert_eq!(csv.get_block_headers().len(), 1);
+        // The signatures of the applied proofs are dropped too.
+        assert!(csv.verified_signatures.is_empty());
+        csv.checkpoint().unwrap_err();
+        csv.apply_commit(&commits[5]).unwrap();
+        let checkpoint = csv.checkpoint().unwrap();
+
+        // Resume from the serialized checkpoint.
+        let checkpoint: VerifierCheckpoint =
+            serde_spb::from_str(&serde_spb::to_string(&checkpoint).unwrap()).unwrap();
+        let mut resumed =
+            CommitSequenceVerifier::from_checkpoint(checkpoint, VerifierOptions::default())
+                .unwrap();
+        for commit in &commits[6..] {
+            resumed.apply_commit(commit).unwrap();
+        }
+        assert_eq!(resumed.header, generator.header);
+        assert_eq!(resumed.get_total_commits(), &commits[11..]);
+        assert_eq!(
+            resumed.checkpoint().unwrap(),
+            generator.checkpoint().unwrap()
+        );
+    }
 }
@@ -1,6 +1,9 @@
 use light_client::*;
 use merkle_tree::*;
-use simperby_common::{verify::CommitSequenceVerifier, *};
+use simperby_common::{
+    verify::{CommitSequenceVerifier, VerifierOptions},
+    *,
+};
 use simperby_test_suite::*;
 
 #[test]
@@ -9,8 +12,11 @@ fn basic1() {
     let (rs, keys) = generate_standard_genesis(member_number);
     let genesis_info = rs.genesis_info.clone();
     let genesis_header = rs.genesis_info.header.clone();
+    let encoding = genesis_header.hash_encoding().unwrap();
 
-    let mut csv = CommitSequenceVerifier::new(genesis_header.clone(), rs).unwrap();
+    let mut csv =
+        CommitSequenceVerifier::new(genesis_header.clone(), rs, VerifierOptions::default())
+            .unwrap();
     let mut light_client = LightClient::new(genesis_header);
 
     let tx = Transaction {
@@ -25,18 +31,29 @@ fn basic1() {
         height: 1,
         author: keys[0].0.clone(),
         timestamp: 0,
-        transactions_hash: Agenda::calculate_transactions_hash(&[tx.clone()]),
+        transactions_hash: Agenda::calculate_transactions_hash(&[tx.clone()], encoding),
     };
     csv.apply_commit(&Commit::Agenda(agenda.clone())).unwrap();
     csv.apply_commit(&Commit::AgendaProof(AgendaProof {
         height: 1,
-        agenda_hash: agenda.to_hash256(),
+        agenda_hash: agenda.to_hash256_with(encoding),
         proof: keys
             .iter()
-            .map(|(_, private_key)| TypedSignature::sign(&agenda, private_key).unwrap())
+            .map(|(_, private_key)| {
+                TypedSignature::sign_with(&agenda, encoding, private_key).unwrap()
+            })
             .collect::<Vec<_>>(),
     }))
     .unwrap();
+    let repository_merkle_tree = RepositoryMerkleTree::create(
+        csv.get_reserved_state()
+            .to_files()
+            .into_iter()
+            .chain(vec![("README.md".to_owned(), b"hello simperby".to_vec())])
+            .collect(),
+    );
+    csv.set_reserved_state_root(repository_merkle_tree.reserved_root());
+    csv.set_non_reserved_state_root(repository_merkle_tree.non_reserved_root());
     let block_header = BlockHeader {
         author: keys[0].0.clone(),
         prev_block_finalization_proof: genesis_info.genesis_proof,
@@ -45,8 +62,9 @@ fn basic1() {
         timestamp: 0,
         commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
             &csv.get_total_commits()[1..],
+            encoding,
         ),
-        repository_merkle_root: Hash256::zero(),
+        repository_merkle_root: repository_merkle_tree.root(),
         validator_set: genesis_info.header.validator_set.clone(),
         version: genesis_info.header.version,
     };
@@ -55,17 +73,45 @@ fn basic1() {
     let fp = keys
         .iter()
         .map(|(_, private_key)| TypedSignature::sign(&block_header, private_key).unwrap())
-        .collect::<Vec<_>>();
+        .collect::<FinalizationProof>();
     csv.verify_last_header_finalization(&fp).unwrap();
     light_client.update(block_header, fp).unwrap();
     let merkle_tree = OneshotMerkleTree::create(
         csv.get_total_commits()[1..=3]
             .iter()
-            .map(|c| c.to_hash256())
+            .map(|c| c.to_hash256_with(encoding))
             .collect(),
     );
-    let merkle_proof = merkle_tree.create_merkle_proof(tx.to_hash256()).unwrap();
-    assert!(light_client.verify_commitment(serde_spb::to_vec(&tx).unwrap(), 1, merkle_proof));
+    let merkle_proof = merkle_tree
+        .create_merkle_proof(tx.to_hash256_with(encoding))
+        .unwrap();
+    assert!(light_client.verify_commitment(
+        serde_spb::to_hashable_vec_with(&tx, encoding).unwrap(),
+        1,
+        merkle_proof
+    ));
+    let state_proof = repository_merkle_tree
+        .create_merkle_proof("README.md", b"hello simperby")
+        .unwrap();
+    assert!(light_client.verify_state("README.md", b"hello simperby", 1, state_proof.clone()));
+    assert!(!light_client.verify_state("README.md", b"hello world", 1, state_proof));
+    let (path, content) = csv.get_reserved_state().to_files().pop().unwrap();
+    let state_proof = repository_merkle_tree
+        .create_merkle_proof(&path, &content)
+        .unwrap();
+    assert!(light_client.verify_state(&path, &content, 1, state_proof));
+    let absence_proof = repository_merkle_tree
+        .create_non_membership_proof("LICENSE")
+        .unwrap();
+    assert!(light_client.verify_state_absence("LICENSE", 1, absence_proof.clone()));
+    assert!(!light_client.verify_state_absence("README.md", 1, absence_proof));
+    assert!(repository_merkle_tree
+        .create_non_membership_proof("README.md")
+        .is_none());
+    let absence_proof = repository_merkle_tree
+        .create_non_membership_proof("reserved/members/nobody.json")
+        .unwrap();
+    assert!(light_client.verify_state_absence("reserved/members/nobody.json", 1, absence_proof));
 }
 
 #[test]
@@ -76,9 +122,14 @@ fn basic2() {
         generate_delegated_genesis(member_number);
     let genesis_info = reserved_state.genesis_info.clone();
     let genesis_header = reserved_state.genesis_info.header.clone();
+    let encoding = genesis_header.hash_encoding().unwrap();
 
-    let mut csv =
-        CommitSequenceVerifier::new(genesis_header.clone(), reserved_state.clone()).unwrap();
+    let mut csv = CommitSequenceVerifier::new(
+        genesis_header.clone(),
+        reserved_state.clone(),
+        VerifierOptions::default(),
+    )
+    .unwrap();
     let mut light_client = LightClient::new(genesis_header);
 
     let tx = Transaction {
@@ -93,18 +144,21 @@ fn basic2() {
         height: 1,
         author: keys[0].0.clone(), // Note that keys[0] is member-0001
         timestamp: 0,
-        transactions_hash: Agenda::calculate_transactions_hash(&[tx.clone()]),
+        transactions_hash: Agenda::calculate_transactions_hash(&[tx.clone()], encoding),
     };
     csv.apply_commit(&Commit::Agenda(agenda.clone())).unwrap();
     csv.apply_commit(&Commit::AgendaProof(AgendaProof {
         height: 1,
-        agenda_hash: agenda.to_hash256(),
+        agenda_hash: agenda.to_hash256_with(encoding),
         proof: keys
             .iter()
-            .map(|(_, private_key)| TypedSignature::sign(&agenda, private_key).unwrap())
+            .map(|(_, private_key)| {
+                TypedSignature::sign_with(&agenda, encoding, private_key).unwrap()
+            })
             .collect::<Vec<_>>(),
     }))
     .unwrap();
+    csv.set_non_reserved_state_root(OneshotMerkleTree::EMPTY_HASH);
     let block_header = BlockHeader {
         author: keys[0].0.clone(), // Note that keys[0] is member-0001
         prev_block_finalization_proof: genesis_info.genesis_proof,
@@ -113,10 +167,14 @@ fn basic2() {
         timestamp: 0,
         commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
             &csv.get_total_commits()[1..],
+            encoding,
+        ),
+        repository_merkle_root: BlockHeader::calculate_repository_merkle_root(
+            csv.get_reserved_state(),
+            OneshotMerkleTree::EMPTY_HASH,
         ),
-        repository_merkle_root: Hash256::zero(),
         // Note that validator set here is from member-0001 to member-0009
-        validator_set: reserved_state.get_validator_set().unwrap(),
+        validator_set: reserved_state.get_validator_set(0, 0, None).unwrap(),
         version: genesis_info.header.version,
     };
     csv.apply_commit(&Commit::Block(block_header.clone()))
@@ -124,15 +182,311 @@ fn basic2() {
     let fp = keys
         .iter()
         .map(|(_, private_key)| TypedSignature::sign(&block_header, private_key).unwrap())
-        .collect::<Vec<_>>();
+        .collect::<FinalizationProof>();
     csv.verify_last_header_finalization(&fp).unwrap();
     light_client.update(block_header, fp).unwrap();
     let merkle_tree = OneshotMerkleTree::create(
         csv.get_total_commits()[1..=3]
             .iter()
-            .map(|c| c.to_hash256())
+            .map(|c| c.to_hash256_with(encoding))
             .collect(),
     );
-    let merkle_proof = merkle_tree.create_merkle_proof(tx.to_hash256()).unwrap();
-    assert!(light_client.verify_commitment(serde_spb::to_vec(&tx).unwrap(), 1, merkle_proof));
+    let merkle_proof = merkle_tree
+        .create_merkle_proof(tx.to_hash256_with(encoding))
+        .unwrap();
+    assert!(light_client.verify_commitment(
+        serde_spb::to_hashable_vec_with(&tx, encoding).unwrap(),
+        1,
+        merkle_proof
+    ));
+    let merkle_multi_proof = merkle_tree
+        .create_merkle_multi_proof(&[
+            agenda.to_hash256_with(encoding),
+            tx.to_hash256_with(encoding),
+        ])
+        .unwrap();
+    let messages = vec![
+        serde_spb::to_hashable_vec_with(&agenda, encoding).unwrap(),
+        serde_spb::to_hashable_vec_with(&tx, encoding).unwrap(),
+    ];
+    assert!(light_client.verify_commitments(&messages, 1, merkle_multi_proof.clone()));
+    assert!(!light_client.verify_commitments(&messages[..1], 1, merkle_multi_proof.clone()));
+    assert!(!light_client.verify_commitments(&messages, 0, merkle_multi_proof));
+}
+
+/// A chain of headers where the validator set rotates by one member every two blocks.
+struct RotatingChain {
+    headers: Vec<(BlockHeader, FinalizationProof)>,
+    fetched_heights: Vec<BlockHeight>,
+}
+
+impl RotatingChain {
+    fn new(length: u64) -> (Self, Vec<(PublicKey, PrivateKey)>) {
+        let keys = (0..length / 2 + 4)
+            .map(|i| generate_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        let mut headers: Vec<(BlockHeader, FinalizationProof)> = Vec::new();
+        for height in 0..length {
+            let (previous_hash, prev_block_finalization_proof) = match headers.last() {
+                Some((header, proof)) => (header.to_hash256(), proof.clone()),
+                None => (Hash256::zero(), FinalizationProof::default()),
+            };
+            let validators = &keys[(height / 2) as usize..(height / 2 + 4) as usize];
+            let header = BlockHeader {
+                author: keys[(height.saturating_sub(1) / 2) as usize].0.clone(),
+                prev_block_finalization_proof,
+                previous_hash,
+                height,
+                timestamp: height as Timestamp,
+                commit_merkle_root: Hash256::hash(format!("commit {}", height)),
+                repository_merkle_root: Hash256::zero(),
+                validator_set: validators
+                    .iter()
+                    .map(|(public_key, _)| (public_key.clone(), 1))
+                    .collect(),
+                version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+            };
+            let proof = sign_header(&header, &keys);
+            headers.push((header, proof));
+        }
+        (
+            RotatingChain {
+                headers,
+                fetched_heights: Vec::new(),
+            },
+            keys,
+        )
+    }
+}
+
+/// Signs the header with the keys in its validator set.
+fn sign_header(header: &BlockHeader, keys: &[(PublicKey, PrivateKey)]) -> FinalizationProof {
+    keys.iter()
+        .filter(|(public_key, _)| header.validator_set.iter().any(|(v, _)| v == public_key))
+        .map(|(_, private_key)| TypedSignature::sign(header, private_key).unwrap())
+        .collect()
+}
+
+impl HeaderSource for RotatingChain {
+    fn get_header(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<(BlockHeader, FinalizationProof), String> {
+        self.fetched_heights.push(height);
+        self.headers
+            .get(height as usize)
+            .cloned()
+            .ok_or_else(|| format!("no header at {}", height))
+    }
+}
+
+#[test]
+fn light_client_skipping() {
+    setup_test();
+    let (mut chain, keys) = RotatingChain::new(100);
+    let mut light_client = LightClient::new(chain.headers[0].0.clone());
+    // The headers of the test chain are as old as the unix epoch.
+    light_client.set_trusting_period(Timestamp::MAX);
+
+    // The validator sets of height 0 and 2 share 3 of 4 members.
+    let (header, proof) = chain.headers[2].clone();
+    light_client.update_skipping(header, proof).unwrap();
+    // The validator sets of height 2 and 10 share no member.
+    let (header, proof) = chain.headers[10].clone();
+    light_client.update_skipping(header, proof).unwrap_err();
+    assert_eq!(light_client.last_header.height, 2);
+
+    light_client.sync(&mut chain, 99).unwrap();
+    assert_eq!(light_client.last_header, chain.headers[99].0);
+    assert!(chain.fetched_heights.len() < 97);
+    let verified_heights = light_client
+        .commit_roots
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    for height in &verified_heights {
+        let message = format!("commit {}", height).into_bytes();
+        assert!(light_client.verify_commitment(
+            message,
+            *height,
+            MerkleProof { proof: Vec::new() }
+        ));
+    }
+    let skipped_height = (3..99).find(|h| !verified_heights.contains(h)).unwrap();
+    assert!(!light_client.verify_commitment(
+        format!("commit {}", skipped_height).into_bytes(),
+        skipped_height,
+        MerkleProof { proof: Vec::new() }
+    ));
+    light_client.sync(&mut chain, 99).unwrap_err();
+
+    // A header finalized at a verified height, but different from the verified one.
+    let (header, proof) = chain.headers[99].clone();
+    assert!(light_client.detect_fork(&header, &proof).is_none());
+    let mut conflicting_header = header;
+    conflicting_header.timestamp += 1;
+    assert!(light_client
+        .detect_fork(&conflicting_header, &proof)
+        .is_none());
+    let conflicting_proof = sign_header(&conflicting_header, &keys);
+    let evidence = light_client
+        .detect_fork(&conflicting_header, &conflicting_proof)
+        .unwrap();
+    assert_eq!(evidence.height, 99);
+    assert_eq!(
+        evidence.trusted_header_hash,
+        chain.headers[99].0.to_hash256()
+    );
+    // A header finalized by a validator set made up by the attacker.
+    let attacker_keys = (0..4)
+        .map(|i| generate_keypair(format!("attacker {}", i)))
+        .collect::<Vec<_>>();
+    conflicting_header.validator_set = attacker_keys
+        .iter()
+        .map(|(public_key, _)| (public_key.clone(), 1))
+        .collect();
+    let fabricated_proof = sign_header(&conflicting_header, &attacker_keys);
+    assert!(light_client
+        .detect_fork(&conflicting_header, &fabricated_proof)
+        .is_none());
+}
+
+#[test]
+fn light_client_trusting_period() {
+    setup_test();
+    let (chain, _) = RotatingChain::new(10);
+    let mut light_client = LightClient::new(chain.headers[0].0.clone());
+    // The headers of the test chain are as old as the unix epoch.
+    let (header, proof) = chain.headers[2].clone();
+    light_client
+        .update_skipping(header.clone(), proof.clone())
+        .unwrap_err();
+    light_client.set_trusting_period(Timestamp::MAX);
+    light_client.update_skipping(header, proof).unwrap();
+    // Verifying the next header doesn't depend on the trusting period.
+    light_client.set_trusting_period(0);
+    let (header, proof) = chain.headers[3].clone();
+    light_client.update(header, proof).unwrap();
+}
+
+#[test]
+fn light_client_pruning() {
+    setup_test();
+    let (chain, _) = RotatingChain::new(30);
+    let mut light_client = LightClient::new(chain.headers[0].0.clone());
+    light_client.set_retention_window(Some(5));
+    for (header, proof) in chain.headers[1..].iter().cloned() {
+        light_client.update(header, proof).unwrap();
+        assert!(light_client.commit_roots.len() <= 5);
+    }
+    assert_eq!(
+        light_client
+            .commit_roots
+            .keys()
+            .copied()
+            .collect::<Vec<_>>(),
+        (25..30).collect::<Vec<_>>()
+    );
+    assert_eq!(light_client.pruned_roots.leaf_count, 25);
+
+    // A prover who knows all the headers can prove the pruned roots.
+    let pruned_roots = chain.headers[..25]
+        .iter()
+        .map(|(header, _)| HistoricalRoots {
+            height: header.height,
+            repository_root: header.repository_merkle_root,
+            commit_root: header.commit_merkle_root,
+        })
+        .collect::<Vec<_>>();
+    let leaves = pruned_roots
+        .iter()
+        .map(|roots| roots.to_hash256())
+        .collect::<Vec<_>>();
+    let roots_proof = MerkleMountainRange::create_proof(&leaves, 7).unwrap();
+    assert!(!light_client.verify_commitment(
+        b"commit 7".to_vec(),
+        7,
+        MerkleProof { proof: Vec::new() }
+    ));
+    assert!(light_client.verify_historical_commitment(
+        b"commit 7".to_vec(),
+        &pruned_roots[7],
+        &roots_proof,
+        MerkleProof { proof: Vec::new() }
+    ));
+    assert!(!light_client.verify_historical_commitment(
+        b"commit 8".to_vec(),
+        &pruned_roots[8],
+        &roots_proof,
+        MerkleProof { proof: Vec::new() }
+    ));
+
+    let checkpoint = light_client.to_checkpoint();
+    let mut restored = LightClient::from_checkpoint(&checkpoint).unwrap();
+    assert_eq!(restored.to_checkpoint(), checkpoint);
+    assert!(restored.verify_historical_commitment(
+        b"commit 7".to_vec(),
+        &pruned_roots[7],
+        &roots_proof,
+        MerkleProof { proof: Vec::new() }
+    ));
+    LightClient::from_checkpoint(&checkpoint[1..]).unwrap_err();
+    restored.set_retention_window(Some(1));
+    assert_eq!(restored.commit_roots.len(), 1);
+    assert_eq!(restored.pruned_roots.leaf_count, 29);
+}
+
+#[test]
+fn light_client_aggregated_proof() {
+    setup_test();
+    let keys = (0..10)
+        .map(|i| generate_keypair_with_scheme(format!("{}", i), SignatureScheme::Bls12381))
+        .collect::<Vec<_>>();
+    let mut header = BlockHeader {
+        author: keys[0].0.clone(),
+        prev_block_finalization_proof: FinalizationProof::default(),
+        previous_hash: Hash256::zero(),
+        height: 0,
+        timestamp: 0,
+        commit_merkle_root: Hash256::zero(),
+        repository_merkle_root: Hash256::zero(),
+        validator_set: keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect(),
+        version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+    };
+    let mut light_client = LightClient::new(header.clone());
+    let aggregate = |header: &BlockHeader, keys: &[(PublicKey, PrivateKey)]| {
+        let signatures = keys
+            .iter()
+            .map(|(_, private_key)| TypedSignature::sign(header, private_key).unwrap())
+            .collect::<Vec<_>>();
+        FinalizationProof::Aggregated(
+            AggregatedFinalizationProof::aggregate(&header.validator_set, &signatures).unwrap(),
+        )
+    };
+    for height in 1..4 {
+        let previous_proof = aggregate(&header, &keys);
+        header = BlockHeader {
+            prev_block_finalization_proof: previous_proof,
+            previous_hash: header.to_hash256(),
+            height,
+            timestamp: height as Timestamp,
+            ..header
+        };
+        // 6 of 10 is not enough to finalize.
+        light_client
+            .update(header.clone(), aggregate(&header, &keys[..6]))
+            .unwrap_err();
+        let proof = aggregate(&header, &keys[..7]);
+        assert!(
+            serde_spb::to_canonical_vec(&proof).unwrap().len()
+                < serde_spb::to_canonical_vec(&sign_header(&header, &keys[..7]))
+                    .unwrap()
+                    .len()
+        );
+        light_client.update(header.clone(), proof).unwrap();
+    }
+    assert_eq!(light_client.last_header, header);
 }
@@ -5,8 +5,10 @@ use eyre::eyre;
 use serde::{Deserialize, Serialize};
 use simperby_common::{
     crypto::{Hash256, PublicKey},
-    serde_spb, BlockHeader, BlockHeight, ConsensusRound, FinalizationProof, PrivateKey, Signature,
-    Timestamp, ToHash256, TypedSignature, VotingPower,
+    serde_spb, AggregatedFinalizationProof, BlockHeader, BlockHeight, ConsensusProposal,
+    ConsensusRound, ConsensusVote, ConsensusVoteKind, FinalizationProof, PrivateKey, Signature,
+    SignedConsensusProposal, SignedConsensusVote, Timestamp, ToHash256, TypedSignature,
+    VotingPower,
 };
 use simperby_network::{
     dms::{DistributedMessageSet as DMS, Message, MessageFilter},
@@ -17,13 +19,22 @@ use std::sync::Arc;
 use vetomint::*;
 
 pub type ConsensusParameters = ConsensusParams;
+pub use vetomint::{TimeoutGrowth, TimeoutParams};
 pub type Error = eyre::Error;
 const STATE_FILE_NAME: &str = "state.json";
 pub type Nil = ();
 const NIL_BLOCK_PROPOSAL_INDEX: BlockIdentifier = BlockIdentifier::MAX;
 
-/// The signed `String` is constructed by `format!("{}-prevote", block_hash)`.
-pub type Prevote = TypedSignature<String>;
+/// The signature on the `ConsensusVote` of a prevote or a precommit, including the nil ones.
+///
+/// It binds the chain, the kind, the height and the round of the vote,
+/// so that two conflicting votes are the evidence of `Misbehavior::DoubleVote`.
+pub type Vote = TypedSignature<ConsensusVote>;
+/// The signature on the `ConsensusProposal` of a proposal.
+///
+/// It binds the chain, the height and the round of the proposal,
+/// so that two conflicting proposals are the evidence of `Misbehavior::DoubleProposal`.
+pub type ProposalSignature = TypedSignature<ConsensusProposal>;
 /// This can be verified by `precommit.get_raw_signature().verify(block_hash, signer)`
 /// where `block_hash` is the hash of `BlockHeader`.
 pub type Precommit = TypedSignature<BlockHeader>;
@@ -62,6 +73,9 @@ fn generate_height_info(
     round_zero_timestamp: Timestamp,
     this_node_key: PrivateKey,
 ) -> Result<HeightInfo, Error> {
+    consensus_params
+        .validate()
+        .map_err(|e| eyre!("invalid consensus parameters: {}", e))?;
     let this_node_index = header
         .validator_set
         .iter()
@@ -119,11 +133,37 @@ pub enum ConsensusMessage {
         round: ConsensusRound,
         valid_round: Option<ConsensusRound>,
         block_hash: Hash256,
+        signature: ProposalSignature,
     },
-    NonNilPreVoted(ConsensusRound, Hash256, Prevote),
-    NonNilPreCommitted(ConsensusRound, Hash256, Precommit),
-    NilPreVoted(ConsensusRound),
-    NilPreCommitted(ConsensusRound),
+    NonNilPreVoted(ConsensusRound, Hash256, Vote),
+    /// The precommit is for the finalization proof, and the vote is for the evidence.
+    NonNilPreCommitted(ConsensusRound, Hash256, Precommit, Vote),
+    NilPreVoted(ConsensusRound, Vote),
+    NilPreCommitted(ConsensusRound, Vote),
+}
+
+impl ConsensusMessage {
+    /// Returns the vote with what it is for, if the message is a prevote or a precommit.
+    fn vote(&self) -> Option<(ConsensusVoteKind, ConsensusRound, Option<Hash256>, &Vote)> {
+        match self {
+            ConsensusMessage::Proposal { .. } => None,
+            ConsensusMessage::NonNilPreVoted(round, block_hash, vote) => {
+                Some((ConsensusVoteKind::Prevote, *round, Some(*block_hash), vote))
+            }
+            ConsensusMessage::NonNilPreCommitted(round, block_hash, _, vote) => Some((
+                ConsensusVoteKind::Precommit,
+                *round,
+                Some(*block_hash),
+                vote,
+            )),
+            ConsensusMessage::NilPreVoted(round, vote) => {
+                Some((ConsensusVoteKind::Prevote, *round, None, vote))
+            }
+            ConsensusMessage::NilPreCommitted(round, vote) => {
+                Some((ConsensusVoteKind::Precommit, *round, None, vote))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -134,7 +174,9 @@ pub enum ProgressResult {
     NilPreVoted(ConsensusRound, Timestamp),
     NilPreCommitted(ConsensusRound, Timestamp),
     Finalized(Hash256, Timestamp, FinalizationProof),
-    ViolationReported(PublicKey, String, Timestamp),
+    /// A misbehavior reported by Vetomint, with the evidence signed by the violator,
+    /// which can be reported by a `TxReport` as is.
+    ViolationReported(PublicKey, simperby_common::Misbehavior, Timestamp),
 }
 
 pub struct ConsensusMessageFilter {
@@ -142,6 +184,10 @@ pub struct ConsensusMessageFilter {
     /// if it is guaranteed that the lock is not held for a long time.
     verified_block_hashes: Arc<parking_lot::RwLock<BTreeSet<Hash256>>>,
     validator_set: BTreeSet<PublicKey>,
+    /// The hash of the genesis block of the chain that the votes and the proposals are for.
+    genesis_hash: Hash256,
+    /// The height of the block that the votes are for.
+    height: BlockHeight,
 }
 
 impl MessageFilter for ConsensusMessageFilter {
@@ -153,16 +199,46 @@ impl MessageFilter for ConsensusMessageFilter {
         let consensus_message =
             serde_spb::from_str::<ConsensusMessage>(message.data()).map_err(|e| e.to_string())?;
         match consensus_message {
-            ConsensusMessage::Proposal { block_hash, .. } => self.verify_block_hash(block_hash),
-            ConsensusMessage::NonNilPreVoted(_, block_hash, prevote) => {
-                if signer != prevote.signer() {
-                    return Err("DMS message signer does not match with prevote signer".to_string());
+            ConsensusMessage::Proposal {
+                round,
+                valid_round,
+                block_hash,
+                signature,
+            } => {
+                if signer != signature.signer() {
+                    return Err(
+                        "DMS message signer does not match with proposal signer".to_string()
+                    );
                 }
-                let original_data = format!("{}-{}", block_hash, "prevote");
-                prevote.verify(&original_data).map_err(|e| e.to_string())?;
+                signature
+                    .verify(&ConsensusProposal {
+                        genesis_hash: self.genesis_hash,
+                        height: self.height,
+                        round,
+                        valid_round,
+                        block_hash,
+                    })
+                    .map_err(|e| e.to_string())?;
+                self.verify_block_hash(block_hash)
+            }
+            ConsensusMessage::NonNilPreVoted(round, block_hash, vote) => {
+                self.verify_vote(
+                    signer,
+                    ConsensusVoteKind::Prevote,
+                    round,
+                    Some(block_hash),
+                    &vote,
+                )?;
                 self.verify_block_hash(block_hash)
             }
-            ConsensusMessage::NonNilPreCommitted(_, block_hash, precommit) => {
+            ConsensusMessage::NonNilPreCommitted(round, block_hash, precommit, vote) => {
+                self.verify_vote(
+                    signer,
+                    ConsensusVoteKind::Precommit,
+                    round,
+                    Some(block_hash),
+                    &vote,
+                )?;
                 if signer != precommit.signer() {
                     return Err(
                         "DMS message signer does not match with precommit signer".to_string()
@@ -174,12 +250,38 @@ impl MessageFilter for ConsensusMessageFilter {
                     .map_err(|e| e.to_string())?;
                 self.verify_block_hash(block_hash)
             }
-            _ => Ok(()),
+            ConsensusMessage::NilPreVoted(round, vote) => {
+                self.verify_vote(signer, ConsensusVoteKind::Prevote, round, None, &vote)
+            }
+            ConsensusMessage::NilPreCommitted(round, vote) => {
+                self.verify_vote(signer, ConsensusVoteKind::Precommit, round, None, &vote)
+            }
         }
     }
 }
 
 impl ConsensusMessageFilter {
+    fn verify_vote(
+        &self,
+        signer: &PublicKey,
+        kind: ConsensusVoteKind,
+        round: ConsensusRound,
+        block_hash: Option<Hash256>,
+        vote: &Vote,
+    ) -> Result<(), String> {
+        if signer != vote.signer() {
+            return Err("DMS message signer does not match with vote signer".to_string());
+        }
+        vote.verify(&ConsensusVote {
+            genesis_hash: self.genesis_hash,
+            kind,
+            height: self.height,
+            round,
+            block_hash,
+        })
+        .map_err(|e| e.to_string())
+    }
+
     fn verify_block_hash(&self, block_hash: Hash256) -> Result<(), String> {
         if self.verified_block_hashes.read().contains(&block_hash) {
             Ok(())
@@ -205,6 +307,8 @@ pub struct Consensus<N: GossipNetwork, S: Storage> {
     verified_block_hashes: Arc<parking_lot::RwLock<BTreeSet<Hash256>>>,
     /// (If participated) the private key of this node
     this_node_key: Option<PrivateKey>,
+    /// The hash of the genesis block, to which the votes and the proposals are bound.
+    genesis_hash: Hash256,
 }
 
 impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
@@ -216,6 +320,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         mut dms: DMS<N, S>,
         mut state_storage: S,
         block_header: BlockHeader,
+        genesis_hash: Hash256,
         consensus_parameters: ConsensusParams,
         round_zero_timestamp: Timestamp,
         this_node_key: Option<PrivateKey>,
@@ -254,6 +359,8 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                 .iter()
                 .map(|(pk, _)| pk.clone())
                 .collect(),
+            genesis_hash,
+            height: state.block_header.height + 1,
         }));
         Ok(Self {
             dms,
@@ -261,6 +368,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
             state,
             verified_block_hashes,
             this_node_key,
+            genesis_hash,
         })
     }
 
@@ -416,6 +524,34 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
             .filter(|(cm, _)| matches!(cm, ConsensusMessage::NonNilPreCommitted(..)))
             .collect())
     }
+
+    /// Builds the aggregated finalization proof of the given block header
+    /// from the precommits for it received so far.
+    ///
+    /// The signer bitmap is over the `validator_set` of the header,
+    /// and every precommit must be a BLS12-381 signature.
+    pub async fn build_aggregated_finalization_proof(
+        &self,
+        header: &BlockHeader,
+    ) -> Result<FinalizationProof, Error> {
+        let block_hash = header.to_hash256();
+        let precommits = self
+            .read_precommits()
+            .await?
+            .into_iter()
+            .filter_map(|(cm, _)| match cm {
+                ConsensusMessage::NonNilPreCommitted(_, hash, precommit, _)
+                    if hash == block_hash =>
+                {
+                    Some(precommit)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let proof = AggregatedFinalizationProof::aggregate(&header.validator_set, &precommits)
+            .map_err(|e| eyre!("failed to aggregate the precommits: {}", e))?;
+        Ok(FinalizationProof::Aggregated(proof))
+    }
 }
 
 // Private methods
@@ -470,6 +606,134 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
             .map_err(|_| eyre!("failed to commit consensus state to the storage"))
     }
 
+    /// Signs the vote of this node for the block next to `block_header`.
+    fn sign_vote(
+        &self,
+        kind: ConsensusVoteKind,
+        round: ConsensusRound,
+        block_hash: Option<Hash256>,
+    ) -> Result<Vote, Error> {
+        let private_key = self
+            .this_node_key
+            .as_ref()
+            .ok_or_else(|| eyre!("this node is not a validator"))?;
+        let vote = ConsensusVote {
+            genesis_hash: self.genesis_hash,
+            kind,
+            height: self.state.block_header.height + 1,
+            round,
+            block_hash,
+        };
+        Ok(TypedSignature::sign(&vote, private_key)?)
+    }
+
+    /// Signs the proposal of this node for the block next to `block_header`.
+    fn sign_proposal(
+        &self,
+        round: ConsensusRound,
+        valid_round: Option<ConsensusRound>,
+        block_hash: Hash256,
+    ) -> Result<ProposalSignature, Error> {
+        let private_key = self
+            .this_node_key
+            .as_ref()
+            .ok_or_else(|| eyre!("this node is not a validator"))?;
+        let proposal = ConsensusProposal {
+            genesis_hash: self.genesis_hash,
+            height: self.state.block_header.height + 1,
+            round,
+            valid_round,
+            block_hash,
+        };
+        Ok(TypedSignature::sign(&proposal, private_key)?)
+    }
+
+    /// Finds the message of the given signer in the DMS that satisfies the predicate.
+    async fn find_message(
+        &self,
+        signer: &PublicKey,
+        predicate: impl Fn(&ConsensusMessage) -> bool,
+    ) -> Result<(Message, ConsensusMessage), Error> {
+        self.dms
+            .read_messages()
+            .await?
+            .into_iter()
+            .filter(|message| message.signature().signer() == signer)
+            .find_map(|message| {
+                let consensus_message =
+                    serde_spb::from_str::<ConsensusMessage>(message.data()).ok()?;
+                if predicate(&consensus_message) {
+                    Some((message, consensus_message))
+                } else {
+                    None
+                }
+            })
+            .ok_or_else(|| eyre!("the message of {} is not found in the DMS", signer))
+    }
+
+    /// Finds the signed vote of the given signer for the block next to `block_header` in the DMS.
+    async fn find_vote(
+        &self,
+        signer: &PublicKey,
+        kind: ConsensusVoteKind,
+        round: ConsensusRound,
+        block_hash: Option<Hash256>,
+    ) -> Result<SignedConsensusVote, Error> {
+        let (_, message) = self
+            .find_message(signer, |message| {
+                message.vote().map(|(k, r, h, _)| (k, r, h)) == Some((kind, round, block_hash))
+            })
+            .await?;
+        let (_, _, _, signature) = message.vote().expect("it is found as a vote");
+        Ok(SignedConsensusVote {
+            vote: ConsensusVote {
+                genesis_hash: self.genesis_hash,
+                kind,
+                height: self.state.block_header.height + 1,
+                round,
+                block_hash,
+            },
+            signature: signature.clone(),
+        })
+    }
+
+    /// Finds the signed proposal of the given signer for the block next to `block_header` in the DMS.
+    async fn find_proposal(
+        &self,
+        signer: &PublicKey,
+        round: ConsensusRound,
+        block_hash: Hash256,
+    ) -> Result<SignedConsensusProposal, Error> {
+        let (_, message) = self
+            .find_message(signer, |message| {
+                matches!(
+                    message,
+                    ConsensusMessage::Proposal { round: r, block_hash: h, .. }
+                        if *r == round && *h == block_hash
+                )
+            })
+            .await?;
+        if let ConsensusMessage::Proposal {
+            valid_round,
+            signature,
+            ..
+        } = message
+        {
+            Ok(SignedConsensusProposal {
+                proposal: ConsensusProposal {
+                    genesis_hash: self.genesis_hash,
+                    height: self.state.block_header.height + 1,
+                    round,
+                    valid_round,
+                    block_hash,
+                },
+                signature,
+            })
+        } else {
+            unreachable!("it is found as a proposal")
+        }
+    }
+
     async fn broadcast_consensus_message(
         &mut self,
         consensus_message: &ConsensusMessage,
@@ -510,6 +774,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                 round,
                 valid_round,
                 block_hash,
+                ..
             } => {
                 let valid_round = valid_round.map(|r| r as usize);
                 let index = self
@@ -536,7 +801,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     round: *round as usize,
                 }
             }
-            ConsensusMessage::NonNilPreCommitted(round, block_hash, _) => {
+            ConsensusMessage::NonNilPreCommitted(round, block_hash, _, _) => {
                 let index = self
                     .get_block_index(block_hash)
                     .expect("this must be already verified by the message filter");
@@ -546,12 +811,12 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     round: *round as usize,
                 }
             }
-            ConsensusMessage::NilPreVoted(round) => ConsensusEvent::Prevote {
+            ConsensusMessage::NilPreVoted(round, _) => ConsensusEvent::Prevote {
                 proposal: None,
                 signer,
                 round: *round as usize,
             },
-            ConsensusMessage::NilPreCommitted(round) => ConsensusEvent::Precommit {
+            ConsensusMessage::NilPreCommitted(round, _) => ConsensusEvent::Precommit {
                 proposal: None,
                 signer,
                 round: *round as usize,
@@ -587,6 +852,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     round: round as u64,
                     valid_round,
                     block_hash,
+                    signature: self.sign_proposal(round as u64, valid_round, block_hash)?,
                 };
                 self.broadcast_consensus_message(&consensus_message).await?;
                 Ok(ProgressResult::Proposed(
@@ -596,10 +862,6 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                 ))
             }
             ConsensusResponse::BroadcastPrevote { proposal, round } => {
-                let private_key = self
-                    .this_node_key
-                    .as_ref()
-                    .ok_or_else(|| eyre!("this node is not a validator"))?;
                 let (consensus_message, progress_result) = if let Some(block_index) = proposal {
                     let block_hash = *self
                         .state
@@ -609,16 +871,16 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     let message = ConsensusMessage::NonNilPreVoted(
                         round as u64,
                         block_hash,
-                        TypedSignature::sign(
-                            &format!("{}-{}", block_hash, "prevote"),
-                            private_key,
-                        )?,
+                        self.sign_vote(ConsensusVoteKind::Prevote, round as u64, Some(block_hash))?,
                     );
                     let result =
                         ProgressResult::NonNilPreVoted(round as u64, block_hash, timestamp);
                     (message, result)
                 } else {
-                    let message = ConsensusMessage::NilPreVoted(round as u64);
+                    let message = ConsensusMessage::NilPreVoted(
+                        round as u64,
+                        self.sign_vote(ConsensusVoteKind::Prevote, round as u64, None)?,
+                    );
                     let result = ProgressResult::NilPreVoted(round as u64, timestamp);
                     (message, result)
                 };
@@ -643,12 +905,20 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                             Signature::sign(block_hash, private_key)?,
                             private_key.public_key(),
                         ),
+                        self.sign_vote(
+                            ConsensusVoteKind::Precommit,
+                            round as u64,
+                            Some(block_hash),
+                        )?,
                     );
                     let result =
                         ProgressResult::NonNilPreCommitted(round as u64, block_hash, timestamp);
                     (message, result)
                 } else {
-                    let message = ConsensusMessage::NilPreCommitted(round as u64);
+                    let message = ConsensusMessage::NilPreCommitted(
+                        round as u64,
+                        self.sign_vote(ConsensusVoteKind::Precommit, round as u64, None)?,
+                    );
                     let result = ProgressResult::NilPreCommitted(round as u64, timestamp);
                     (message, result)
                 };
@@ -677,7 +947,9 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     .map(|(cm, _)| cm);
                 let proof = precommits_for_proof
                     .map(|cm| match cm {
-                        ConsensusMessage::NonNilPreCommitted(_, _, precommit) => precommit.clone(),
+                        ConsensusMessage::NonNilPreCommitted(_, _, precommit, _) => {
+                            precommit.clone()
+                        }
                         _ => panic!(
                             "consensus::read_precommits should return only `NonNilPreCommitted`"
                         ),
@@ -688,7 +960,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
             }
             ConsensusResponse::ViolationReport {
                 violator,
-                description,
+                misbehavior,
             } => {
                 let pubkey = self
                     .state
@@ -698,10 +970,53 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     .expect("oob access to validators")
                     .0
                     .clone();
+                let block_hash = |index: BlockIdentifier| {
+                    *self
+                        .state
+                        .verified_block_hashes
+                        .get(index)
+                        .expect("oob access to verified_block_hashes")
+                };
+                let evidence = match misbehavior {
+                    Misbehavior::DoubleProposal {
+                        round,
+                        first,
+                        second,
+                    } => simperby_common::Misbehavior::DoubleProposal(
+                        self.find_proposal(&pubkey, round as u64, block_hash(first))
+                            .await?,
+                        self.find_proposal(&pubkey, round as u64, block_hash(second))
+                            .await?,
+                    ),
+                    Misbehavior::DoublePrevote {
+                        round,
+                        first,
+                        second,
+                    } => {
+                        let kind = ConsensusVoteKind::Prevote;
+                        simperby_common::Misbehavior::DoubleVote(
+                            self.find_vote(&pubkey, kind, round as u64, first.map(block_hash))
+                                .await?,
+                            self.find_vote(&pubkey, kind, round as u64, second.map(block_hash))
+                                .await?,
+                        )
+                    }
+                    Misbehavior::DoublePrecommit {
+                        round,
+                        first,
+                        second,
+                    } => {
+                        let kind = ConsensusVoteKind::Precommit;
+                        simperby_common::Misbehavior::DoubleVote(
+                            self.find_vote(&pubkey, kind, round as u64, first.map(block_hash))
+                                .await?,
+                            self.find_vote(&pubkey, kind, round as u64, second.map(block_hash))
+                                .await?,
+                        )
+                    }
+                };
                 Ok(ProgressResult::ViolationReported(
-                    pubkey,
-                    description,
-                    timestamp,
+                    pubkey, evidence, timestamp,
                 ))
             }
         }
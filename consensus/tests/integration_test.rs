@@ -1,6 +1,7 @@
 use common::{
     crypto::{Signature, TypedSignature},
-    BlockHeight, FinalizationProof, PrivateKey, Timestamp,
+    BlockHeight, ConsensusProposal, ConsensusVote, ConsensusVoteKind, FinalizationProof,
+    PrivateKey, Timestamp,
 };
 #[allow(unused_imports)]
 use log::debug;
@@ -9,7 +10,9 @@ use simperby_common::{
     crypto::{Hash256, PublicKey},
     BlockHeader, VotingPower,
 };
-use simperby_consensus::{Consensus, ConsensusMessage, Precommit, Prevote, ProgressResult};
+use simperby_consensus::{
+    Consensus, ConsensusMessage, Precommit, ProgressResult, ProposalSignature, Vote,
+};
 use simperby_network::{
     primitives::Storage, storage::StorageImpl, NetworkConfig, SharedKnownPeers,
 };
@@ -17,12 +20,12 @@ use simperby_test_suite as test_suite;
 use std::fmt::Debug;
 use std::iter::once;
 use test_suite::*;
-use vetomint::ConsensusParams;
+use vetomint::{ConsensusParams, TimeoutParams};
 
 fn get_initial_block_header(validator_set: Vec<(PublicKey, VotingPower)>) -> BlockHeader {
     BlockHeader {
         author: PublicKey::zero(),
-        prev_block_finalization_proof: Vec::new(),
+        prev_block_finalization_proof: FinalizationProof::default(),
         previous_hash: Hash256::zero(),
         height: 0 as BlockHeight,
         timestamp: 0 as Timestamp,
@@ -66,8 +69,34 @@ fn assert_eq_unordered<T: Eq + PartialEq + Debug>(expected: &Vec<T>, actual: &Ve
     }
 }
 
-fn prevote(block_hash: Hash256, privkey: &PrivateKey) -> Prevote {
-    TypedSignature::sign(&format!("{}-{}", block_hash, "prevote"), privkey).unwrap()
+fn genesis_hash() -> Hash256 {
+    Hash256::hash("genesis")
+}
+
+fn vote(kind: ConsensusVoteKind, block_hash: Hash256, privkey: &PrivateKey) -> Vote {
+    let vote = ConsensusVote {
+        genesis_hash: genesis_hash(),
+        kind,
+        height: 1,
+        round: 0,
+        block_hash: Some(block_hash),
+    };
+    TypedSignature::sign(&vote, privkey).unwrap()
+}
+
+fn proposal(block_hash: Hash256, privkey: &PrivateKey) -> ProposalSignature {
+    let proposal = ConsensusProposal {
+        genesis_hash: genesis_hash(),
+        height: 1,
+        round: 0,
+        valid_round: None,
+        block_hash,
+    };
+    TypedSignature::sign(&proposal, privkey).unwrap()
+}
+
+fn prevote(block_hash: Hash256, privkey: &PrivateKey) -> Vote {
+    vote(ConsensusVoteKind::Prevote, block_hash, privkey)
 }
 
 fn precommit(block_hash: Hash256, privkey: &PrivateKey) -> Precommit {
@@ -90,7 +119,9 @@ async fn single_server_propose_1() {
     let voting_powers = vec![1, 1, 1, 1, 1];
     let num_nodes = voting_powers.len();
     let params = ConsensusParams {
-        timeout_ms: 60 * 1_000,
+        propose_timeout: TimeoutParams::constant(60 * 1_000),
+        prevote_timeout: TimeoutParams::constant(60 * 1_000),
+        precommit_timeout: TimeoutParams::constant(60 * 1_000),
         repeat_round_for_first_leader: 100,
     };
     let round_zero_timestamp = get_timestamp();
@@ -112,6 +143,7 @@ async fn single_server_propose_1() {
         .await,
         create_storage(create_temp_dir()).await,
         block_header.clone(),
+        genesis_hash(),
         params.clone(),
         round_zero_timestamp,
         Some(server_config.private_key.clone()),
@@ -124,6 +156,7 @@ async fn single_server_propose_1() {
             create_test_dms(config.clone(), dms_key.clone(), peers.clone()).await,
             create_storage(create_temp_dir()).await,
             block_header.clone(),
+            genesis_hash(),
             params.clone(),
             round_zero_timestamp,
             Some(config.private_key.clone()),
@@ -199,6 +232,7 @@ async fn single_server_propose_1() {
                 round: 0,
                 valid_round: None,
                 block_hash: dummy_block_hash,
+                signature: proposal(dummy_block_hash, &server_config.private_key),
             },
             server_config.public_key.clone(),
         ),
@@ -226,6 +260,11 @@ async fn single_server_propose_1() {
                     0,
                     dummy_block_hash,
                     precommit(dummy_block_hash, &config.private_key),
+                    vote(
+                        ConsensusVoteKind::Precommit,
+                        dummy_block_hash,
+                        &config.private_key,
+                    ),
                 ),
                 config.public_key.clone(),
             ));
@@ -291,6 +330,11 @@ async fn single_server_propose_1() {
             0,
             dummy_block_hash,
             precommit(dummy_block_hash, &server_config.private_key),
+            vote(
+                ConsensusVoteKind::Precommit,
+                dummy_block_hash,
+                &server_config.private_key,
+            ),
         ),
         server_config.public_key.clone(),
     ));
@@ -300,6 +344,11 @@ async fn single_server_propose_1() {
                 0,
                 dummy_block_hash,
                 precommit(dummy_block_hash, &config.private_key),
+                vote(
+                    ConsensusVoteKind::Precommit,
+                    dummy_block_hash,
+                    &config.private_key,
+                ),
             ),
             config.public_key.clone(),
         ));
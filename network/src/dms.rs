@@ -37,7 +37,7 @@ impl Message {
 
 impl ToHash256 for Message {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
+        Hash256::hash(serde_spb::to_hashable_vec(self).unwrap())
     }
 }
 
@@ -47,7 +47,7 @@ pub trait MessageFilter: Send + Sync + 'static {
 }
 
 /// A message before verification.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RawMessage {
     pub data: String,
     pub signature: TypedSignature<String>,
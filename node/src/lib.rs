@@ -5,9 +5,10 @@ pub use simperby_network;
 pub use simperby_repository;
 
 use async_trait::async_trait;
-use eyre::Result;
+use eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
 use simperby_common::crypto::*;
+use simperby_common::keystore::Keystore;
 use simperby_common::*;
 use simperby_governance::Governance;
 use simperby_repository::raw::SemanticCommit;
@@ -18,7 +19,13 @@ pub struct Config {
     pub chain_name: String,
 
     pub public_key: PublicKey,
-    pub private_key: PrivateKey,
+    /// The private key in plaintext, used if `keystore` is not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<PrivateKey>,
+    /// The path of the encrypted keystore (relative to the node directory),
+    /// which is unlocked with a password on startup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keystore: Option<String>,
 
     pub broadcast_interval_ms: Option<u64>,
     pub fetch_interval_ms: Option<u64>,
@@ -33,6 +40,36 @@ pub struct Config {
     pub repository_port: u16,
 }
 
+impl Config {
+    /// Returns the private key of the node, decrypting the keystore with the password if configured.
+    pub async fn unlock_private_key(
+        &self,
+        path: &str,
+        password: Option<&str>,
+    ) -> Result<PrivateKey> {
+        let private_key = if let Some(keystore) = &self.keystore {
+            let keystore: Keystore = serde_spb::from_str(
+                &tokio::fs::read_to_string(&format!("{}/{}", path, keystore)).await?,
+            )?;
+            let password =
+                password.ok_or_else(|| eyre!("a password is required to unlock the keystore"))?;
+            keystore.decrypt(password)?
+        } else if let Some(private_key) = &self.private_key {
+            private_key.clone()
+        } else {
+            return Err(eyre!(
+                "neither the keystore nor the private key is configured"
+            ));
+        };
+        if private_key.public_key() != self.public_key {
+            return Err(eyre!(
+                "the private key doesn't match the configured public key"
+            ));
+        }
+        Ok(private_key)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConsensusStatus {
     // TODO
@@ -62,6 +99,8 @@ pub enum CommitInfo {
     Transaction {
         semantic_commit: SemanticCommit,
         transaction: Transaction,
+        /// The human-readable change of the reserved state, if it is a reserved-diff transaction.
+        reserved_state_diff: Option<String>,
     },
     PreGenesisCommit {
         title: String,
@@ -143,3 +182,12 @@ pub type SimperbyNode = node::Node<
 pub async fn initialize(config: Config, path: &str) -> Result<SimperbyNode> {
     SimperbyNode::initialize(config, path).await
 }
+
+/// Initializes the node, unlocking the keystore with the given password.
+pub async fn initialize_with_password(
+    config: Config,
+    path: &str,
+    password: &str,
+) -> Result<SimperbyNode> {
+    SimperbyNode::initialize_with_password(config, path, password).await
+}
@@ -1,6 +1,6 @@
 use super::*;
 use eyre::eyre;
-use simperby_consensus::{Consensus, ConsensusParameters, ProgressResult};
+use simperby_consensus::{Consensus, ConsensusParameters, ProgressResult, TimeoutParams};
 use simperby_network::primitives::{GossipNetwork, Storage};
 use simperby_network::NetworkConfig;
 use simperby_network::{dms, storage::StorageImpl, Dms, Peer, SharedKnownPeers};
@@ -34,7 +34,6 @@ pub struct Node<N: GossipNetwork, S: Storage, R: RawRepository> {
     consensus: Consensus<N, S>,
 
     last_reserved_state: ReservedState,
-    #[allow(dead_code)]
     last_finalized_header: BlockHeader,
 
     path: String,
@@ -42,7 +41,27 @@ pub struct Node<N: GossipNetwork, S: Storage, R: RawRepository> {
 }
 
 impl SimperbyNode {
+    /// Initializes the node with the plaintext private key in the config.
     pub async fn initialize(config: Config, path: &str) -> Result<Self> {
+        let private_key = config.unlock_private_key(path, None).await?;
+        Self::initialize_with_private_key(config, path, private_key).await
+    }
+
+    /// Initializes the node, unlocking the keystore in the config with the password.
+    pub async fn initialize_with_password(
+        config: Config,
+        path: &str,
+        password: &str,
+    ) -> Result<Self> {
+        let private_key = config.unlock_private_key(path, Some(password)).await?;
+        Self::initialize_with_private_key(config, path, private_key).await
+    }
+
+    async fn initialize_with_private_key(
+        config: Config,
+        path: &str,
+        private_key: PrivateKey,
+    ) -> Result<Self> {
         // Step 0: initialize the repository module
         let peers: Vec<Peer> = serde_spb::from_str(
             &tokio::fs::read_to_string(&format!("{}/peers.json", path)).await?,
@@ -85,7 +104,7 @@ impl SimperbyNode {
                 .map(|m| m.public_key.clone())
                 .collect(),
             public_key: config.public_key.clone(),
-            private_key: config.private_key.clone(),
+            private_key: private_key.clone(),
         };
         let dms_config = dms::Config {
             fetch_interval: Some(std::time::Duration::from_millis(500)),
@@ -104,7 +123,7 @@ impl SimperbyNode {
             peers.clone(),
         )
         .await?;
-        let governance = Governance::new(dms, Some(config.private_key.clone())).await?;
+        let governance = Governance::new(dms, Some(private_key.clone())).await?;
 
         // Step 3: initialize the consensus module
         let dms_path = format!("{}/consensus/dms", path);
@@ -124,13 +143,16 @@ impl SimperbyNode {
             dms,
             consensus_state_storage,
             last_finalized_header.clone(),
+            reserved_state.genesis_info.header.to_hash256(),
             // TODO: replace params and timestamp with proper values
             ConsensusParameters {
-                timeout_ms: 10000000,
+                propose_timeout: TimeoutParams::constant(10000000),
+                prevote_timeout: TimeoutParams::constant(10000000),
+                precommit_timeout: TimeoutParams::constant(10000000),
                 repeat_round_for_first_leader: 100,
             },
             0,
-            Some(config.private_key.clone()),
+            Some(private_key),
         )
         .await?;
         Ok(Self {
@@ -203,8 +225,9 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
         Ok(commit_hash)
     }
 
-    async fn create_extra_agenda_transaction(&mut self, _tx: ExtraAgendaTransaction) -> Result<()> {
-        unimplemented!()
+    async fn create_extra_agenda_transaction(&mut self, tx: ExtraAgendaTransaction) -> Result<()> {
+        self.repository.create_extra_agenda_transaction(&tx).await?;
+        Ok(())
     }
 
     async fn vote(&mut self, agenda_commit: CommitHash) -> Result<()> {
@@ -237,6 +260,10 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
             .read_semantic_commit(commit_hash)
             .await?;
         let commit = simperby_repository::format::from_semantic_commit(semantic_commit.clone())?;
+        let encoding = self
+            .last_finalized_header
+            .hash_encoding()
+            .map_err(|e| eyre!(e))?;
         let result = match commit {
             Commit::Block(block_header) => CommitInfo::Block {
                 semantic_commit,
@@ -250,7 +277,7 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
                     .read()
                     .await?
                     .votes
-                    .get(&agenda.to_hash256())
+                    .get(&agenda.to_hash256_with(encoding))
                     .unwrap_or(&Default::default())
                     .iter()
                     .filter_map(|(public_key, _)| {
@@ -264,6 +291,14 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
                 semantic_commit,
                 agenda_proof,
             },
+            Commit::Transaction(transaction) => CommitInfo::Transaction {
+                semantic_commit,
+                transaction,
+                reserved_state_diff: self
+                    .repository
+                    .render_reserved_state_diff(commit_hash)
+                    .await?,
+            },
             x => CommitInfo::Unknown {
                 semantic_commit,
                 msg: format!("{:?}", x),
@@ -333,35 +368,39 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
         futures::try_join!(t1, t2, t3)?;
 
         // Update governance
-        let governance_set = self
-            .last_reserved_state
-            .get_governance_set()
-            .unwrap()
-            .into_iter()
-            .collect::<HashMap<_, _>>();
+        // The governance set is evaluated at the agenda timestamp,
+        // as `CommitSequenceVerifier` does for the agenda proof.
+        let mut agenda_timestamps = HashMap::new();
+        for (commit_hash, agenda_hash) in self.repository.get_agendas().await? {
+            if let Commit::Agenda(agenda) = self.repository.read_commit(commit_hash).await? {
+                agenda_timestamps.insert(agenda_hash, agenda.timestamp);
+            }
+        }
         let governance_state = self.governance.read().await?;
-        let votes: Vec<(Hash256, VotingPower)> = governance_state
-            .votes
-            .iter()
-            .map(|(agenda, votes)| {
-                (
-                    *agenda,
-                    votes
-                        .keys()
-                        .map(|voter| governance_set.get(voter).unwrap())
-                        .sum(),
-                )
-            })
-            .collect();
-        let total_voting_power = governance_set.values().sum::<VotingPower>();
-        for (agenda, voted_power) in votes {
+        for (agenda, votes) in &governance_state.votes {
+            let timestamp = if let Some(timestamp) = agenda_timestamps.get(agenda) {
+                *timestamp
+            } else {
+                continue;
+            };
+            let governance_set = self
+                .last_reserved_state
+                .get_governance_set(self.last_finalized_header.height + 1, timestamp)
+                .map_err(|e| eyre!("failed to get the governance set: {}", e))?
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            let voted_power = votes
+                .keys()
+                .filter_map(|voter| governance_set.get(voter))
+                .sum::<VotingPower>();
+            let total_voting_power = governance_set.values().sum::<VotingPower>();
             if voted_power * 2 > total_voting_power {
                 // TODO: handle this error
                 let _ = self
                     .repository
                     .approve(
-                        &agenda,
-                        governance_state.votes[&agenda]
+                        agenda,
+                        votes
                             .iter()
                             .map(|(k, s)| TypedSignature::new(s.clone(), k.clone()))
                             .collect(),
@@ -9,7 +9,8 @@ fn generate_config(key: PrivateKey, chain_name: String) -> Config {
     Config {
         chain_name,
         public_key: key.public_key(),
-        private_key: key,
+        private_key: Some(key),
+        keystore: None,
         broadcast_interval_ms: None,
         fetch_interval_ms: None,
         public_repo_url: vec![],
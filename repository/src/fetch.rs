@@ -97,9 +97,25 @@ pub async fn fetch<T: RawRepository>(this: &mut DistributedRepository<T>) -> Res
         };
 
         // Verify all the incoming commits
-        let mut csv = CommitSequenceVerifier::new(last_header.clone(), reserved_state.clone())
-            .expect("finalized branch is not accepted by CSV");
+        let mut csv = CommitSequenceVerifier::new(
+            last_header.clone(),
+            reserved_state.clone(),
+            VerifierOptions::default(),
+        )
+        .expect("finalized branch is not accepted by CSV");
+        if let Err(e) = csv.preverify_signatures(commits.iter().map(|(commit, _)| commit)) {
+            warn!(
+                "signature verification failed for branch {}: {}",
+                branch_displayed, e
+            );
+            continue 'branch_loop;
+        }
         for (new_commit, new_commit_hash) in &commits {
+            if let Commit::Block(_) = new_commit {
+                let tree = this.get_repository_merkle_tree(*new_commit_hash).await?;
+                csv.set_reserved_state_root(tree.reserved_root());
+                csv.set_non_reserved_state_root(tree.non_reserved_root());
+            }
             if let Err(e) = csv.apply_commit(new_commit) {
                 warn!(
                     "commit sequence verification failed for branch {}: {} at {}",
@@ -37,8 +37,37 @@ pub fn to_semantic_commit(commit: &Commit) -> SemanticCommit {
                 diff: Diff::None,
             }
         }
-        Commit::ExtraAgendaTransaction(_) => unimplemented!(),
-        Commit::ChatLog(_) => unimplemented!(),
+        Commit::ExtraAgendaTransaction(tx) => {
+            let (title, body) = match tx {
+                ExtraAgendaTransaction::Delegate(tx) => (
+                    format!(">tx-delegate: {}", tx.block_height),
+                    serde_spb::to_string(tx).unwrap(),
+                ),
+                ExtraAgendaTransaction::Undelegate(tx) => (
+                    format!(">tx-undelegate: {}", tx.block_height),
+                    serde_spb::to_string(tx).unwrap(),
+                ),
+                // A report is titled with the height of the misbehavior.
+                ExtraAgendaTransaction::Report(tx) => (
+                    format!(">tx-report: {}", tx.misbehavior.height()),
+                    serde_spb::to_string(tx).unwrap(),
+                ),
+            };
+            SemanticCommit {
+                title,
+                body,
+                diff: Diff::None,
+            }
+        }
+        Commit::ChatLog(chat_log) => {
+            let title = format!(">chat-log: {}", chat_log.height);
+            let body = serde_spb::to_string(chat_log).unwrap();
+            SemanticCommit {
+                title,
+                body,
+                diff: Diff::None,
+            }
+        }
     }
 }
 
@@ -46,7 +75,10 @@ pub fn to_semantic_commit(commit: &Commit) -> SemanticCommit {
 ///
 /// TODO: retrieve author and timestamp from the commit metadata.
 pub fn from_semantic_commit(semantic_commit: SemanticCommit) -> Result<Commit, Error> {
-    let pattern = Regex::new(r"^>((agenda)|(block)|(agenda-proof)): (\d+)$").unwrap();
+    let pattern = Regex::new(
+        r"^>((agenda)|(block)|(agenda-proof)|(chat-log)|(tx-delegate)|(tx-undelegate)|(tx-report)): (\d+)$",
+    )
+    .unwrap();
     let captures = pattern.captures(&semantic_commit.title);
     if let Some(captures) = captures {
         let commit_type = captures.get(1).map(|m| m.as_str()).ok_or_else(|| {
@@ -55,7 +87,7 @@ pub fn from_semantic_commit(semantic_commit: SemanticCommit) -> Result<Commit, E
                 semantic_commit.title
             )
         })?;
-        let height = captures.get(5).map(|m| m.as_str()).ok_or_else(|| {
+        let height = captures.get(9).map(|m| m.as_str()).ok_or_else(|| {
             eyre!(
                 "Failed to parse commit height from commit title: {}",
                 semantic_commit.title
@@ -96,6 +128,56 @@ pub fn from_semantic_commit(semantic_commit: SemanticCommit) -> Result<Commit, E
                 }
                 Ok(Commit::AgendaProof(agenda_proof))
             }
+            "chat-log" => {
+                let chat_log: ChatLog = serde_spb::from_str(&semantic_commit.body)?;
+                if height != chat_log.height {
+                    return Err(eyre!(
+                        "chat-log height mismatch: expected {}, got {}",
+                        chat_log.height,
+                        height
+                    ));
+                }
+                Ok(Commit::ChatLog(chat_log))
+            }
+            "tx-delegate" => {
+                let tx: TxDelegate = serde_spb::from_str(&semantic_commit.body)?;
+                if height != tx.block_height {
+                    return Err(eyre!(
+                        "tx-delegate height mismatch: expected {}, got {}",
+                        tx.block_height,
+                        height
+                    ));
+                }
+                Ok(Commit::ExtraAgendaTransaction(
+                    ExtraAgendaTransaction::Delegate(tx),
+                ))
+            }
+            "tx-undelegate" => {
+                let tx: TxUndelegate = serde_spb::from_str(&semantic_commit.body)?;
+                if height != tx.block_height {
+                    return Err(eyre!(
+                        "tx-undelegate height mismatch: expected {}, got {}",
+                        tx.block_height,
+                        height
+                    ));
+                }
+                Ok(Commit::ExtraAgendaTransaction(
+                    ExtraAgendaTransaction::Undelegate(tx),
+                ))
+            }
+            "tx-report" => {
+                let tx: TxReport = serde_spb::from_str(&semantic_commit.body)?;
+                if height != tx.misbehavior.height() {
+                    return Err(eyre!(
+                        "tx-report height mismatch: expected {}, got {}",
+                        tx.misbehavior.height(),
+                        height
+                    ));
+                }
+                Ok(Commit::ExtraAgendaTransaction(
+                    ExtraAgendaTransaction::Report(tx),
+                ))
+            }
             _ => Err(eyre!("unknown commit type: {}", commit_type)),
         }
     } else {
@@ -187,7 +269,8 @@ mod tests {
             prev_block_finalization_proof: vec![TypedSignature::new(
                 Signature::zero(),
                 PublicKey::zero(),
-            )],
+            )]
+            .into(),
             previous_hash: Hash256::hash("hello1"),
             timestamp: 0,
             commit_merkle_root: Hash256::hash("hello2"),
@@ -214,6 +297,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn format_chat_log_commit() {
+        let chat_log = Commit::ChatLog(ChatLog {
+            height: 3,
+            messages: vec![ChatMessage {
+                author: PublicKey::zero(),
+                timestamp: 123,
+                content: "hello".to_string(),
+                signature: TypedSignature::new(Signature::zero(), PublicKey::zero()),
+            }],
+        });
+        assert_eq!(
+            chat_log,
+            from_semantic_commit(to_semantic_commit(&chat_log)).unwrap()
+        );
+    }
+
+    #[test]
+    fn format_tx_report_commit() {
+        let vote = ConsensusVote {
+            genesis_hash: Hash256::hash("genesis"),
+            kind: ConsensusVoteKind::Precommit,
+            height: 3,
+            round: 0,
+            block_hash: None,
+        };
+        let signed_vote = SignedConsensusVote {
+            vote: vote.clone(),
+            signature: TypedSignature::new(Signature::zero(), PublicKey::zero()),
+        };
+        let tx_report = Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Report(TxReport {
+            misbehavior: Misbehavior::DoubleVote(
+                signed_vote.clone(),
+                SignedConsensusVote {
+                    vote: ConsensusVote {
+                        block_hash: Some(Hash256::hash("hello")),
+                        ..vote
+                    },
+                    ..signed_vote
+                },
+            ),
+            headers: vec![],
+            timestamp: 123,
+        }));
+        assert_eq!(
+            tx_report,
+            from_semantic_commit(to_semantic_commit(&tx_report)).unwrap()
+        );
+    }
+
     #[test]
     fn format_fp() {
         let fp = LastFinalizationProof {
@@ -221,7 +354,8 @@ mod tests {
             proof: vec![
                 TypedSignature::new(Signature::zero(), PublicKey::zero()),
                 TypedSignature::new(Signature::zero(), PublicKey::zero()),
-            ],
+            ]
+            .into(),
         };
         assert_eq!(
             fp,
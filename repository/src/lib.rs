@@ -12,7 +12,7 @@ use log::{info, warn};
 use raw::RawRepository;
 use serde::{Deserialize, Serialize};
 use simperby_common::reserved::ReservedState;
-use simperby_common::verify::CommitSequenceVerifier;
+use simperby_common::verify::{CommitSequenceVerifier, VerifierOptions};
 use simperby_common::*;
 use simperby_network::{NetworkConfig, Peer, SharedKnownPeers};
 use std::{collections::HashSet, fmt};
@@ -173,6 +173,43 @@ impl<T: RawRepository> DistributedRepository<T> {
         self.raw.read_reserved_state().await.map_err(|e| eyre!(e))
     }
 
+    /// Renders the change of the reserved state made by the given transaction commit
+    /// (see `ReservedState::render_diff()`),
+    /// or returns `None` if the commit doesn't have a reserved diff.
+    ///
+    /// The commit must be a descendant of the `finalized` branch.
+    pub async fn render_reserved_state_diff(
+        &self,
+        commit_hash: CommitHash,
+    ) -> Result<Option<String>, Error> {
+        let finalized_commit_hash = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+        let mut reserved_state = self.get_reserved_state().await?;
+        for (commit, hash) in read_commits(self, finalized_commit_hash, commit_hash).await? {
+            if let Commit::Transaction(Transaction {
+                diff: Diff::Reserved(next),
+                ..
+            }) = commit
+            {
+                if hash == commit_hash {
+                    return Ok(Some(
+                        reserved_state.render_diff(&next).map_err(|e| eyre!(e))?,
+                    ));
+                }
+                reserved_state = *next;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the Merkle tree of the actual files at the given commit.
+    async fn get_repository_merkle_tree(
+        &self,
+        commit_hash: CommitHash,
+    ) -> Result<merkle_tree::RepositoryMerkleTree, Error> {
+        let files = self.raw.read_files(commit_hash).await?;
+        Ok(merkle_tree::RepositoryMerkleTree::create(files))
+    }
+
     /// Cleans all the outdated commits, remote repositories and branches.
     ///
     /// It will leave only
@@ -290,10 +327,8 @@ impl<T: RawRepository> DistributedRepository<T> {
 
         // Check if the last commit is a block commit.
         let current_finalized_commit = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
-        let new_commits =
-            utils::read_commits(self, current_finalized_commit, block_commit_hash).await?;
         let last_block_header =
-            if let Commit::Block(last_block_header) = &new_commits.last().unwrap().0 {
+            if let Commit::Block(last_block_header) = self.read_commit(block_commit_hash).await? {
                 last_block_header
             } else {
                 return Err(eyre!("the last commit is not a block commit"));
@@ -311,18 +346,41 @@ impl<T: RawRepository> DistributedRepository<T> {
             ));
         }
 
-        // Verify every commit along the way.
+        // Verify every commit along the way, reading and verifying one block at a time.
+        // The verified commits are never looked up again, so it doesn't need to keep them.
         let last_finalized_block_header = self.get_last_finalized_block_header().await?;
         let reserved_state = self.get_reserved_state().await?;
-        let mut verifier = CommitSequenceVerifier::new(
+        let mut verifier = CommitSequenceVerifier::new_streaming(
             last_finalized_block_header.clone(),
             reserved_state.clone(),
+            VerifierOptions::default(),
         )
         .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
-        for (new_commit, new_commit_hash) in &new_commits {
+        let new_commit_hashes = self
+            .raw
+            .query_commit_path(current_finalized_commit, block_commit_hash)
+            .await?;
+        let mut block_commits = Vec::new();
+        for new_commit_hash in new_commit_hashes {
+            let new_commit = self.read_commit(new_commit_hash).await?;
+            let is_block = matches!(new_commit, Commit::Block(_));
+            block_commits.push((new_commit, new_commit_hash));
+            if !is_block {
+                continue;
+            }
             verifier
-                .apply_commit(new_commit)
-                .map_err(|e| eyre!("verification error on commit {}: {}", new_commit_hash, e))?;
+                .preverify_signatures(block_commits.iter().map(|(commit, _)| commit))
+                .map_err(|e| eyre!("verification error on the signatures: {}", e))?;
+            for (new_commit, new_commit_hash) in block_commits.drain(..) {
+                if let Commit::Block(_) = new_commit {
+                    let tree = self.get_repository_merkle_tree(new_commit_hash).await?;
+                    verifier.set_reserved_state_root(tree.reserved_root());
+                    verifier.set_non_reserved_state_root(tree.non_reserved_root());
+                }
+                verifier.apply_commit(&new_commit).map_err(|e| {
+                    eyre!("verification error on commit {}: {}", new_commit_hash, e)
+                })?;
+            }
         }
         verifier
             .verify_last_header_finalization(last_block_proof)
@@ -375,10 +433,11 @@ impl<T: RawRepository> DistributedRepository<T> {
                 let commits =
                     read_commits(self, last_header_commit_hash, branch_commit_hash).await?;
                 let last_header = self.get_last_finalized_block_header().await?;
+                let encoding = last_header.hash_encoding().map_err(|e| eyre!(e))?;
                 for (commit, hash) in commits {
                     if let Commit::Agenda(agenda) = commit {
                         if agenda.height == last_header.height + 1 {
-                            agendas.push((hash, agenda.to_hash256()));
+                            agendas.push((hash, agenda.to_hash256_with(encoding)));
                         }
                     }
                 }
@@ -459,8 +518,12 @@ impl<T: RawRepository> DistributedRepository<T> {
         let reserved_state = self.get_reserved_state().await?;
         let finalized_commit_hash = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
         let commits = utils::read_commits(self, finalized_commit_hash, agenda_commit_hash).await?;
-        let mut verifier = CommitSequenceVerifier::new(finalized_header.clone(), reserved_state)
-            .map_err(|e| eyre!("failed to create a verifier: {}", e))?;
+        let mut verifier = CommitSequenceVerifier::new(
+            finalized_header.clone(),
+            reserved_state,
+            VerifierOptions::default(),
+        )
+        .map_err(|e| eyre!("failed to create a verifier: {}", e))?;
         for (commit, hash) in commits.iter() {
             verifier
                 .apply_commit(commit)
@@ -477,7 +540,8 @@ impl<T: RawRepository> DistributedRepository<T> {
         // Create agenda proof commit
         let agenda_proof = AgendaProof {
             height: agenda.height,
-            agenda_hash: agenda_commit.to_hash256(),
+            agenda_hash: agenda_commit
+                .to_hash256_with(finalized_header.hash_encoding().map_err(|e| eyre!(e))?),
             proof,
         };
 
@@ -535,8 +599,12 @@ impl<T: RawRepository> DistributedRepository<T> {
         }
         // Check the validity of the commit sequence
         let reserved_state = self.get_reserved_state().await?;
-        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state)
-            .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
+        let mut verifier = CommitSequenceVerifier::new(
+            last_header.clone(),
+            reserved_state,
+            VerifierOptions::default(),
+        )
+        .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
         let commits = read_commits(self, last_header_commit, work_commit).await?;
         for (commit, hash) in commits.iter() {
             verifier
@@ -561,7 +629,10 @@ impl<T: RawRepository> DistributedRepository<T> {
         let agenda = Agenda {
             author,
             timestamp: get_timestamp(),
-            transactions_hash: Agenda::calculate_transactions_hash(&transactions),
+            transactions_hash: Agenda::calculate_transactions_hash(
+                &transactions,
+                last_header.hash_encoding().map_err(|e| eyre!(e))?,
+            ),
             height: last_header.height + 1,
         };
         let agenda_commit = Commit::Agenda(agenda.clone());
@@ -636,13 +707,22 @@ impl<T: RawRepository> DistributedRepository<T> {
         let last_header = self.get_last_finalized_block_header().await?;
         self.raw.checkout(WORK_BRANCH_NAME.into()).await?;
         let reserved_state = self.get_reserved_state().await?;
-        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state.clone())
-            .map_err(|e| eyre!("verification error on commit {}: {}", last_header_commit, e))?;
+        let mut verifier = CommitSequenceVerifier::new(
+            last_header.clone(),
+            reserved_state,
+            VerifierOptions::default(),
+        )
+        .map_err(|e| eyre!("verification error on commit {}: {}", last_header_commit, e))?;
         for (commit, hash) in commits.iter() {
             verifier
                 .apply_commit(commit)
                 .map_err(|e| eyre!("verification error on commit {}: {}", hash, e))?;
         }
+        simperby_common::verify::verify_protocol_version_supported(
+            &verifier.get_reserved_state().version,
+            last_header.height + 1,
+        )
+        .map_err(|e| eyre!("can't create a block: {}", e))?;
 
         // Check whether the commit sequence is in the agenda proof phase or
         // extra-agenda transaction phase.
@@ -654,6 +734,7 @@ impl<T: RawRepository> DistributedRepository<T> {
         match last_commit {
             Commit::AgendaProof(_) => (),
             Commit::ExtraAgendaTransaction(_) => (),
+            Commit::ChatLog(_) => (),
             x => return Err(eyre!("a block can't be made on top of a commit {:?}", x)),
         }
 
@@ -679,26 +760,51 @@ impl<T: RawRepository> DistributedRepository<T> {
         let fp_commit_hash = self.raw.locate_branch(FP_BRANCH_NAME.into()).await?;
         let fp_semantic_commit = self.raw.read_semantic_commit(fp_commit_hash).await?;
         let finalization_proof = fp_from_semantic_commit(fp_semantic_commit).unwrap().proof;
+        // The signers of the last block tell whether the delegatees are active.
+        let last_signers =
+            simperby_common::verify::verify_finalization_signers(&last_header, &finalization_proof)
+                .map_err(|e| eyre!("invalid finalization proof: {}", e))?;
+
+        // Calculate the repository merkle root from the `work` branch
+        // (note that the block commit changes nothing but the reserved state).
+        let repository_merkle_tree = self.get_repository_merkle_tree(work_commit).await?;
+        let reserved_files = verifier.get_reserved_state().to_files();
+        let reserved_state_root = merkle_tree::FileMerkleTree::create(reserved_files).root();
 
         // Create block commit
+        let height = last_header.height + 1;
+        let timestamp = get_timestamp();
         let block_header = BlockHeader {
             author: author.clone(),
             prev_block_finalization_proof: finalization_proof,
             previous_hash: last_header.to_hash256(),
-            height: last_header.height + 1,
-            timestamp: get_timestamp(),
+            height,
+            timestamp,
             commit_merkle_root: BlockHeader::calculate_commit_merkle_root(
                 &commits
                     .iter()
                     .map(|(commit, _)| commit.clone())
                     .collect::<Vec<_>>(),
+                last_header.hash_encoding().map_err(|e| eyre!(e))?,
             ),
-            repository_merkle_root: Hash256::zero(), // TODO
-            validator_set: reserved_state.get_validator_set().unwrap(),
-            version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+            repository_merkle_root: BlockHeader::calculate_repository_merkle_root(
+                verifier.get_reserved_state(),
+                repository_merkle_tree.non_reserved_root(),
+            ),
+            validator_set: verifier
+                .get_reserved_state()
+                .get_validator_set(height, timestamp, Some(&last_signers))
+                .map_err(|e| eyre!("failed to get the validator set: {}", e))?,
+            version: verifier.get_reserved_state().version.clone(),
         };
         let block_commit = Commit::Block(block_header.clone());
-        let semantic_commit = to_semantic_commit(&block_commit);
+        let mut semantic_commit = to_semantic_commit(&block_commit);
+        // The extra-agenda transactions (e.g., delegations) update the reserved state
+        // without any diff, so the block commit writes the result of them
+        // if the actual reserved directory doesn't match it.
+        if repository_merkle_tree.reserved_root() != reserved_state_root {
+            semantic_commit.diff = Diff::Reserved(Box::new(verifier.get_reserved_state().clone()));
+        }
 
         self.raw.checkout_clean().await?;
         self.raw.checkout(WORK_BRANCH_NAME.into()).await?;
@@ -710,11 +816,55 @@ impl<T: RawRepository> DistributedRepository<T> {
         Ok((block_header, result))
     }
 
-    /// Creates an agenda commit on top of the `work` branch.
+    /// Creates an extra-agenda transaction commit on top of the `work` branch.
     pub async fn create_extra_agenda_transaction(
         &mut self,
-        _transaction: &ExtraAgendaTransaction,
+        transaction: &ExtraAgendaTransaction,
     ) -> Result<CommitHash, Error> {
-        unimplemented!()
+        let work_commit = self.raw.locate_branch(WORK_BRANCH_NAME.into()).await?;
+        let last_header_commit = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+
+        // Check if the `work` branch is rebased on top of the `finalized` branch.
+        if self
+            .raw
+            .find_merge_base(last_header_commit, work_commit)
+            .await?
+            != last_header_commit
+        {
+            return Err(eyre!(
+                "branch {} should be rebased on {}",
+                WORK_BRANCH_NAME,
+                FINALIZED_BRANCH_NAME
+            ));
+        }
+
+        // Check the validity of the commit sequence with the new transaction,
+        // which must follow an agenda proof or other extra-agenda transactions.
+        let last_header = self.get_last_finalized_block_header().await?;
+        let reserved_state = self.get_reserved_state().await?;
+        let mut verifier = CommitSequenceVerifier::new(
+            last_header.clone(),
+            reserved_state,
+            VerifierOptions::default(),
+        )
+        .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
+        let commits = read_commits(self, last_header_commit, work_commit).await?;
+        for (commit, hash) in commits.iter() {
+            verifier
+                .apply_commit(commit)
+                .map_err(|e| eyre!("verification error on commit {}: {}", hash, e))?;
+        }
+        let commit = Commit::ExtraAgendaTransaction(transaction.clone());
+        verifier
+            .apply_commit(&commit)
+            .map_err(|e| eyre!("invalid extra-agenda transaction: {}", e))?;
+
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(WORK_BRANCH_NAME.into()).await?;
+        let result = self
+            .raw
+            .create_semantic_commit(to_semantic_commit(&commit))
+            .await?;
+        Ok(result)
     }
 }
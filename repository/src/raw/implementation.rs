@@ -477,6 +477,51 @@ impl RawRepositoryImplInner {
         Ok(email)
     }
 
+    pub(crate) fn read_files(
+        &self,
+        commit_hash: CommitHash,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let oid = git2::Oid::from_bytes(&commit_hash.hash)?;
+        let tree = self.repo.find_commit(oid)?.tree()?;
+
+        let mut files = vec![];
+        let mut error = None;
+        let walk_result = tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() != Some(ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
+            }
+            match entry
+                .to_object(&self.repo)
+                .and_then(|object| object.peel_to_blob())
+            {
+                Ok(blob) => match entry.name() {
+                    Some(name) => {
+                        files.push((format!("{}{}", root, name), blob.content().to_vec()));
+                        git2::TreeWalkResult::Ok
+                    }
+                    None => {
+                        error = Some(Error::InvalidRepository(format!(
+                            "file name is not valid UTF-8: {}{}",
+                            root,
+                            String::from_utf8_lossy(entry.name_bytes())
+                        )));
+                        git2::TreeWalkResult::Abort
+                    }
+                },
+                Err(e) => {
+                    error = Some(e.into());
+                    git2::TreeWalkResult::Abort
+                }
+            }
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        walk_result?;
+
+        Ok(files)
+    }
+
     pub(crate) fn list_ancestors(
         &self,
         commit_hash: CommitHash,
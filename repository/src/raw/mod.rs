@@ -166,6 +166,11 @@ pub trait RawRepository: Send + Sync + 'static {
     /// Returns the diff of the given commit.
     async fn show_commit(&self, commit_hash: CommitHash) -> Result<String, Error>;
 
+    /// Returns the files in the tree of the given commit as `(path, content)`.
+    ///
+    /// The path is relative to the root of the repository and uses `/` as the separator.
+    async fn read_files(&self, commit_hash: CommitHash) -> Result<Vec<(String, Vec<u8>)>, Error>;
+
     /// Lists the ancestor commits of the given commit (The first element is the direct parent).
     ///
     /// It fails if there is a merge commit.
@@ -489,6 +494,10 @@ impl RawRepository for RawRepositoryImpl {
         helper_1(self, RawRepositoryImplInner::show_commit, commit_hash).await
     }
 
+    async fn read_files(&self, commit_hash: CommitHash) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        helper_1(self, RawRepositoryImplInner::read_files, commit_hash).await
+    }
+
     async fn list_ancestors(
         &self,
         commit_hash: CommitHash,
@@ -42,42 +42,18 @@ pub async fn read_reserved_state(path: &str) -> Result<ReservedState, Error> {
 }
 
 /// Writes the given reserved state to the given path, overwriting the existing file.
+///
+/// The files are from `ReservedState::to_files()` so that they match `repository_merkle_root`.
 pub async fn write_reserved_state(path: &str, state: &ReservedState) -> Result<(), Error> {
-    let genesis_info = serde_spb::to_string(&state.genesis_info)?;
-    let consensus_leader_order = serde_spb::to_string(&state.consensus_leader_order)?;
-    let version = serde_spb::to_string(&state.version)?;
-
     // Create files of reserved state.
-    let path = format!("{}/{}", path, "reserved");
-    let reserved_path = Path::new(path.as_str());
-    if !reserved_path.exists() {
-        fs::create_dir(path.as_str()).await?;
-    } else {
-        fs::remove_dir_all(path.as_str()).await?;
-        fs::create_dir(path.as_str()).await?;
+    let reserved_path = format!("{}/{}", path, RESERVED_DIRECTORY);
+    if Path::new(reserved_path.as_str()).exists() {
+        fs::remove_dir_all(reserved_path.as_str()).await?;
     }
+    fs::create_dir_all(format!("{}/{}", reserved_path.as_str(), "members")).await?;
 
-    fs::write(
-        format!("{}/{}", path.as_str(), "genesis_info.json"),
-        genesis_info,
-    )
-    .await?;
-    fs::write(
-        format!("{}/{}", path.as_str(), "consensus_leader_order.json"),
-        consensus_leader_order,
-    )
-    .await?;
-    fs::write(format!("{}/{}", path.as_str(), "version"), version).await?;
-
-    let path = format!("{}/{}", path.as_str(), "members");
-    let members_path = Path::new(path.as_str());
-    if !members_path.exists() {
-        fs::create_dir(path.as_str()).await?;
-    }
-    for member in &state.members {
-        let file_name = format!("{}{}", member.name, ".json");
-        let member = serde_spb::to_string(member)?;
-        fs::write(format!("{}/{}", path.as_str(), file_name), member).await?;
+    for (file_path, content) in state.to_files() {
+        fs::write(format!("{}/{}", path, file_path), content).await?;
     }
 
     Ok(())
@@ -508,3 +508,23 @@ async fn semantic_commit() {
 
     assert_eq!(semantic_commit_nonreserved.diff, Diff::NonReserved(hash));
 }
+
+/// Read the files of a commit which adds a file.
+#[tokio::test]
+async fn read_files() {
+    let td = TempDir::new().unwrap();
+    let path = td.path();
+    let mut repo = init_repository_with_initial_commit(path).await.unwrap();
+
+    let initial_commit = repo.get_head().await.unwrap();
+    let files = repo.read_files(initial_commit).await.unwrap();
+    assert!(files.is_empty());
+
+    // `create_commit` adds a file named `test` with the content `test`.
+    let commit_file = repo
+        .create_commit("add a file".to_string(), None)
+        .await
+        .unwrap();
+    let files = repo.read_files(commit_file).await.unwrap();
+    assert_eq!(files, vec![("test".to_owned(), b"test".to_vec())]);
+}
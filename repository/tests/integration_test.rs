@@ -9,6 +9,7 @@ async fn basic_1() {
     let port = dispense_port();
 
     let (rs, keys) = generate_standard_genesis(4);
+    let encoding = rs.genesis_info.header.hash_encoding().unwrap();
     let config = Config {
         mirrors: Vec::new(),
         long_range_attack_distance: 1,
@@ -68,13 +69,15 @@ async fn basic_1() {
     client_node_repo.fetch().await.unwrap();
     assert_eq!(
         client_node_repo.get_agendas().await.unwrap(),
-        vec![(agenda_commit, agenda.to_hash256())]
+        vec![(agenda_commit, agenda.to_hash256_with(encoding))]
     );
     let agenda_proof = server_node_repo
         .approve(
-            &agenda.to_hash256(),
+            &agenda.to_hash256_with(encoding),
             keys.iter()
-                .map(|(_, private_key)| TypedSignature::sign(&agenda, private_key).unwrap())
+                .map(|(_, private_key)| {
+                    TypedSignature::sign_with(&agenda, encoding, private_key).unwrap()
+                })
                 .collect(),
         )
         .await
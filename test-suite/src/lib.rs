@@ -68,11 +68,14 @@ pub fn generate_standard_genesis(
             consensus_voting_power: 1,
             governance_delegations: None,
             consensus_delegations: None,
+            governance_delegation_conditions: vec![],
+            consensus_delegation_conditions: vec![],
+            proof_of_possession: None,
         })
         .collect::<Vec<_>>();
     let genesis_header = BlockHeader {
         author: PublicKey::zero(),
-        prev_block_finalization_proof: Vec::new(),
+        prev_block_finalization_proof: FinalizationProof::default(),
         previous_hash: Hash256::zero(),
         height: 0,
         timestamp: 0,
@@ -89,7 +92,8 @@ pub fn generate_standard_genesis(
         genesis_proof: keys
             .iter()
             .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
-            .collect::<Vec<_>>(),
+            .collect::<Vec<_>>()
+            .into(),
         chain_name: "test-chain".to_string(),
     };
     (
@@ -135,6 +139,9 @@ pub fn generate_delegated_genesis(
             } else {
                 None
             },
+            governance_delegation_conditions: vec![],
+            consensus_delegation_conditions: vec![],
+            proof_of_possession: None,
         })
         .collect::<Vec<_>>();
     // remove key of member-0000
@@ -146,7 +153,7 @@ pub fn generate_delegated_genesis(
         .collect::<Vec<_>>();
     let genesis_header = BlockHeader {
         author: PublicKey::zero(),
-        prev_block_finalization_proof: Vec::new(),
+        prev_block_finalization_proof: FinalizationProof::default(),
         previous_hash: Hash256::zero(),
         height: 0,
         timestamp: 0,
@@ -163,7 +170,8 @@ pub fn generate_delegated_genesis(
         genesis_proof: keys
             .iter()
             .map(|(_, private_key)| TypedSignature::sign(&genesis_header, private_key).unwrap())
-            .collect::<Vec<_>>(),
+            .collect::<Vec<_>>()
+            .into(),
         chain_name: "test-chain".to_string(),
     };
     (
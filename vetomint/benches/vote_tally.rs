@@ -0,0 +1,74 @@
+//! Processes all the votes of a height with many validators,
+//! where every vote has to be tallied with the previous ones.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use vetomint::*;
+
+fn height_info(validators: usize) -> HeightInfo {
+    HeightInfo {
+        validators: vec![1; validators],
+        this_node_index: Some(1),
+        timestamp: 0,
+        consensus_params: ConsensusParams {
+            propose_timeout: TimeoutParams::constant(1000),
+            prevote_timeout: TimeoutParams::constant(1000),
+            precommit_timeout: TimeoutParams::constant(1000),
+            repeat_round_for_first_leader: 1,
+        },
+        initial_block_candidate: 0,
+    }
+}
+
+fn events(validators: usize) -> Vec<ConsensusEvent> {
+    let mut events = vec![
+        ConsensusEvent::Start,
+        ConsensusEvent::BlockProposalReceived {
+            proposal: 0,
+            valid: true,
+            valid_round: None,
+            proposer: 0,
+            round: 0,
+            favor: true,
+        },
+    ];
+    let others = (0..validators).filter(|&i| i != 1);
+    events.extend(others.clone().map(|signer| ConsensusEvent::Prevote {
+        proposal: Some(0),
+        signer,
+        round: 0,
+    }));
+    events.extend(others.map(|signer| ConsensusEvent::Precommit {
+        proposal: Some(0),
+        signer,
+        round: 0,
+    }));
+    events
+}
+
+fn vote_tally(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vote_tally");
+    for validators in [100, 300] {
+        let height_info = height_info(validators);
+        let events = events(validators);
+        group.bench_with_input(
+            BenchmarkId::new("height", validators),
+            &events,
+            |b, events| {
+                b.iter(|| {
+                    let mut vetomint = Vetomint::new(height_info.clone());
+                    let mut finalized = false;
+                    for event in events {
+                        let responses = vetomint.progress(event.clone(), 0);
+                        finalized |= responses
+                            .iter()
+                            .any(|r| matches!(r, ConsensusResponse::FinalizeBlock { .. }));
+                    }
+                    assert!(finalized);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, vote_tally);
+criterion_main!(benches);
@@ -15,11 +15,119 @@ pub type VotingPower = u64;
 pub type Timestamp = i64;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "SerializedConsensusParams")]
 pub struct ConsensusParams {
-    pub timeout_ms: u64,
+    /// The time to wait for the proposal, from the beginning of the round.
+    pub propose_timeout: TimeoutParams,
+    /// The time to wait for more prevotes, after having any 2/3 of them.
+    pub prevote_timeout: TimeoutParams,
+    /// The time to wait for more precommits, after having any 5/6 of them.
+    pub precommit_timeout: TimeoutParams,
     pub repeat_round_for_first_leader: usize,
 }
 
+/// The serialized format of `ConsensusParams`, which also accepts the former one with a single timeout.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedConsensusParams {
+    Current {
+        propose_timeout: TimeoutParams,
+        prevote_timeout: TimeoutParams,
+        precommit_timeout: TimeoutParams,
+        repeat_round_for_first_leader: usize,
+    },
+    Legacy {
+        timeout_ms: u64,
+        repeat_round_for_first_leader: usize,
+    },
+}
+
+impl From<SerializedConsensusParams> for ConsensusParams {
+    fn from(serialized: SerializedConsensusParams) -> Self {
+        match serialized {
+            SerializedConsensusParams::Current {
+                propose_timeout,
+                prevote_timeout,
+                precommit_timeout,
+                repeat_round_for_first_leader,
+            } => Self {
+                propose_timeout,
+                prevote_timeout,
+                precommit_timeout,
+                repeat_round_for_first_leader,
+            },
+            SerializedConsensusParams::Legacy {
+                timeout_ms,
+                repeat_round_for_first_leader,
+            } => Self {
+                propose_timeout: TimeoutParams::constant(timeout_ms),
+                prevote_timeout: TimeoutParams::constant(timeout_ms),
+                precommit_timeout: TimeoutParams::constant(timeout_ms),
+                repeat_round_for_first_leader,
+            },
+        }
+    }
+}
+
+impl ConsensusParams {
+    /// Checks that every timeout is well-formed.
+    pub fn validate(&self) -> Result<(), String> {
+        for (step, timeout) in [
+            ("propose", &self.propose_timeout),
+            ("prevote", &self.prevote_timeout),
+            ("precommit", &self.precommit_timeout),
+        ] {
+            timeout
+                .validate()
+                .map_err(|e| format!("invalid {} timeout: {}", step, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// A timeout that grows as the round goes on, so that the network eventually catches up with the actual latency.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TimeoutParams {
+    /// The timeout for the round 0.
+    pub initial_ms: u64,
+    pub growth: TimeoutGrowth,
+    /// The upper bound of the timeout.
+    pub max_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TimeoutGrowth {
+    /// Adds `increment_ms` for every round.
+    Linear { increment_ms: u64 },
+    /// Multiplies by `factor` (at least 1) for every round.
+    Exponential { factor: u64 },
+}
+
+impl TimeoutParams {
+    /// The timeout that stays the same for every round.
+    pub fn constant(timeout_ms: u64) -> Self {
+        Self {
+            initial_ms: timeout_ms,
+            growth: TimeoutGrowth::Linear { increment_ms: 0 },
+            max_ms: timeout_ms,
+        }
+    }
+
+    /// Checks that the timeout never decreases and stays within `max_ms`.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.initial_ms > self.max_ms {
+            return Err(format!(
+                "the initial timeout {} is larger than the maximum {}",
+                self.initial_ms, self.max_ms
+            ));
+        }
+        if let TimeoutGrowth::Exponential { factor: 0 } = self.growth {
+            return Err("the exponential factor must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
 /// An event that (potentially) triggers a state transition of `StateMachine`.
 ///
 /// Note that there is no cryptography-related info here, because it's
@@ -81,9 +189,35 @@ pub enum ConsensusResponse {
         proposal: BlockIdentifier,
         proof: Vec<ValidatorIndex>,
     },
+    /// Reports a misbehavior of a validator with its evidence.
     ViolationReport {
         violator: ValidatorIndex,
-        description: String,
+        misbehavior: Misbehavior,
+    },
+}
+
+/// A misbehavior of a validator, proved by two conflicting messages that it has sent in the same round.
+///
+/// `first` is the one that has been accepted (and counted), and `second` is the one that has been rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Misbehavior {
+    /// Proposed two different blocks.
+    DoubleProposal {
+        round: Round,
+        first: BlockIdentifier,
+        second: BlockIdentifier,
+    },
+    /// Prevoted twice for different proposals (including nil).
+    DoublePrevote {
+        round: Round,
+        first: Option<BlockIdentifier>,
+        second: Option<BlockIdentifier>,
+    },
+    /// Precommitted twice for different proposals (including nil).
+    DoublePrecommit {
+        round: Round,
+        first: Option<BlockIdentifier>,
+        second: Option<BlockIdentifier>,
     },
 }
 
@@ -110,10 +244,25 @@ pub struct HeightInfo {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "SerializedVetomint")]
 pub struct Vetomint {
     state: state::ConsensusState,
 }
 
+/// The same format as `Vetomint`, to rebuild the vote tallies (which are not serialized) on deserialization.
+#[derive(Deserialize)]
+struct SerializedVetomint {
+    state: state::ConsensusState,
+}
+
+impl From<SerializedVetomint> for Vetomint {
+    fn from(serialized: SerializedVetomint) -> Self {
+        let mut state = serialized.state;
+        state.rebuild_tallies();
+        Self { state }
+    }
+}
+
 impl Vetomint {
     pub fn new(height_info: HeightInfo) -> Self {
         Self {
@@ -133,9 +282,9 @@ impl Vetomint {
         let mut responses = progress::progress(&mut self.state, event, timestamp);
         let mut final_responses = responses.clone();
         // feedback to myself
+        let this_node_index = self.state.height_info.this_node_index;
         loop {
             let mut responses_ = Vec::new();
-            let state = self.state.clone();
             for response in responses.clone() {
                 match response {
                     ConsensusResponse::BroadcastProposal {
@@ -148,7 +297,7 @@ impl Vetomint {
                             proposal,
                             valid: true,
                             valid_round,
-                            proposer: state.height_info.this_node_index.unwrap(),
+                            proposer: this_node_index.unwrap(),
                             round,
                             favor: true,
                         },
@@ -159,7 +308,7 @@ impl Vetomint {
                             &mut self.state,
                             ConsensusEvent::Prevote {
                                 proposal,
-                                signer: state.height_info.this_node_index.unwrap(),
+                                signer: this_node_index.unwrap(),
                                 round,
                             },
                             timestamp,
@@ -170,7 +319,7 @@ impl Vetomint {
                             &mut self.state,
                             ConsensusEvent::Precommit {
                                 proposal,
-                                signer: state.height_info.this_node_index.unwrap(),
+                                signer: this_node_index.unwrap(),
                                 round,
                             },
                             timestamp,
@@ -189,15 +338,81 @@ impl Vetomint {
     }
 }
 
+/// Decides the proposer of the round.
+///
+/// The first validator leads the first `repeat_round_for_first_leader` rounds.
+/// After that, the proposers are selected in proportion to their voting power (see `ProposerSchedule`),
+/// which is the plain round-robin starting from the second validator if all the voting powers are the same.
+///
+/// It replays the selections from the first round; the consensus state keeps a `ProposerSchedule` instead.
 pub fn decide_proposer(round: usize, height_info: &HeightInfo) -> ValidatorIndex {
-    if round < height_info.consensus_params.repeat_round_for_first_leader {
-        0
-    } else {
-        (round - height_info.consensus_params.repeat_round_for_first_leader + 1)
-            % height_info.validators.len()
+    ProposerSchedule::default().decide(round, height_info)
+}
+
+/// The proposers selected so far by the priority accumulator, as in Tendermint.
+///
+/// Every validator starts with zero priority. For each selection, the priority of every validator
+/// increases by its voting power, and the one with the highest priority (the lowest index among ties)
+/// is chosen and has its priority decreased by the total voting power.
+/// Thus each validator is chosen as often as its share of the voting power, evenly spread over the rounds.
+///
+/// The selections are extended only as far as the rounds asked, so each of them is made once.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct ProposerSchedule {
+    /// The priority of each validator after the last selection.
+    priorities: Vec<i128>,
+    /// The selected proposers in order.
+    proposers: Vec<ValidatorIndex>,
+}
+
+impl ProposerSchedule {
+    pub(crate) fn decide(&mut self, round: usize, height_info: &HeightInfo) -> ValidatorIndex {
+        let repeat_round = height_info.consensus_params.repeat_round_for_first_leader;
+        if round < repeat_round {
+            0
+        } else {
+            self.select(&height_info.validators, round - repeat_round + 1)
+        }
+    }
+
+    /// Returns the `n`-th (from 0) proposer, making the selections up to it if not made yet.
+    fn select(&mut self, validators: &[VotingPower], n: usize) -> ValidatorIndex {
+        let total_voting_power: i128 = validators.iter().map(|&x| x as i128).sum();
+        if self.priorities.is_empty() {
+            self.priorities = vec![0; validators.len()];
+        }
+        while self.proposers.len() <= n {
+            for (priority, &voting_power) in self.priorities.iter_mut().zip(validators) {
+                *priority += voting_power as i128;
+            }
+            let proposer = (0..self.priorities.len())
+                .rev()
+                .max_by_key(|&i| self.priorities[i])
+                .expect("there must be at least one validator");
+            self.priorities[proposer] -= total_voting_power;
+            self.proposers.push(proposer);
+        }
+        self.proposers[n]
     }
 }
 
-pub fn decide_timeout(params: &ConsensusParams, _round: usize) -> Timestamp {
-    params.timeout_ms as i64
+/// Returns the timeout of the given round, which is never less than `initial_ms`
+/// (even for the parameters that `TimeoutParams::validate()` rejects) nor more than `max_ms`.
+pub fn decide_timeout(params: &TimeoutParams, round: usize) -> Timestamp {
+    let timeout = match params.growth {
+        TimeoutGrowth::Linear { increment_ms } => params
+            .initial_ms
+            .saturating_add(increment_ms.saturating_mul(round as u64)),
+        TimeoutGrowth::Exponential { factor } => {
+            let multiplier = u32::try_from(round)
+                .ok()
+                .and_then(|round| factor.checked_pow(round))
+                .unwrap_or(u64::MAX);
+            params.initial_ms.saturating_mul(multiplier)
+        }
+    };
+    timeout
+        .max(params.initial_ms)
+        .min(params.max_ms)
+        .min(Timestamp::MAX as u64) as Timestamp
 }
@@ -1,5 +1,6 @@
 use super::*;
 use state::*;
+use std::collections::BTreeSet;
 
 pub(crate) fn progress(
     state: &mut ConsensusState,
@@ -19,8 +20,26 @@ pub(crate) fn progress(
             round,
             favor,
         } => {
-            state.proposals.insert(
-                proposal,
+            match state.get_proposal_of(proposer, round) {
+                Some(first) if first != proposal => {
+                    return vec![ConsensusResponse::ViolationReport {
+                        violator: proposer,
+                        misbehavior: Misbehavior::DoubleProposal {
+                            round,
+                            first,
+                            second: proposal,
+                        },
+                    }];
+                }
+                Some(_) => (),
+                None => {
+                    state
+                        .proposals_by_proposer
+                        .insert((round, proposer, proposal));
+                }
+            }
+            let mut response = receive_proposal(
+                state,
                 Proposal {
                     proposal,
                     valid,
@@ -30,23 +49,13 @@ pub(crate) fn progress(
                     favor,
                 },
             );
-            let mut response = Vec::new();
-            if valid_round.is_some() {
-                response.extend(on_4f_non_nil_prevote_in_propose_step(
-                    state, round, proposal,
-                ));
-            } else {
-                response.extend(on_proposal(state, round, proposal));
-            }
-            response.extend(on_4f_non_nil_prevote_in_prevote_step(
-                state, round, proposal,
-            ));
-            response.extend(on_4f_non_nil_precommit(state, round, proposal));
+            response.extend(on_2f_messages_from_higher_round(state, round, timestamp));
             response
         }
-        ConsensusEvent::SkipRound { round } => progress(
+        // A skip is not an actual proposal from the proposer, so it is not checked for double proposals.
+        ConsensusEvent::SkipRound { round } => receive_proposal(
             state,
-            ConsensusEvent::BlockProposalReceived {
+            Proposal {
                 proposal: 0,
                 valid: false,
                 valid_round: None,
@@ -54,7 +63,6 @@ pub(crate) fn progress(
                 round,
                 favor: false,
             },
-            timestamp,
         ),
         ConsensusEvent::BlockCandidateUpdated { proposal } => {
             state.block_candidate = proposal;
@@ -65,7 +73,19 @@ pub(crate) fn progress(
             signer,
             round,
         } => {
-            state.prevotes.insert(Vote {
+            if let Some(vote) = state.get_prevote(signer, round) {
+                if vote.proposal != proposal {
+                    return vec![ConsensusResponse::ViolationReport {
+                        violator: signer,
+                        misbehavior: Misbehavior::DoublePrevote {
+                            round,
+                            first: vote.proposal,
+                            second: proposal,
+                        },
+                    }];
+                }
+            }
+            state.insert_prevote(Vote {
                 proposal,
                 signer,
                 round,
@@ -82,6 +102,8 @@ pub(crate) fn progress(
                 response.extend(on_4f_nil_prevote(state, round));
             }
             response.extend(on_5f_prevote(state, round, proposal));
+            response.extend(on_4f_prevote(state, round, timestamp));
+            response.extend(on_2f_messages_from_higher_round(state, round, timestamp));
             response
         }
         ConsensusEvent::Precommit {
@@ -89,17 +111,30 @@ pub(crate) fn progress(
             signer,
             round,
         } => {
-            state.precommits.insert(Vote {
+            if let Some(vote) = state.get_precommit(signer, round) {
+                if vote.proposal != proposal {
+                    return vec![ConsensusResponse::ViolationReport {
+                        violator: signer,
+                        misbehavior: Misbehavior::DoublePrecommit {
+                            round,
+                            first: vote.proposal,
+                            second: proposal,
+                        },
+                    }];
+                }
+            }
+            state.insert_precommit(Vote {
                 proposal,
                 signer,
                 round,
             });
             let mut response = Vec::new();
-            response.extend(on_5f_precommit(state, round));
+            response.extend(on_5f_precommit(state, round, timestamp));
             response.extend(on_4f_nil_precommit(state, round, timestamp));
             if let Some(proposal) = proposal {
                 response.extend(on_4f_non_nil_precommit(state, round, proposal));
             }
+            response.extend(on_2f_messages_from_higher_round(state, round, timestamp));
             response
         }
         ConsensusEvent::Timer => {
@@ -116,6 +151,18 @@ pub(crate) fn progress(
                     state.step = ConsensusStep::Prevote;
                 }
             }
+            for (round, timeout) in state.prevote_timeout_schedules.clone() {
+                if timestamp >= timeout
+                    && round == state.round
+                    && state.step == ConsensusStep::Prevote
+                {
+                    response.push(ConsensusResponse::BroadcastPrecommit {
+                        proposal: None,
+                        round,
+                    });
+                    state.step = ConsensusStep::Precommit;
+                }
+            }
             for (round, timeout) in state.precommit_timeout_schedules.clone() {
                 if timestamp >= timeout && round == state.round {
                     response.extend(start_round(state, round + 1, timestamp));
@@ -127,6 +174,22 @@ pub(crate) fn progress(
     }
 }
 
+fn receive_proposal(state: &mut ConsensusState, proposal: Proposal) -> Vec<ConsensusResponse> {
+    let block = proposal.proposal;
+    let round = proposal.round;
+    let has_valid_round = proposal.valid_round.is_some();
+    state.proposals.insert(block, proposal);
+    let mut response = Vec::new();
+    if has_valid_round {
+        response.extend(on_4f_non_nil_prevote_in_propose_step(state, round, block));
+    } else {
+        response.extend(on_proposal(state, round, block));
+    }
+    response.extend(on_4f_non_nil_prevote_in_prevote_step(state, round, block));
+    response.extend(on_4f_non_nil_precommit(state, round, block));
+    response
+}
+
 fn start_round(
     state: &mut ConsensusState,
     round: usize,
@@ -134,7 +197,7 @@ fn start_round(
 ) -> Vec<ConsensusResponse> {
     state.round = round;
     state.step = ConsensusStep::Propose;
-    let proposer = decide_proposer(round, &state.height_info);
+    let proposer = state.get_proposer(round);
     if Some(proposer) == state.height_info.this_node_index {
         let proposal = if let Some(x) = state.valid_value {
             x
@@ -149,12 +212,87 @@ fn start_round(
     } else {
         state.propose_timeout_schedules.insert((
             round,
-            timestamp + decide_timeout(&state.height_info.consensus_params, round),
+            timestamp + decide_timeout(&state.height_info.consensus_params.propose_timeout, round),
         ));
         Vec::new()
     }
 }
 
+/// Skips to the higher round if more than 1/3 of the voting power has already sent messages for it.
+fn on_2f_messages_from_higher_round(
+    state: &mut ConsensusState,
+    target_round: Round,
+    timestamp: Timestamp,
+) -> Vec<ConsensusResponse> {
+    if target_round <= state.round
+        || state.get_total_senders(target_round) * 3 <= state.get_total_voting_power()
+    {
+        return Vec::new();
+    }
+    let mut response = start_round(state, target_round, timestamp);
+    let mut round = target_round;
+    // The round may be decided by the messages received before catching up,
+    // in which case the node moves on to the next one and replays it as well.
+    loop {
+        response.extend(replay_round(state, round, timestamp));
+        if state.round == round || state.finalized.is_some() {
+            break;
+        }
+        round = state.round;
+    }
+    response
+}
+
+/// Re-runs every round-scoped rule on the messages already received for the (just started) round.
+fn replay_round(
+    state: &mut ConsensusState,
+    round: Round,
+    timestamp: Timestamp,
+) -> Vec<ConsensusResponse> {
+    let mut response = Vec::new();
+    let proposals: Vec<_> = state
+        .proposals
+        .values()
+        .filter(|proposal| proposal.round == round)
+        .map(|proposal| (proposal.proposal, proposal.valid_round.is_some()))
+        .collect();
+    for &(proposal, has_valid_round) in &proposals {
+        if has_valid_round {
+            response.extend(on_4f_non_nil_prevote_in_propose_step(
+                state, round, proposal,
+            ));
+        } else {
+            response.extend(on_proposal(state, round, proposal));
+        }
+        response.extend(on_4f_non_nil_prevote_in_prevote_step(
+            state, round, proposal,
+        ));
+    }
+    response.extend(on_4f_nil_prevote(state, round));
+    // At most one proposal can have more than 2/3 of the prevotes.
+    let prevoted: BTreeSet<_> = state
+        .prevotes
+        .iter()
+        .filter(|vote| vote.round == round)
+        .filter_map(|vote| vote.proposal)
+        .collect();
+    let prevote_quorum = prevoted.into_iter().find(|&proposal| {
+        state.get_total_prevotes_on_proposal(round, proposal) * 3
+            > state.get_total_voting_power() * 2
+    });
+    response.extend(on_5f_prevote(state, round, prevote_quorum));
+    response.extend(on_4f_prevote(state, round, timestamp));
+    for &(proposal, _) in &proposals {
+        response.extend(on_4f_non_nil_precommit(state, round, proposal));
+        if state.finalized.is_some() {
+            return response;
+        }
+    }
+    response.extend(on_5f_precommit(state, round, timestamp));
+    response.extend(on_4f_nil_precommit(state, round, timestamp));
+    response
+}
+
 fn on_proposal(
     state: &mut ConsensusState,
     target_round: Round,
@@ -168,7 +306,7 @@ fn on_proposal(
     let locked_value: i64 = state.locked_value.map(|x| x as i64).unwrap_or(-1);
     let locked_round: i64 = state.locked_round.map(|x| x as i64).unwrap_or(-1);
 
-    let valid_proposer = decide_proposer(target_round, &state.height_info);
+    let valid_proposer = state.get_proposer(target_round);
     let proposal = if let Some(proposal) = state.proposals.get(&target_proposal) {
         proposal.clone()
     } else {
@@ -209,7 +347,7 @@ fn on_4f_non_nil_prevote_in_propose_step(
     // take `None` as `-1` for simple comparison
     let locked_value: i64 = state.locked_value.map(|x| x as i64).unwrap_or(-1);
     let locked_round: i64 = state.locked_round.map(|x| x as i64).unwrap_or(-1);
-    let valid_proposer = decide_proposer(target_round, &state.height_info);
+    let valid_proposer = state.get_proposer(target_round);
     let proposal = if let Some(proposal) = state.proposals.get(&target_proposal) {
         proposal.clone()
     } else {
@@ -256,7 +394,7 @@ fn on_4f_non_nil_prevote_in_prevote_step(
     if target_round != state.round {
         return Vec::new();
     }
-    let valid_proposer = decide_proposer(target_round, &state.height_info);
+    let valid_proposer = state.get_proposer(target_round);
     let proposal = if let Some(proposal) = state.proposals.get(&target_proposal) {
         proposal.clone()
     } else {
@@ -340,7 +478,36 @@ fn on_5f_prevote(
     }
 }
 
-fn on_5f_precommit(state: &mut ConsensusState, target_round: Round) -> Vec<ConsensusResponse> {
+fn on_4f_prevote(
+    state: &mut ConsensusState,
+    target_round: Round,
+    timestamp: Timestamp,
+) -> Vec<ConsensusResponse> {
+    if target_round != state.round {
+        return Vec::new();
+    }
+    if state.step == ConsensusStep::Prevote
+        && !state.for_the_first_time_1.contains(&target_round)
+        && state.get_total_prevotes(target_round) * 3 > state.get_total_voting_power() * 2
+    {
+        state.for_the_first_time_1.insert(target_round);
+        state.prevote_timeout_schedules.insert((
+            target_round,
+            timestamp
+                + decide_timeout(
+                    &state.height_info.consensus_params.prevote_timeout,
+                    target_round,
+                ),
+        ));
+    }
+    Vec::new()
+}
+
+fn on_5f_precommit(
+    state: &mut ConsensusState,
+    target_round: Round,
+    timestamp: Timestamp,
+) -> Vec<ConsensusResponse> {
     if target_round != state.round {
         return Vec::new();
     }
@@ -348,9 +515,14 @@ fn on_5f_precommit(state: &mut ConsensusState, target_round: Round) -> Vec<Conse
         && state.get_total_precommits(target_round) * 6 > state.get_total_voting_power() * 5
     {
         state.for_the_first_time_2.insert(target_round);
-        state
-            .precommit_timeout_schedules
-            .insert((target_round, 1000));
+        state.precommit_timeout_schedules.insert((
+            target_round,
+            timestamp
+                + decide_timeout(
+                    &state.height_info.consensus_params.precommit_timeout,
+                    target_round,
+                ),
+        ));
     }
     Vec::new()
 }
@@ -363,7 +535,7 @@ fn on_4f_nil_precommit(
     if target_round != state.round {
         return Vec::new();
     }
-    if state.get_total_precommits_on_nil(target_round) * 2 > state.get_total_voting_power() * 3 {
+    if state.get_total_precommits_on_nil(target_round) * 3 > state.get_total_voting_power() * 2 {
         start_round(state, target_round + 1, timestamp)
     } else {
         Vec::new()
@@ -372,10 +544,10 @@ fn on_4f_nil_precommit(
 
 fn on_4f_non_nil_precommit(
     state: &mut ConsensusState,
-    target_proposal: BlockIdentifier,
     target_round: Round,
+    target_proposal: BlockIdentifier,
 ) -> Vec<ConsensusResponse> {
-    let valid_proposer = decide_proposer(target_round, &state.height_info);
+    let valid_proposer = state.get_proposer(target_round);
     let proposal = if let Some(proposal) = state.proposals.get(&target_proposal) {
         proposal.clone()
     } else {
@@ -26,6 +26,55 @@ pub(crate) struct Vote {
     pub round: Round,
 }
 
+/// The sums of the voting power of the votes of a kind, updated as the votes are inserted.
+///
+/// It is derived from the votes, so it is not serialized but rebuilt with `ConsensusState::rebuild_tallies()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct VoteTally {
+    /// The proposal (`None` for nil) voted by each signer for each round.
+    pub votes: BTreeMap<(Round, ValidatorIndex), Option<BlockIdentifier>>,
+    /// The voting power for each round.
+    pub total: BTreeMap<Round, VotingPower>,
+    /// The voting power for each round and proposal (`None` for nil).
+    pub by_proposal: BTreeMap<(Round, Option<BlockIdentifier>), VotingPower>,
+}
+
+impl VoteTally {
+    fn insert(&mut self, vote: &Vote, voting_power: VotingPower) {
+        self.votes.insert((vote.round, vote.signer), vote.proposal);
+        *self.total.entry(vote.round).or_default() += voting_power;
+        *self
+            .by_proposal
+            .entry((vote.round, vote.proposal))
+            .or_default() += voting_power;
+    }
+
+    fn get_vote(&self, signer: ValidatorIndex, round: Round) -> Option<Vote> {
+        self.votes.get(&(round, signer)).map(|&proposal| Vote {
+            proposal,
+            signer,
+            round,
+        })
+    }
+
+    fn get_signers(&self, round: Round) -> impl Iterator<Item = ValidatorIndex> + '_ {
+        self.votes
+            .range((round, ValidatorIndex::MIN)..=(round, ValidatorIndex::MAX))
+            .map(|((_, signer), _)| *signer)
+    }
+
+    fn get_total(&self, round: Round) -> VotingPower {
+        self.total.get(&round).copied().unwrap_or_default()
+    }
+
+    fn get_total_on(&self, round: Round, proposal: Option<BlockIdentifier>) -> VotingPower {
+        self.by_proposal
+            .get(&(round, proposal))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub(crate) struct ConsensusState {
     pub height_info: HeightInfo,
@@ -37,13 +86,25 @@ pub(crate) struct ConsensusState {
     pub valid_round: Option<Round>,
     pub block_candidate: BlockIdentifier,
     pub proposals: BTreeMap<BlockIdentifier, Proposal>,
+    /// The first proposal received from each proposer for each round (`(round, proposer, proposal)`),
+    /// to detect double proposals.
+    #[serde(default)]
+    pub proposals_by_proposer: BTreeSet<(Round, ValidatorIndex, BlockIdentifier)>,
     pub prevotes: BTreeSet<Vote>,
     pub precommits: BTreeSet<Vote>,
+    #[serde(skip)]
+    pub prevote_tally: VoteTally,
+    #[serde(skip)]
+    pub precommit_tally: VoteTally,
     pub propose_timeout_schedules: BTreeSet<(Round, Timestamp)>,
+    #[serde(default)]
+    pub prevote_timeout_schedules: BTreeSet<(Round, Timestamp)>,
     pub precommit_timeout_schedules: BTreeSet<(Round, Timestamp)>,
     pub for_the_first_time_1: BTreeSet<Round>,
     pub for_the_first_time_2: BTreeSet<Round>,
     pub finalized: Option<(BlockIdentifier, Vec<ValidatorIndex>)>,
+    #[serde(default)]
+    pub proposer_schedule: ProposerSchedule,
 }
 
 impl ConsensusState {
@@ -58,34 +119,113 @@ impl ConsensusState {
             valid_round: None,
             block_candidate: BlockIdentifier::default(),
             proposals: Default::default(),
+            proposals_by_proposer: Default::default(),
             prevotes: Default::default(),
             precommits: Default::default(),
+            prevote_tally: Default::default(),
+            precommit_tally: Default::default(),
             propose_timeout_schedules: Default::default(),
+            prevote_timeout_schedules: Default::default(),
             precommit_timeout_schedules: Default::default(),
             for_the_first_time_1: Default::default(),
             for_the_first_time_2: Default::default(),
             finalized: None,
+            proposer_schedule: Default::default(),
         }
     }
 
+    /// Returns the proposer of the round, extending the proposer schedule as needed.
+    pub(crate) fn get_proposer(&mut self, round: Round) -> ValidatorIndex {
+        self.proposer_schedule.decide(round, &self.height_info)
+    }
+
     pub(crate) fn get_total_voting_power(&self) -> VotingPower {
         self.height_info.validators.iter().sum()
     }
 
-    pub(crate) fn get_total_prevotes(&self, round: Round) -> VotingPower {
-        self.prevotes
-            .iter()
-            .filter(|vote| vote.round == round)
-            .map(|vote| self.height_info.validators[vote.signer])
+    /// Returns the first proposal received from the proposer for the round, if any.
+    pub(crate) fn get_proposal_of(
+        &self,
+        proposer: ValidatorIndex,
+        round: Round,
+    ) -> Option<BlockIdentifier> {
+        self.proposals_by_proposer
+            .range(
+                (round, proposer, BlockIdentifier::MIN)..=(round, proposer, BlockIdentifier::MAX),
+            )
+            .next()
+            .map(|(_, _, proposal)| *proposal)
+    }
+
+    /// Rebuilds the vote tallies from the votes, which is required after deserialization.
+    pub(crate) fn rebuild_tallies(&mut self) {
+        self.prevote_tally = VoteTally::default();
+        for vote in &self.prevotes {
+            self.prevote_tally
+                .insert(vote, self.height_info.validators[vote.signer]);
+        }
+        self.precommit_tally = VoteTally::default();
+        for vote in &self.precommits {
+            self.precommit_tally
+                .insert(vote, self.height_info.validators[vote.signer]);
+        }
+    }
+
+    pub(crate) fn insert_prevote(&mut self, vote: Vote) {
+        let voting_power = self.height_info.validators[vote.signer];
+        if !self.prevotes.contains(&vote) {
+            self.prevote_tally.insert(&vote, voting_power);
+            self.prevotes.insert(vote);
+        }
+    }
+
+    pub(crate) fn insert_precommit(&mut self, vote: Vote) {
+        let voting_power = self.height_info.validators[vote.signer];
+        if !self.precommits.contains(&vote) {
+            self.precommit_tally.insert(&vote, voting_power);
+            self.precommits.insert(vote);
+        }
+    }
+
+    /// Returns the prevote of the signer for the round, if any.
+    ///
+    /// There is at most one because a conflicting vote is never inserted.
+    pub(crate) fn get_prevote(&self, signer: ValidatorIndex, round: Round) -> Option<Vote> {
+        self.prevote_tally.get_vote(signer, round)
+    }
+
+    /// Returns the precommit of the signer for the round, if any.
+    pub(crate) fn get_precommit(&self, signer: ValidatorIndex, round: Round) -> Option<Vote> {
+        self.precommit_tally.get_vote(signer, round)
+    }
+
+    /// Returns the total voting power of the validators who have sent any message for the round.
+    pub(crate) fn get_total_senders(&self, round: Round) -> VotingPower {
+        let senders: BTreeSet<ValidatorIndex> = self
+            .prevote_tally
+            .get_signers(round)
+            .chain(self.precommit_tally.get_signers(round))
+            .chain(
+                self.proposals_by_proposer
+                    .range(
+                        (round, ValidatorIndex::MIN, BlockIdentifier::MIN)
+                            ..=(round, ValidatorIndex::MAX, BlockIdentifier::MAX),
+                    )
+                    .map(|(_, proposer, _)| *proposer),
+            )
+            .collect();
+        senders
+            .into_iter()
+            .map(|signer| self.height_info.validators[signer])
             .sum()
     }
 
+    pub(crate) fn get_total_prevotes(&self, round: Round) -> VotingPower {
+        self.prevote_tally.get_total(round)
+    }
+
     pub(crate) fn get_total_precommits(&self, round: Round) -> VotingPower {
-        self.precommits
-            .iter()
-            .filter(|vote| vote.round == round)
-            .map(|vote| self.height_info.validators[vote.signer])
-            .sum()
+        self.precommit_tally.get_total(round)
     }
 
     pub(crate) fn get_total_prevotes_on_proposal(
@@ -93,11 +233,7 @@ impl ConsensusState {
         round: Round,
         proposal: BlockIdentifier,
     ) -> VotingPower {
-        self.prevotes
-            .iter()
-            .filter(|vote| vote.round == round && vote.proposal == Some(proposal))
-            .map(|vote| self.height_info.validators[vote.signer])
-            .sum()
+        self.prevote_tally.get_total_on(round, Some(proposal))
     }
 
     pub(crate) fn get_total_precommits_on_proposal(
@@ -105,26 +241,14 @@ impl ConsensusState {
         round: Round,
         proposal: BlockIdentifier,
     ) -> VotingPower {
-        self.precommits
-            .iter()
-            .filter(|vote| vote.round == round && vote.proposal == Some(proposal))
-            .map(|vote| self.height_info.validators[vote.signer])
-            .sum()
+        self.precommit_tally.get_total_on(round, Some(proposal))
     }
 
     pub(crate) fn get_total_prevotes_on_nil(&self, round: Round) -> VotingPower {
-        self.prevotes
-            .iter()
-            .filter(|vote| vote.round == round && vote.proposal.is_none())
-            .map(|vote| self.height_info.validators[vote.signer])
-            .sum()
+        self.prevote_tally.get_total_on(round, None)
     }
 
     pub(crate) fn get_total_precommits_on_nil(&self, round: Round) -> VotingPower {
-        self.precommits
-            .iter()
-            .filter(|vote| vote.round == round && vote.proposal.is_none())
-            .map(|vote| self.height_info.validators[vote.signer])
-            .sum()
+        self.precommit_tally.get_total_on(round, None)
     }
 }
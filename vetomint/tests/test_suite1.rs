@@ -8,7 +8,9 @@ fn normal_1() {
         this_node_index: Some(0),
         timestamp: 0,
         consensus_params: ConsensusParams {
-            timeout_ms: 100,
+            propose_timeout: TimeoutParams::constant(100),
+            prevote_timeout: TimeoutParams::constant(100),
+            precommit_timeout: TimeoutParams::constant(100),
             repeat_round_for_first_leader: 1,
         },
         initial_block_candidate: 0,
@@ -125,3 +127,771 @@ fn normal_1() {
         );
     }
 }
+
+/// A proposal whose identifier differs from the round is finalized.
+#[test]
+fn normal_2() {
+    let height_info = HeightInfo {
+        validators: vec![1, 1, 1, 1],
+        this_node_index: Some(1),
+        timestamp: 0,
+        consensus_params: ConsensusParams {
+            propose_timeout: TimeoutParams::constant(100),
+            prevote_timeout: TimeoutParams::constant(100),
+            precommit_timeout: TimeoutParams::constant(100),
+            repeat_round_for_first_leader: 1,
+        },
+        initial_block_candidate: 0,
+    };
+    let mut node = Vetomint::new(height_info);
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+    let response = node.progress(
+        ConsensusEvent::BlockProposalReceived {
+            proposal: 7,
+            valid: true,
+            valid_round: None,
+            proposer: 0,
+            round: 0,
+            favor: true,
+        },
+        1,
+    );
+    assert_eq!(
+        response,
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: Some(7),
+            round: 0,
+        }]
+    );
+    for signer in [0, 2] {
+        node.progress(
+            ConsensusEvent::Prevote {
+                proposal: Some(7),
+                signer,
+                round: 0,
+            },
+            2,
+        );
+    }
+    node.progress(
+        ConsensusEvent::Precommit {
+            proposal: Some(7),
+            signer: 0,
+            round: 0,
+        },
+        3,
+    );
+    let response = node.progress(
+        ConsensusEvent::Precommit {
+            proposal: Some(7),
+            signer: 2,
+            round: 0,
+        },
+        3,
+    );
+    assert_eq!(
+        response,
+        vec![ConsensusResponse::FinalizeBlock {
+            proposal: 7,
+            proof: vec![0, 1, 2],
+        }]
+    );
+}
+
+/// A validator sends conflicting messages, which are reported and counted only once.
+#[test]
+fn double_votes_1() {
+    let height_info = HeightInfo {
+        validators: vec![1, 1, 1, 1],
+        this_node_index: Some(1),
+        timestamp: 0,
+        consensus_params: ConsensusParams {
+            propose_timeout: TimeoutParams::constant(100),
+            prevote_timeout: TimeoutParams::constant(100),
+            precommit_timeout: TimeoutParams::constant(100),
+            repeat_round_for_first_leader: 1,
+        },
+        initial_block_candidate: 0,
+    };
+    let mut node = Vetomint::new(height_info);
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+
+    let proposal = |proposal| ConsensusEvent::BlockProposalReceived {
+        proposal,
+        valid: true,
+        valid_round: None,
+        proposer: 0,
+        round: 0,
+        favor: true,
+    };
+    assert_eq!(
+        node.progress(proposal(0), 1),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: Some(0),
+            round: 0,
+        }]
+    );
+    assert_eq!(
+        node.progress(proposal(1), 1),
+        vec![ConsensusResponse::ViolationReport {
+            violator: 0,
+            misbehavior: Misbehavior::DoubleProposal {
+                round: 0,
+                first: 0,
+                second: 1,
+            },
+        }]
+    );
+
+    let prevote = |proposal, signer| ConsensusEvent::Prevote {
+        proposal,
+        signer,
+        round: 0,
+    };
+    assert_eq!(node.progress(prevote(Some(0), 2), 2), vec![]);
+    // The same vote again is not a violation.
+    assert_eq!(node.progress(prevote(Some(0), 2), 2), vec![]);
+    assert_eq!(
+        node.progress(prevote(None, 2), 2),
+        vec![ConsensusResponse::ViolationReport {
+            violator: 2,
+            misbehavior: Misbehavior::DoublePrevote {
+                round: 0,
+                first: Some(0),
+                second: None,
+            },
+        }]
+    );
+    assert_eq!(
+        node.progress(prevote(Some(0), 3), 2),
+        vec![ConsensusResponse::BroadcastPrecommit {
+            proposal: Some(0),
+            round: 0,
+        }]
+    );
+
+    let precommit = |proposal, signer| ConsensusEvent::Precommit {
+        proposal,
+        signer,
+        round: 0,
+    };
+    assert_eq!(node.progress(precommit(Some(0), 2), 3), vec![]);
+    assert_eq!(
+        node.progress(precommit(None, 2), 3),
+        vec![ConsensusResponse::ViolationReport {
+            violator: 2,
+            misbehavior: Misbehavior::DoublePrecommit {
+                round: 0,
+                first: Some(0),
+                second: None,
+            },
+        }]
+    );
+    assert_eq!(
+        node.progress(precommit(Some(0), 3), 3),
+        vec![ConsensusResponse::FinalizeBlock {
+            proposal: 0,
+            proof: vec![1, 2, 3],
+        }]
+    );
+}
+
+/// 2/3 of the precommits for nil move the node to the next round without waiting for the timeout.
+#[test]
+fn nil_precommits_1() {
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 1]);
+    height_info.this_node_index = Some(1);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    let mut node = Vetomint::new(height_info);
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 100),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: None,
+            round: 0,
+        }]
+    );
+    node.progress(
+        ConsensusEvent::Prevote {
+            proposal: None,
+            signer: 0,
+            round: 0,
+        },
+        110,
+    );
+    assert_eq!(
+        node.progress(
+            ConsensusEvent::Prevote {
+                proposal: None,
+                signer: 2,
+                round: 0,
+            },
+            110,
+        ),
+        vec![ConsensusResponse::BroadcastPrecommit {
+            proposal: None,
+            round: 0,
+        }]
+    );
+    node.progress(
+        ConsensusEvent::Precommit {
+            proposal: None,
+            signer: 0,
+            round: 0,
+        },
+        120,
+    );
+    assert_eq!(
+        node.progress(
+            ConsensusEvent::Precommit {
+                proposal: None,
+                signer: 2,
+                round: 0,
+            },
+            120,
+        ),
+        vec![
+            ConsensusResponse::BroadcastProposal {
+                proposal: 0,
+                valid_round: None,
+                round: 1,
+            },
+            ConsensusResponse::BroadcastPrevote {
+                proposal: Some(0),
+                round: 1,
+            }
+        ]
+    );
+}
+
+/// Every step times out, with the timeouts growing over the rounds.
+#[test]
+fn timeouts_1() {
+    let height_info = HeightInfo {
+        validators: vec![1, 1, 1, 1],
+        this_node_index: Some(2),
+        timestamp: 0,
+        consensus_params: ConsensusParams {
+            propose_timeout: TimeoutParams {
+                initial_ms: 100,
+                growth: TimeoutGrowth::Exponential { factor: 2 },
+                max_ms: 1000,
+            },
+            prevote_timeout: TimeoutParams {
+                initial_ms: 50,
+                growth: TimeoutGrowth::Linear { increment_ms: 10 },
+                max_ms: 100,
+            },
+            precommit_timeout: TimeoutParams::constant(30),
+            repeat_round_for_first_leader: 1,
+        },
+        initial_block_candidate: 0,
+    };
+    let mut node = Vetomint::new(height_info);
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+    assert_eq!(node.progress(ConsensusEvent::Timer, 99), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 100),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: None,
+            round: 0,
+        }]
+    );
+
+    // Any 2/3 of the prevotes, but neither for the proposal nor for nil.
+    for (signer, proposal) in [(0, Some(0)), (3, None)] {
+        let response = node.progress(
+            ConsensusEvent::Prevote {
+                proposal,
+                signer,
+                round: 0,
+            },
+            110,
+        );
+        assert_eq!(response, vec![]);
+    }
+    assert_eq!(node.progress(ConsensusEvent::Timer, 159), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 160),
+        vec![ConsensusResponse::BroadcastPrecommit {
+            proposal: None,
+            round: 0,
+        }]
+    );
+
+    // All of the precommits, but not 2/3 of them for nil.
+    for (signer, proposal) in [(0, Some(0)), (1, Some(0)), (3, None)] {
+        let response = node.progress(
+            ConsensusEvent::Precommit {
+                proposal,
+                signer,
+                round: 0,
+            },
+            170,
+        );
+        assert_eq!(response, vec![]);
+    }
+    assert_eq!(node.progress(ConsensusEvent::Timer, 199), vec![]);
+    // Moves to the round 1, where the propose timeout is doubled.
+    assert_eq!(node.progress(ConsensusEvent::Timer, 200), vec![]);
+    assert_eq!(node.progress(ConsensusEvent::Timer, 399), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 400),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: None,
+            round: 1,
+        }]
+    );
+}
+
+#[test]
+fn decide_timeout_1() {
+    let linear = TimeoutParams {
+        initial_ms: 100,
+        growth: TimeoutGrowth::Linear { increment_ms: 50 },
+        max_ms: 300,
+    };
+    let exponential = TimeoutParams {
+        initial_ms: 100,
+        growth: TimeoutGrowth::Exponential { factor: 2 },
+        max_ms: 1000,
+    };
+    assert_eq!(
+        (0..6)
+            .map(|round| decide_timeout(&linear, round))
+            .collect::<Vec<_>>(),
+        vec![100, 150, 200, 250, 300, 300]
+    );
+    assert_eq!(
+        (0..6)
+            .map(|round| decide_timeout(&exponential, round))
+            .collect::<Vec<_>>(),
+        vec![100, 200, 400, 800, 1000, 1000]
+    );
+    assert_eq!(decide_timeout(&exponential, usize::MAX), 1000);
+    assert_eq!(decide_timeout(&TimeoutParams::constant(100), 1000), 100);
+    linear.validate().unwrap();
+    exponential.validate().unwrap();
+
+    // The timeout never shrinks to zero.
+    let zero_factor = TimeoutParams {
+        growth: TimeoutGrowth::Exponential { factor: 0 },
+        ..exponential.clone()
+    };
+    zero_factor.validate().unwrap_err();
+    assert_eq!(decide_timeout(&zero_factor, 3), 100);
+    let inverted = TimeoutParams {
+        initial_ms: 2000,
+        ..exponential
+    };
+    inverted.validate().unwrap_err();
+    let mut params = height_info_with_validators(vec![1]).consensus_params;
+    params.validate().unwrap();
+    params.prevote_timeout = inverted;
+    params.validate().unwrap_err();
+}
+
+/// The consensus parameters of the former format, with a single timeout, are still readable.
+#[test]
+fn legacy_consensus_params_1() {
+    let params: ConsensusParams =
+        serde_json::from_str(r#"{"timeout_ms":100,"repeat_round_for_first_leader":3}"#).unwrap();
+    assert_eq!(
+        params,
+        height_info_with_validators(vec![1]).consensus_params
+    );
+    let serialized = serde_json::to_string(&params).unwrap();
+    assert_eq!(
+        serde_json::from_str::<ConsensusParams>(&serialized).unwrap(),
+        params
+    );
+}
+
+/// A state saved by the previous version, without the prevote timeouts, is restored.
+#[test]
+fn legacy_state_1() {
+    let mut node: Vetomint = serde_json::from_str(
+        r#"{"state":{"height_info":{"validators":[1,1,1,1],"this_node_index":1,"timestamp":0,"consensus_params":{"timeout_ms":100,"repeat_round_for_first_leader":1},"initial_block_candidate":0},"round":0,"step":"Propose","locked_value":null,"locked_round":null,"valid_value":null,"valid_round":null,"block_candidate":0,"proposals":{},"prevotes":[],"precommits":[],"propose_timeout_schedules":[[0,100]],"precommit_timeout_schedules":[],"for_the_first_time_1":[],"for_the_first_time_2":[],"finalized":null}}"#,
+    )
+    .unwrap();
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 1]);
+    height_info.this_node_index = Some(1);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    assert_eq!(node.get_height_info(), &height_info);
+
+    assert_eq!(node.progress(ConsensusEvent::Timer, 99), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 100),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: None,
+            round: 0,
+        }]
+    );
+    // The prevote timeout, which the previous version didn't have, works as well.
+    for (signer, proposal) in [(0, Some(0)), (2, None)] {
+        let response = node.progress(
+            ConsensusEvent::Prevote {
+                proposal,
+                signer,
+                round: 0,
+            },
+            110,
+        );
+        assert_eq!(response, vec![]);
+    }
+    assert_eq!(node.progress(ConsensusEvent::Timer, 209), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 210),
+        vec![ConsensusResponse::BroadcastPrecommit {
+            proposal: None,
+            round: 0,
+        }]
+    );
+}
+
+fn height_info_with_validators(validators: Vec<VotingPower>) -> HeightInfo {
+    HeightInfo {
+        validators,
+        this_node_index: Some(0),
+        timestamp: 0,
+        consensus_params: ConsensusParams {
+            propose_timeout: TimeoutParams::constant(100),
+            prevote_timeout: TimeoutParams::constant(100),
+            precommit_timeout: TimeoutParams::constant(100),
+            repeat_round_for_first_leader: 3,
+        },
+        initial_block_candidate: 0,
+    }
+}
+
+#[test]
+fn proposer_1() {
+    // Same as the plain round-robin for the same voting powers.
+    let height_info = height_info_with_validators(vec![1, 1, 1, 1]);
+    assert_eq!(
+        (0..10)
+            .map(|round| decide_proposer(round, &height_info))
+            .collect::<Vec<_>>(),
+        vec![0, 0, 0, 1, 2, 3, 0, 1, 2, 3]
+    );
+    let height_info = height_info_with_validators(vec![1, 3]);
+    assert_eq!(
+        (0..11)
+            .map(|round| decide_proposer(round, &height_info))
+            .collect::<Vec<_>>(),
+        vec![0, 0, 0, 0, 1, 1, 1, 0, 1, 1, 1]
+    );
+}
+
+/// The leadership frequency of each validator converges to its share of the voting power,
+/// for randomly generated validator sets.
+#[test]
+fn proposer_frequency_1() {
+    // A deterministic pseudo-random generator (xorshift64) to keep the test reproducible.
+    let mut seed: u64 = 0x5eed;
+    let mut random = move |bound: u64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % bound
+    };
+    for _ in 0..20 {
+        let validators: Vec<VotingPower> = (0..random(7) + 1).map(|_| random(100) + 1).collect();
+        let height_info = height_info_with_validators(validators.clone());
+        let repeat = height_info.consensus_params.repeat_round_for_first_leader;
+        let rounds = 500;
+        let mut counts = vec![0u64; validators.len()];
+        for round in repeat..repeat + rounds {
+            counts[decide_proposer(round, &height_info)] += 1;
+        }
+        let total_voting_power: u64 = validators.iter().sum();
+        for (count, voting_power) in counts.into_iter().zip(validators.iter()) {
+            // The difference from the expected count is less than the number of validators.
+            let expected = rounds as f64 * *voting_power as f64 / total_voting_power as f64;
+            assert!(
+                (count as f64 - expected).abs() < validators.len() as f64,
+                "{:?}: {} times for {}, expected {}",
+                validators,
+                count,
+                voting_power,
+                expected
+            );
+        }
+    }
+}
+
+/// A node behind the others skips to their round, and prevotes on the proposal received in advance.
+#[test]
+fn round_catch_up_1() {
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 1]);
+    height_info.this_node_index = Some(3);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    let mut node = Vetomint::new(height_info.clone());
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+    assert_eq!(decide_proposer(2, &height_info), 2);
+
+    let response = node.progress(
+        ConsensusEvent::BlockProposalReceived {
+            proposal: 0,
+            valid: true,
+            valid_round: None,
+            proposer: 2,
+            round: 2,
+            favor: true,
+        },
+        10,
+    );
+    assert_eq!(response, vec![]);
+    // Still from a single validator.
+    let response = node.progress(
+        ConsensusEvent::Prevote {
+            proposal: Some(0),
+            signer: 2,
+            round: 2,
+        },
+        10,
+    );
+    assert_eq!(response, vec![]);
+    let response = node.progress(
+        ConsensusEvent::Prevote {
+            proposal: Some(0),
+            signer: 1,
+            round: 2,
+        },
+        10,
+    );
+    assert_eq!(
+        response,
+        vec![
+            ConsensusResponse::BroadcastPrevote {
+                proposal: Some(0),
+                round: 2,
+            },
+            ConsensusResponse::BroadcastPrecommit {
+                proposal: Some(0),
+                round: 2,
+            }
+        ]
+    );
+}
+
+/// A node skips to a higher round by the voting power of the senders, not by their number.
+#[test]
+fn round_catch_up_2() {
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 3]);
+    height_info.this_node_index = Some(1);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    let mut node = Vetomint::new(height_info.clone());
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+    assert_eq!(decide_proposer(5, &height_info), 3);
+
+    for signer in [2, 3] {
+        let response = node.progress(
+            ConsensusEvent::Precommit {
+                proposal: None,
+                signer,
+                round: 5,
+            },
+            10,
+        );
+        assert_eq!(response, vec![]);
+    }
+    // The timeout of the round 0 is no longer effective.
+    assert_eq!(node.progress(ConsensusEvent::Timer, 100), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 110),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: None,
+            round: 5,
+        }]
+    );
+    // Messages from a lower round don't move the node back.
+    let response = node.progress(
+        ConsensusEvent::Prevote {
+            proposal: None,
+            signer: 3,
+            round: 1,
+        },
+        120,
+    );
+    assert_eq!(response, vec![]);
+}
+
+/// The round caught up to already has 2/3 of the precommits for nil, so the node moves on to the next one.
+#[test]
+fn round_catch_up_3() {
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 3]);
+    height_info.this_node_index = Some(2);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    let mut node = Vetomint::new(height_info.clone());
+    assert_eq!(node.progress(ConsensusEvent::Start, 0), vec![]);
+    assert_eq!(decide_proposer(2, &height_info), 1);
+    assert_eq!(decide_proposer(3, &height_info), 3);
+
+    for signer in [0, 1, 3] {
+        let response = node.progress(
+            ConsensusEvent::Precommit {
+                proposal: None,
+                signer,
+                round: 2,
+            },
+            10,
+        );
+        assert_eq!(response, vec![]);
+    }
+    assert_eq!(node.progress(ConsensusEvent::Timer, 100), vec![]);
+    assert_eq!(
+        node.progress(ConsensusEvent::Timer, 110),
+        vec![ConsensusResponse::BroadcastPrevote {
+            proposal: None,
+            round: 3,
+        }]
+    );
+}
+
+/// The vote tallies are rebuilt after deserialization, so the restored node counts the votes the same.
+#[test]
+fn serialization_1() {
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 1]);
+    height_info.this_node_index = Some(1);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    let mut node = Vetomint::new(height_info);
+    node.progress(ConsensusEvent::Start, 0);
+    node.progress(
+        ConsensusEvent::BlockProposalReceived {
+            proposal: 0,
+            valid: true,
+            valid_round: None,
+            proposer: 0,
+            round: 0,
+            favor: true,
+        },
+        1,
+    );
+    node.progress(
+        ConsensusEvent::Prevote {
+            proposal: Some(0),
+            signer: 0,
+            round: 0,
+        },
+        2,
+    );
+
+    let serialized = serde_json::to_string(&node).unwrap();
+    // The tallies are not a part of the format.
+    assert!(!serialized.contains("tally"));
+    let mut restored: Vetomint = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(restored, node);
+    let response = restored.progress(
+        ConsensusEvent::Prevote {
+            proposal: Some(0),
+            signer: 2,
+            round: 0,
+        },
+        3,
+    );
+    assert_eq!(
+        response,
+        vec![ConsensusResponse::BroadcastPrecommit {
+            proposal: Some(0),
+            round: 0,
+        }]
+    );
+}
+
+/// A state saved before the vote tallies were introduced is restored with the same tallies.
+#[test]
+fn serialization_2() {
+    let mut restored: Vetomint = serde_json::from_str(
+        r#"{"state":{"height_info":{"validators":[1,1,1,1],"this_node_index":1,"timestamp":0,"consensus_params":{"timeout_ms":100,"repeat_round_for_first_leader":1},"initial_block_candidate":0},"round":0,"step":"Prevote","locked_value":null,"locked_round":null,"valid_value":null,"valid_round":null,"block_candidate":0,"proposals":{"0":{"proposal":0,"valid":true,"valid_round":null,"round":0,"proposer":0,"favor":true}},"prevotes":[{"proposal":0,"signer":0,"round":0},{"proposal":0,"signer":1,"round":0}],"precommits":[],"propose_timeout_schedules":[[0,100]],"precommit_timeout_schedules":[],"for_the_first_time_1":[],"for_the_first_time_2":[],"finalized":null}}"#,
+    )
+    .unwrap();
+
+    // The same events that led to the saved state.
+    let mut height_info = height_info_with_validators(vec![1, 1, 1, 1]);
+    height_info.this_node_index = Some(1);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    let mut node = Vetomint::new(height_info);
+    node.progress(ConsensusEvent::Start, 0);
+    node.progress(
+        ConsensusEvent::BlockProposalReceived {
+            proposal: 0,
+            valid: true,
+            valid_round: None,
+            proposer: 0,
+            round: 0,
+            favor: true,
+        },
+        1,
+    );
+    node.progress(
+        ConsensusEvent::Prevote {
+            proposal: Some(0),
+            signer: 0,
+            round: 0,
+        },
+        2,
+    );
+
+    let events = [
+        ConsensusEvent::Prevote {
+            proposal: Some(0),
+            signer: 2,
+            round: 0,
+        },
+        ConsensusEvent::Precommit {
+            proposal: Some(0),
+            signer: 0,
+            round: 0,
+        },
+        ConsensusEvent::Precommit {
+            proposal: Some(0),
+            signer: 2,
+            round: 0,
+        },
+    ];
+    for event in events {
+        assert_eq!(restored.progress(event.clone(), 3), node.progress(event, 3));
+    }
+    assert_eq!(
+        restored.progress(ConsensusEvent::Timer, 4),
+        vec![ConsensusResponse::FinalizeBlock {
+            proposal: 0,
+            proof: vec![0, 1, 2],
+        }]
+    );
+}
+
+/// The proposer schedule kept in the state agrees with `decide_proposer()`, and survives serialization.
+#[test]
+fn proposer_schedule_1() {
+    let mut height_info = height_info_with_validators(vec![1, 2, 3, 4]);
+    height_info.consensus_params.repeat_round_for_first_leader = 1;
+    for round in [7, 3] {
+        let proposer = decide_proposer(round, &height_info);
+        height_info.this_node_index = Some(proposer);
+        let mut node = Vetomint::new(height_info.clone());
+        node.progress(ConsensusEvent::Start, 0);
+        // More than 1/3 of the voting power from the others.
+        let signers = if proposer == 3 { vec![1, 2] } else { vec![3] };
+        let mut responses = Vec::new();
+        for signer in signers {
+            responses.extend(node.progress(
+                ConsensusEvent::Precommit {
+                    proposal: None,
+                    signer,
+                    round,
+                },
+                10,
+            ));
+        }
+        assert!(responses.contains(&ConsensusResponse::BroadcastProposal {
+            proposal: 0,
+            valid_round: None,
+            round,
+        }));
+        let serialized = serde_json::to_string(&node).unwrap();
+        let restored: Vetomint = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(restored, node);
+    }
+}